@@ -0,0 +1,131 @@
+//! How fast the CPU runs Klaus Dormann's 6502 functional test
+//!
+//! The test isn't part of the repository: point `KLAUS_FUNCTIONAL_TEST` at a
+//! `6502_functional_test.bin` built to load at $0000 and start at $0400, from
+//! https://github.com/Klaus2m5/6502_65C02_functional_tests. Without it only
+//! the instruction tests of this crate are run.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use std::sync::Arc;
+
+use e6502::asm::assemble;
+use e6502::hardware::bus::Bus;
+use e6502::hardware::clock::Clock;
+use e6502::hardware::cpu::{StepOutcome, CPU};
+use e6502::hardware::memory::Memory;
+use e6502::types::{Addr, Byte};
+
+/// Instructions run per iteration of the functional test
+const INSTRUCTIONS: u64 = 100_000;
+
+/// A loop through most addressing modes, for when the functional test isn't
+/// there
+const MIX: &str = "
+        .org $0400
+start:  ldx #0
+        ldy #0
+loop:   lda table,x
+        clc
+        adc $10
+        sta $10
+        eor ($20),y
+        sta $0300,y
+        asl
+        rol $11
+        inc $12
+        cmp #$80
+        bcc @low
+        lsr
+@low:   pha
+        pla
+        jsr sub
+        iny
+        inx
+        bne loop
+        jmp loop
+sub:    bit $10
+        dec $13,x
+        rts
+table:  .res 256, $5A
+";
+
+/// A CPU on a free-running clock, as `e6502 run` builds it, starting at `pc`
+/// with `memory`
+///
+/// The reset vector is pointed at `pc`, so a plain reset starts there.
+fn machine(memory: &[(u16, u8)], pc: u16) -> CPU {
+    let mut bus = Bus::new();
+    bus.register(Memory::new(Addr(0x0000), Addr(0xFFFF)))
+        .unwrap();
+    for &(addr, byte) in memory {
+        bus.poke(Addr(addr), Byte(byte));
+    }
+    let [low, high] = pc.to_le_bytes();
+    bus.poke(Addr(0xFFFC), Byte(low));
+    bus.poke(Addr(0xFFFD), Byte(high));
+
+    let mut cpu = CPU::new(bus, Arc::new(Clock::free_running()));
+    cpu.reset();
+    cpu
+}
+
+/// Run at most `limit` instructions, returning how many ran before a trap
+fn run(cpu: &mut CPU, limit: u64) -> u64 {
+    for n in 0..limit {
+        if cpu.exec().unwrap() != StepOutcome::Continue {
+            return n + 1;
+        }
+    }
+    limit
+}
+
+fn functional_test(c: &mut Criterion) {
+    let Ok(path) = std::env::var("KLAUS_FUNCTIONAL_TEST") else {
+        eprintln!("KLAUS_FUNCTIONAL_TEST is not set, skipping the functional test");
+        return;
+    };
+    let image = std::fs::read(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+    let memory: Vec<(u16, u8)> = (0..=0xFFFF).zip(image).collect();
+
+    let mut group = c.benchmark_group("klaus");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.sample_size(20);
+    group.bench_function("functional_test", |b| {
+        b.iter_batched(
+            || machine(&memory, 0x0400),
+            |mut cpu| {
+                run(&mut cpu, INSTRUCTIONS);
+                cpu
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn mix(c: &mut Criterion) {
+    let memory: Vec<(u16, u8)> = assemble(MIX)
+        .unwrap()
+        .bytes()
+        .map(|(addr, byte)| (addr.0, byte.0))
+        .collect();
+
+    let mut group = c.benchmark_group("mix");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.sample_size(20);
+    group.bench_function("loop", |b| {
+        b.iter_batched(
+            || machine(&memory, 0x0400),
+            |mut cpu| {
+                assert_eq!(run(&mut cpu, INSTRUCTIONS), INSTRUCTIONS);
+                cpu
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, functional_test, mix);
+criterion_main!(benches);
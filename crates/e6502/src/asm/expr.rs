@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// Symbols, mnemonics, registers and directives, which start with `.`
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Punct(&'static str),
+}
+
+impl Token {
+    /// Whether this is the identifier `name`, ignoring case
+    pub fn is(&self, name: &str) -> bool {
+        matches!(self, Token::Ident(ident) if ident.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Longer punctuation first, so `<<` isn't taken for two `<`
+const PUNCTS: [&str; 18] = [
+    "<<", ">>", "#", ",", "(", ")", ":", "=", "+", "-", "*", "/", "&", "|", "^", "~", "<", ">",
+];
+
+/// Split a line into tokens, up to the comment
+pub fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        let rest = &line[i..];
+        let mut take = |n: usize| {
+            let token = &rest[..n];
+            for _ in token.chars() {
+                chars.next();
+            }
+            token
+        };
+
+        if c.is_whitespace() {
+            take(c.len_utf8());
+        } else if c == ';' {
+            break;
+        } else if c == '$' || c == '%' || c.is_ascii_digit() {
+            let (radix, skip) = match c {
+                '$' => (16, 1),
+                '%' => (2, 1),
+                _ => (10, 0),
+            };
+            let len = rest[skip..]
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len() - skip);
+            let digits = &take(skip + len)[skip..];
+            let value = i64::from_str_radix(digits, radix)
+                .map_err(|_| format!("Invalid number {c}{digits}"))?;
+            tokens.push(Token::Number(value));
+        } else if c == '\'' {
+            let mut quoted = rest[1..].chars();
+            match (quoted.next(), quoted.next()) {
+                (Some(ch), Some('\'')) if ch.is_ascii() => {
+                    take(3);
+                    tokens.push(Token::Number(ch as i64));
+                }
+                _ => return Err(format!("Invalid character {rest}")),
+            }
+        } else if c == '"' {
+            let Some(len) = rest[1..].find('"') else {
+                return Err("Unterminated string".to_string());
+            };
+            let text = &take(len + 2)[1..=len];
+            tokens.push(Token::Str(text.to_string()));
+        } else if c.is_ascii_alphabetic() || matches!(c, '_' | '.' | '@') {
+            let len = rest[1..]
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '@')
+                .map_or(rest.len(), |len| len + 1);
+            tokens.push(Token::Ident(take(len).to_string()));
+        } else {
+            let Some(&punct) = PUNCTS.iter().find(|p| rest.starts_with(**p)) else {
+                return Err(format!("Unexpected {c:?}"));
+            };
+            take(punct.len());
+            tokens.push(Token::Punct(punct));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Split `tokens` at the commas outside of parentheses
+pub fn split_args(tokens: &[Token]) -> Vec<&[Token]> {
+    if tokens.is_empty() {
+        return vec![];
+    }
+    let mut args = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") => depth -= 1,
+            Token::Punct(",") if depth == 0 => {
+                args.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    args.push(&tokens[start..]);
+    args
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    /// `*`, the address of the current statement
+    Pc,
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// Binary operators from the loosest to the tightest binding
+const LEVELS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/"],
+];
+
+impl Expr {
+    /// Parse all of `tokens`, naming local symbols (`@name`) with `scope`
+    pub fn parse(tokens: &[Token], scope: &str) -> Result<Self, String> {
+        if tokens.is_empty() {
+            return Err("Expected an expression".to_string());
+        }
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            scope,
+        };
+        let expr = parser.binary(0)?;
+        match tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected {token:?}")),
+        }
+    }
+
+    /// The value of the expression at `pc`
+    pub fn eval(&self, symbols: &HashMap<String, i64>, pc: u32) -> Result<i64, String> {
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Symbol(name) => *symbols
+                .get(name)
+                .ok_or(format!("Undefined symbol {name}"))?,
+            Expr::Pc => pc as i64,
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(symbols, pc)?;
+                match *op {
+                    "-" => value.wrapping_neg(),
+                    "~" => !value,
+                    "<" => value & 0xFF,
+                    ">" => (value >> 8) & 0xFF,
+                    _ => value,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(symbols, pc)?, rhs.eval(symbols, pc)?);
+                match *op {
+                    "|" => lhs | rhs,
+                    "^" => lhs ^ rhs,
+                    "&" => lhs & rhs,
+                    "<<" => lhs.wrapping_shl(rhs as u32),
+                    ">>" => lhs.wrapping_shr(rhs as u32),
+                    "+" => lhs.wrapping_add(rhs),
+                    "-" => lhs.wrapping_sub(rhs),
+                    "*" => lhs.wrapping_mul(rhs),
+                    _ if rhs == 0 => return Err("Division by zero".to_string()),
+                    _ => lhs / rhs,
+                }
+            }
+        })
+    }
+}
+
+/// The name `name` is known by, local names prefixed with their scope
+pub fn qualify(name: &str, scope: &str) -> String {
+    match name.starts_with('@') {
+        true => format!("{scope}{name}"),
+        false => name.to_string(),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    scope: &'a str,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(&Token::Punct(op)) = self.tokens.get(self.pos) {
+            if !LEVELS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next().cloned() {
+            Some(Token::Punct(op @ ("-" | "+" | "~" | "<" | ">"))) => {
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            Some(Token::Punct("(")) => {
+                let expr = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Punct(")")) => Ok(expr),
+                    _ => Err("Expected )".to_string()),
+                }
+            }
+            Some(Token::Punct("*")) => Ok(Expr::Pc),
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) if !name.starts_with('.') => {
+                Ok(Expr::Symbol(qualify(&name, self.scope)))
+            }
+            Some(token) => Err(format!("Unexpected {token:?}")),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
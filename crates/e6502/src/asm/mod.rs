@@ -0,0 +1,754 @@
+//! A two-pass 6502 assembler, enough to write test programs without cc65
+//!
+//! The syntax follows ca65 for the NMOS instruction set:
+//!
+//! ```text
+//! ; Count down from 3
+//! count = 3
+//!         .org $0400
+//! start:  ldx #count
+//! @loop:  dex
+//!         bne @loop
+//!         jmp *
+//! table:  .byte 1, 2, "abc"
+//!         .word start, table + 1
+//!         .res 4, $EA
+//! ```
+//!
+//! * labels end with `:`, and labels starting with `@` are local to the label
+//!   before them; elsewhere they are `label@local`
+//! * `name = expr` defines a constant
+//! * numbers are decimal, `$` hex, `%` binary or a character in `'`
+//! * expressions take `+ - * / & | ^ << >>`, parentheses and the unary
+//!   `- ~ < >`, the last two for the low and high byte; `*` is the address of
+//!   the current statement
+//! * an operand known to be in the zero page when it is reached picks the
+//!   zero-page addressing mode if the instruction has one, unless it's
+//!   written `a:addr`
+//! * `.macro name arg, ...` to `.endmacro` defines a macro, used like an
+//!   instruction; its local labels are new in every use
+//! * `.org`, `.byte`, `.word` (or `.addr`) and `.res count[, fill]`
+
+mod expr;
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::hardware::cpu::instructions::{AddressingMode, Instruction, InstructionArgument};
+use crate::types::{Addr, Byte};
+use expr::{qualify, split_args, tokenize, Expr, Token};
+
+/// How deep macros may use other macros
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+enum Index {
+    X,
+    Y,
+}
+
+#[derive(Debug)]
+enum Operand {
+    Implied,
+    Immediate(Expr),
+    /// An address, or a branch target, and whether it's forced to be
+    /// absolute with `a:`
+    Direct(Expr, Option<Index>, bool),
+    Indirect(Expr),
+    IndirectX(Expr),
+    IndirectY(Expr),
+}
+
+#[derive(Debug)]
+enum Data {
+    Expr(Expr),
+    Str(String),
+}
+
+#[derive(Debug)]
+enum Kind {
+    Empty,
+    Instruction(Instruction, Operand),
+    Byte(Vec<Data>),
+    Word(Vec<Expr>),
+    Org(Expr),
+    Res(Expr, Option<Expr>),
+    Assign(String, Expr),
+}
+
+#[derive(Debug)]
+struct Statement {
+    line: usize,
+    /// The line as written, for the listing
+    source: String,
+    /// Whether the line comes from a macro
+    expanded: bool,
+    label: Option<String>,
+    kind: Kind,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// An assembled program
+#[derive(Debug)]
+pub struct Program {
+    memory: BTreeMap<u16, u8>,
+    symbols: HashMap<String, i64>,
+    listing: String,
+}
+
+impl Program {
+    /// Every byte assembled, by address
+    pub fn bytes(&self) -> impl Iterator<Item = (Addr, Byte)> + '_ {
+        self.memory
+            .iter()
+            .map(|(&addr, &byte)| (Addr(addr), Byte(byte)))
+    }
+
+    /// The program as a binary from its lowest address to its highest, gaps
+    /// filled with zeros
+    pub fn image(&self) -> (Addr, Vec<u8>) {
+        let (Some((&start, _)), Some((&end, _))) =
+            (self.memory.first_key_value(), self.memory.last_key_value())
+        else {
+            return (Addr(0), vec![]);
+        };
+        let mut image = vec![0; (end - start) as usize + 1];
+        for (&addr, &byte) in &self.memory {
+            image[(addr - start) as usize] = byte;
+        }
+        (Addr(start), image)
+    }
+
+    /// The value of the label or constant `name`, if it is an address
+    pub fn symbol(&self, name: &str) -> Option<Addr> {
+        let value = *self.symbols.get(name)?;
+        u16::try_from(value).ok().map(Addr)
+    }
+
+    /// The source lines with their addresses and bytes
+    pub fn listing(&self) -> &str {
+        &self.listing
+    }
+}
+
+/// Assemble `source`, failing on the first error with its line
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let mut parser = Parser {
+        statements: vec![],
+        macros: HashMap::new(),
+        scope: String::new(),
+        expansions: 0,
+    };
+    parser.parse(source)?;
+    link(&parser.statements)
+}
+
+struct Parser {
+    statements: Vec<Statement>,
+    macros: HashMap<String, Macro>,
+    /// The last label not local, which local labels belong to
+    scope: String,
+    expansions: usize,
+}
+
+impl Parser {
+    fn parse(&mut self, source: &str) -> Result<(), String> {
+        let mut defining: Option<(usize, String, Macro)> = None;
+        for (n, text) in source.lines().enumerate() {
+            let line = n + 1;
+            let error = |e: String| format!("Line {line}: {e}");
+            let tokens = tokenize(text).map_err(error)?;
+
+            if let Some((_, _, ref mut def)) = defining {
+                if tokens
+                    .first()
+                    .is_some_and(|t| t.is(".endmacro") || t.is(".endmac"))
+                {
+                    let (_, name, def) = defining.take().unwrap();
+                    self.macros.insert(name, def);
+                } else {
+                    def.body.push(text.to_string());
+                }
+                self.listed(line, text);
+                continue;
+            }
+
+            if tokens.first().is_some_and(|t| t.is(".macro")) {
+                let Some(Token::Ident(name)) = tokens.get(1) else {
+                    return Err(error("Expected a macro name".to_string()));
+                };
+                let params = split_args(&tokens[2..])
+                    .into_iter()
+                    .map(|param| match param {
+                        [Token::Ident(param)] => Ok(param.clone()),
+                        _ => Err(error(format!("Invalid macro parameter {param:?}"))),
+                    })
+                    .collect::<Result<_, _>>()?;
+                let def = Macro {
+                    params,
+                    body: vec![],
+                };
+                defining = Some((line, name.clone(), def));
+                self.listed(line, text);
+                continue;
+            }
+
+            self.statement(line, text, tokens, 0).map_err(error)?;
+        }
+
+        match defining {
+            Some((line, name, _)) => Err(format!("Line {line}: .macro {name} has no .endmacro")),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep a line that assembles to nothing for the listing
+    fn listed(&mut self, line: usize, source: &str) {
+        self.statements.push(Statement {
+            line,
+            source: source.to_string(),
+            expanded: false,
+            label: None,
+            kind: Kind::Empty,
+        });
+    }
+
+    /// Parse one line, or expand the macro it uses
+    fn statement(
+        &mut self,
+        line: usize,
+        source: &str,
+        tokens: Vec<Token>,
+        depth: usize,
+    ) -> Result<(), String> {
+        let mut statement = Statement {
+            line,
+            source: source.to_string(),
+            expanded: depth > 0,
+            label: None,
+            kind: Kind::Empty,
+        };
+
+        let mut rest = &tokens[..];
+        if let [Token::Ident(name), Token::Punct(":"), tail @ ..] = rest {
+            if !name.starts_with('@') {
+                self.scope = name.clone();
+            }
+            statement.label = Some(qualify(name, &self.scope));
+            rest = tail;
+        }
+
+        if let [Token::Ident(name), args @ ..] = rest {
+            if self.macros.contains_key(name) {
+                self.statements.push(statement);
+                return self.expand(line, name, args, depth);
+            }
+        }
+
+        let scope = self.scope.clone();
+        let expr = |tokens: &[Token]| Expr::parse(tokens, &scope);
+        statement.kind = match rest {
+            [] => Kind::Empty,
+            [Token::Ident(name), Token::Punct("="), value @ ..] => {
+                Kind::Assign(qualify(name, &scope), expr(value)?)
+            }
+            [Token::Ident(directive), args @ ..] if directive.starts_with('.') => {
+                let args = split_args(args);
+                match directive.to_ascii_lowercase().as_str() {
+                    ".org" => match args[..] {
+                        [addr] => Kind::Org(expr(addr)?),
+                        _ => return Err(".org takes an address".to_string()),
+                    },
+                    ".byte" => Kind::Byte(
+                        args.into_iter()
+                            .map(|arg| match arg {
+                                [Token::Str(text)] => Ok(Data::Str(text.clone())),
+                                _ => expr(arg).map(Data::Expr),
+                            })
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    ".word" | ".addr" => {
+                        Kind::Word(args.into_iter().map(expr).collect::<Result<_, _>>()?)
+                    }
+                    ".res" => match args[..] {
+                        [count] => Kind::Res(expr(count)?, None),
+                        [count, fill] => Kind::Res(expr(count)?, Some(expr(fill)?)),
+                        _ => return Err(".res takes a count and a fill value".to_string()),
+                    },
+                    ".endmacro" | ".endmac" => return Err(".endmacro without .macro".to_string()),
+                    _ => return Err(format!("Unknown directive {directive}")),
+                }
+            }
+            [Token::Ident(mnemonic), args @ ..] => {
+                let ins = Instruction::from(mnemonic.to_ascii_uppercase().as_str());
+                if matches!(ins, Instruction::XXX(_)) {
+                    return Err(format!("Unknown instruction {mnemonic}"));
+                }
+                Kind::Instruction(ins, operand(args, &scope)?)
+            }
+            [token, ..] => return Err(format!("Unexpected {token:?}")),
+        };
+        self.statements.push(statement);
+        Ok(())
+    }
+
+    /// Parse the body of macro `name` with `args` for its parameters
+    fn expand(
+        &mut self,
+        line: usize,
+        name: &str,
+        args: &[Token],
+        depth: usize,
+    ) -> Result<(), String> {
+        if depth == MAX_DEPTH {
+            return Err(format!("Macros nested deeper than {MAX_DEPTH}"));
+        }
+        self.expansions += 1;
+        let expansion = self.expansions;
+        let def = &self.macros[name];
+        let args = split_args(args);
+        if args.len() != def.params.len() {
+            return Err(format!(
+                "{name} takes {} arguments, got {}",
+                def.params.len(),
+                args.len()
+            ));
+        }
+
+        let args: HashMap<&str, &[Token]> =
+            def.params.iter().map(String::as_str).zip(args).collect();
+        let mut lines = vec![];
+        for text in &def.body {
+            let mut tokens = vec![];
+            for token in tokenize(text)? {
+                match token {
+                    Token::Ident(ref ident) if args.contains_key(ident.as_str()) => {
+                        tokens.extend_from_slice(args[ident.as_str()])
+                    }
+                    // Every use gets its own local labels
+                    Token::Ident(ident) if ident.starts_with('@') => {
+                        tokens.push(Token::Ident(format!("{ident}#{expansion}")))
+                    }
+                    token => tokens.push(token),
+                }
+            }
+            lines.push((text.clone(), tokens));
+        }
+
+        for (text, tokens) in lines {
+            self.statement(line, &text, tokens, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the operand of an instruction
+fn operand(tokens: &[Token], scope: &str) -> Result<Operand, String> {
+    let expr = |tokens: &[Token]| Expr::parse(tokens, scope);
+    let (body, index) = match tokens {
+        [body @ .., Token::Punct(","), reg] if reg.is("x") => (body, Some(Index::X)),
+        [body @ .., Token::Punct(","), reg] if reg.is("y") => (body, Some(Index::Y)),
+        _ => (tokens, None),
+    };
+
+    Ok(match body {
+        [] if index.is_none() => Operand::Implied,
+        [reg] if index.is_none() && reg.is("a") => Operand::Implied,
+        [Token::Punct("#"), value @ ..] if index.is_none() => Operand::Immediate(expr(value)?),
+        [Token::Punct("("), inner @ .., Token::Punct(")")] if closes(inner) => {
+            match (inner, index) {
+                ([addr @ .., Token::Punct(","), reg], None) if reg.is("x") => {
+                    Operand::IndirectX(expr(addr)?)
+                }
+                (addr, None) => Operand::Indirect(expr(addr)?),
+                (addr, Some(Index::Y)) => Operand::IndirectY(expr(addr)?),
+                (_, Some(Index::X)) => return Err("Invalid indirect addressing".to_string()),
+            }
+        }
+        [prefix, Token::Punct(":"), addr @ ..] if prefix.is("a") => {
+            Operand::Direct(expr(addr)?, index, true)
+        }
+        _ => Operand::Direct(expr(body)?, index, false),
+    })
+}
+
+/// Whether the parentheses in `tokens` are balanced, so that an operand
+/// `(tokens)` is indirect rather than an expression like `(1)+(2)`
+fn closes(tokens: &[Token]) -> bool {
+    let mut depth = 0;
+    for token in tokens {
+        match token {
+            Token::Punct("(") => depth += 1,
+            Token::Punct(")") if depth == 0 => return false,
+            Token::Punct(")") => depth -= 1,
+            _ => (),
+        }
+    }
+    depth == 0
+}
+
+/// The addressing mode of `ins` with `operand`, whose value is `value` if
+/// known already
+fn mode(ins: Instruction, operand: &Operand, value: Option<i64>) -> Result<AddressingMode, String> {
+    let has = |mode| ins.valid_address_mode(mode);
+    let mode = match operand {
+        Operand::Implied => AddressingMode::Implied,
+        Operand::Immediate(_) => AddressingMode::Immediate,
+        Operand::Indirect(_) => AddressingMode::Indirect,
+        Operand::IndirectX(_) => AddressingMode::IndirectX,
+        Operand::IndirectY(_) => AddressingMode::IndirectY,
+        Operand::Direct(_, None, _) if has(AddressingMode::Relative) => AddressingMode::Relative,
+        Operand::Direct(_, index, forced) => {
+            let (zero_page, absolute) = match index {
+                None => (AddressingMode::ZeroPage, AddressingMode::Absolute),
+                Some(Index::X) => (AddressingMode::ZeroPageX, AddressingMode::AbsoluteX),
+                Some(Index::Y) => (AddressingMode::ZeroPageY, AddressingMode::AbsoluteY),
+            };
+            let small = !forced && value.is_some_and(|v| (0..=0xFF).contains(&v));
+            if has(zero_page) && (small || !has(absolute)) {
+                zero_page
+            } else {
+                absolute
+            }
+        }
+    };
+    match has(mode) {
+        true => Ok(mode),
+        false => Err(format!("{ins} has no {mode:?} addressing mode")),
+    }
+}
+
+/// `value` as a byte, which may be given signed
+fn byte(value: i64) -> Result<u8, String> {
+    match value {
+        -0x80..=0xFF => Ok(value as u8),
+        _ => Err(format!("{value} doesn't fit in a byte")),
+    }
+}
+
+/// `value` as a word, which may be given signed
+fn word(value: i64) -> Result<u16, String> {
+    match value {
+        -0x8000..=0xFFFF => Ok(value as u16),
+        _ => Err(format!("{value} doesn't fit in a word")),
+    }
+}
+
+/// Lay out the statements, then assemble them with every symbol known
+fn link(statements: &[Statement]) -> Result<Program, String> {
+    let mut symbols = HashMap::new();
+    let mut pending = vec![];
+    // The address of every statement and the addressing mode of instructions
+    let mut layout = vec![];
+    let mut pc: u32 = 0;
+
+    for stmt in statements {
+        let error = |e: String| format!("Line {}: {e}", stmt.line);
+        let define = |symbols: &mut HashMap<String, i64>, name: &String, value| match symbols
+            .insert(name.clone(), value)
+        {
+            Some(_) => Err(error(format!("{name} is defined twice"))),
+            None => Ok(()),
+        };
+        if let Some(ref label) = stmt.label {
+            define(&mut symbols, label, pc as i64)?;
+        }
+
+        let mut mode = None;
+        let size = match stmt.kind {
+            Kind::Empty => 0,
+            Kind::Assign(ref name, ref value) => {
+                match value.eval(&symbols, pc) {
+                    Ok(value) => define(&mut symbols, name, value)?,
+                    Err(_) => pending.push((stmt, name, value, pc)),
+                }
+                0
+            }
+            Kind::Org(ref addr) => {
+                let addr = addr.eval(&symbols, pc).map_err(error)?;
+                pc = word(addr).map_err(error)? as u32;
+                0
+            }
+            Kind::Instruction(ins, ref operand) => {
+                let value = match operand {
+                    Operand::Direct(value, ..) => value.eval(&symbols, pc).ok(),
+                    _ => None,
+                };
+                let m = self::mode(ins, operand, value).map_err(error)?;
+                mode = Some(m);
+                m.size() as u32
+            }
+            Kind::Byte(ref data) => data
+                .iter()
+                .map(|d| match d {
+                    Data::Str(text) => text.len() as u32,
+                    Data::Expr(_) => 1,
+                })
+                .sum(),
+            Kind::Word(ref words) => 2 * words.len() as u32,
+            Kind::Res(ref count, _) => {
+                let count = count.eval(&symbols, pc).map_err(error)?;
+                u32::try_from(count).map_err(|_| error(format!("Invalid count {count}")))?
+            }
+        };
+        layout.push((pc, mode));
+        pc += size;
+        if pc > 0x10000 {
+            return Err(error("Past the end of memory".to_string()));
+        }
+    }
+
+    // Constants defined by symbols further down
+    while !pending.is_empty() {
+        let before = pending.len();
+        let mut error = None;
+        pending.retain(|&(stmt, name, value, pc)| match value.eval(&symbols, pc) {
+            Ok(value) => {
+                symbols.insert(name.clone(), value);
+                false
+            }
+            Err(e) => {
+                error.get_or_insert(format!("Line {}: {e}", stmt.line));
+                true
+            }
+        });
+        if pending.len() == before {
+            return Err(error.unwrap());
+        }
+    }
+
+    let mut memory = BTreeMap::new();
+    let mut listing = String::new();
+    for (stmt, &(pc, mode)) in statements.iter().zip(&layout) {
+        let error = |e: String| format!("Line {}: {e}", stmt.line);
+        let eval = |expr: &Expr| expr.eval(&symbols, pc).map_err(error);
+        let bytes: Vec<u8> = match stmt.kind {
+            Kind::Empty | Kind::Assign(..) | Kind::Org(_) => vec![],
+            Kind::Instruction(ins, ref operand) => {
+                let mode = mode.unwrap();
+                let arg = match operand {
+                    Operand::Implied => InstructionArgument::Implied,
+                    Operand::Immediate(value) => {
+                        InstructionArgument::Immediate(Byte(byte(eval(value)?).map_err(error)?))
+                    }
+                    Operand::Direct(target, ..) if mode == AddressingMode::Relative => {
+                        let offset = eval(target)? - (pc as i64 + 2);
+                        if !(-0x80..0x80).contains(&offset) {
+                            return Err(error(format!("Branch out of range by {offset}")));
+                        }
+                        InstructionArgument::Offset(Byte(offset as u8))
+                    }
+                    Operand::Direct(addr, ..)
+                    | Operand::Indirect(addr)
+                    | Operand::IndirectX(addr)
+                    | Operand::IndirectY(addr) => {
+                        InstructionArgument::Address(Addr(word(eval(addr)?).map_err(error)?))
+                    }
+                };
+                let bytes = ins.encode(mode, arg).map_err(error)?;
+                bytes.into_iter().map(|b| b.0).collect()
+            }
+            Kind::Byte(ref data) => {
+                let mut bytes = vec![];
+                for d in data {
+                    match d {
+                        Data::Str(text) => bytes.extend(text.bytes()),
+                        Data::Expr(value) => bytes.push(byte(eval(value)?).map_err(error)?),
+                    }
+                }
+                bytes
+            }
+            Kind::Word(ref words) => {
+                let mut bytes = vec![];
+                for value in words {
+                    bytes.extend(word(eval(value)?).map_err(error)?.to_le_bytes());
+                }
+                bytes
+            }
+            Kind::Res(ref count, ref fill) => {
+                let fill = match fill {
+                    Some(fill) => byte(eval(fill)?).map_err(error)?,
+                    None => 0,
+                };
+                vec![fill; eval(count)? as usize]
+            }
+        };
+
+        for (i, &b) in bytes.iter().enumerate() {
+            let addr = (pc as usize + i) as u16;
+            if memory.insert(addr, b).is_some() {
+                return Err(error(format!("${addr:04X} is assembled twice")));
+            }
+        }
+        list(&mut listing, stmt, pc, &bytes);
+    }
+
+    Ok(Program {
+        memory,
+        symbols,
+        listing,
+    })
+}
+
+/// Add `stmt` at `pc` assembled to `bytes` to the listing, four bytes a row
+fn list(listing: &mut String, stmt: &Statement, pc: u32, bytes: &[u8]) {
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let shows_addr = !bytes.is_empty() || stmt.label.is_some();
+    let addr = match shows_addr {
+        true => format!("{pc:04X}"),
+        false => "    ".to_string(),
+    };
+    let marker = if stmt.expanded { '+' } else { ' ' };
+    // A long `.res` would only fill the listing with copies of its fill byte
+    let rows: Vec<_> = match stmt.kind {
+        Kind::Res(..) => bytes.chunks(4).take(1).collect(),
+        _ => bytes.chunks(4).collect(),
+    };
+
+    let first = rows.first().map_or(String::new(), |row| hex(row));
+    let line = format!(
+        "{:5}{marker} {addr}  {first:<11}  {}",
+        stmt.line, stmt.source
+    );
+    listing.push_str(line.trim_end());
+    listing.push('\n');
+    for (i, row) in rows.iter().enumerate().skip(1) {
+        let addr = pc as usize + 4 * i;
+        listing.push_str(&format!("       {addr:04X}  {}\n", hex(row)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().image().1
+    }
+
+    #[test]
+    fn test_assemble() {
+        let program = assemble(
+            "count = 3\n\
+             \x20       .org $0400\n\
+             start:  ldx #count ; X = 3\n\
+             @loop:  dex\n\
+             \x20       bne @loop\n\
+             \x20       jmp *\n\
+             table:  .byte 1, -1, \"ab\", <start, >start\n\
+             \x20       .word start, table + 1\n\
+             \x20       .res 2, $EA\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.image(),
+            (
+                Addr(0x400),
+                vec![
+                    0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x4C, 0x05, 0x04, 0x01, 0xFF, 0x61, 0x62, 0x00,
+                    0x04, 0x00, 0x04, 0x09, 0x04, 0xEA, 0xEA,
+                ]
+            )
+        );
+        assert_eq!(program.symbol("start"), Some(Addr(0x400)));
+        assert_eq!(program.symbol("start@loop"), Some(Addr(0x402)));
+        assert_eq!(program.symbol("count"), Some(Addr(3)));
+        assert_eq!(program.bytes().next(), Some((Addr(0x400), Byte(0xA2))));
+        assert!(program
+            .listing()
+            .contains("    3  0400  A2 03        start:  ldx #count ; X = 3"));
+        assert!(program.listing().contains("       040C  00 04\n"));
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let source = "
+            zp = $10
+            asl
+            asl a
+            lda #%101
+            lda zp
+            lda zp,x
+            ldx zp,y
+            lda abs
+            lda abs,X
+            lda abs,y
+            lda (zp,x)
+            lda (zp),y
+            jmp (abs)
+            lda (1+2)*3
+            lda abs + 1 - 1
+            stx zp,y
+            lda fwd
+            lda a:zp,x
+            fwd = $20
+            abs = $1234
+        ";
+        assert_eq!(
+            bytes(source),
+            vec![
+                0x0A, 0x0A, 0xA9, 0x05, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x34, 0x12, 0xBD,
+                0x34, 0x12, 0xB9, 0x34, 0x12, 0xA1, 0x10, 0xB1, 0x10, 0x6C, 0x34, 0x12, 0xA5, 0x09,
+                0xAD, 0x34, 0x12, 0x96, 0x10, 0xAD, 0x20, 0x00, 0xBD, 0x10, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let program = assemble(
+            ".macro wait n\n\
+             \x20 ldy #n\n\
+             @loop: dey\n\
+             \x20 bne @loop\n\
+             .endmacro\n\
+             main: wait 2\n\
+             \x20 wait 'A' + 1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.image().1,
+            vec![0xA0, 0x02, 0x88, 0xD0, 0xFD, 0xA0, 0x42, 0x88, 0xD0, 0xFD]
+        );
+        assert!(program
+            .listing()
+            .contains("    6+ 0002  88           @loop: dey"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(error("nop\nfoo"), "Line 2: Unknown instruction foo");
+        assert_eq!(error("lda #256"), "Line 1: 256 doesn't fit in a byte");
+        assert_eq!(error("jmp nowhere"), "Line 1: Undefined symbol nowhere");
+        assert_eq!(error("a: nop\na: nop"), "Line 2: a is defined twice");
+        assert_eq!(
+            error("sta #1"),
+            "Line 1: STA has no Immediate addressing mode"
+        );
+        assert_eq!(
+            error("ldx 1,x"),
+            "Line 1: LDX has no AbsoluteX addressing mode"
+        );
+        assert_eq!(
+            error(".org 1\nnop\n.org 1\nnop"),
+            "Line 4: $0001 is assembled twice"
+        );
+        assert_eq!(error(".macro m\nnop"), "Line 1: .macro m has no .endmacro");
+        assert_eq!(
+            error("m: .res 200\nbeq m"),
+            "Line 2: Branch out of range by -202"
+        );
+        assert_eq!(error("a = b\nb = a"), "Line 1: Undefined symbol b");
+        assert!(error(".byte \"x").contains("Unterminated string"));
+    }
+}
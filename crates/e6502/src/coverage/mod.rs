@@ -0,0 +1,293 @@
+//! Code coverage: which instructions ran and which way each branch went
+//!
+//! The coverage map is one byte per address, 64 KiB in all, made of the
+//! `EXECUTED`, `TAKEN` and `NOT_TAKEN` bits. With ca65 debug info the coverage
+//! is also written as an lcov `.info` tracefile, by source file and line.
+//!
+//! There is no telling code from data in the debug info, so a line counts as
+//! code when its first byte is a known op code.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::debugger::source::{SourceLine, Sources};
+use crate::disasm::{self, Disassembly};
+use crate::hardware::cpu::instructions::{AddressingMode, Instruction, OPCODES};
+use crate::hardware::cpu::{Register, StepOutcome, CPU};
+use crate::hardware::error::EmuError;
+use crate::trace::Trigger;
+use crate::types::{Addr, Byte};
+
+/// Bits of the coverage map
+pub const EXECUTED: u8 = 0x01;
+pub const TAKEN: u8 = 0x02;
+pub const NOT_TAKEN: u8 = 0x04;
+
+/// One bit per address
+struct Bitmap(Vec<u64>);
+
+impl Bitmap {
+    fn new() -> Self {
+        Self(vec![0; 0x10000 / 64])
+    }
+
+    fn set(&mut self, addr: Addr) {
+        self.0[addr.0 as usize / 64] |= 1 << (addr.0 % 64);
+    }
+
+    fn get(&self, addr: Addr) -> bool {
+        self.0[addr.0 as usize / 64] & (1 << (addr.0 % 64)) != 0
+    }
+}
+
+pub struct Coverage {
+    executed: Bitmap,
+    taken: Bitmap,
+    not_taken: Bitmap,
+    stop: Option<Trigger>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            executed: Bitmap::new(),
+            taken: Bitmap::new(),
+            not_taken: Bitmap::new(),
+            stop: None,
+        }
+    }
+
+    /// Stop recording once `trigger` is hit
+    pub fn stop(mut self, trigger: Trigger) -> Self {
+        self.stop = Some(trigger);
+        self
+    }
+
+    /// Execute the next instruction with `CPU::exec` and record it
+    ///
+    /// Returns `StepOutcome::Break` once the stop trigger is hit.
+    pub fn exec(&mut self, cpu: &mut CPU) -> Result<StepOutcome, EmuError> {
+        let pc = cpu.get_reg(Register::PC).unwrap_right();
+        let start = cpu.clock().ticks();
+        if self.stop.is_some_and(|t| t.hit(pc, start)) {
+            return Ok(StepOutcome::Break);
+        }
+
+        let op_code = cpu.peek(pc).0;
+        let step = cpu.exec();
+        if cpu.clock().ticks() > start {
+            let next = cpu.get_reg(Register::PC).unwrap_right();
+            self.record(pc, op_code, next);
+        }
+        step
+    }
+
+    /// Record the instruction `op_code` at `pc` that continued at `next`
+    fn record(&mut self, pc: Addr, op_code: u8, next: Addr) {
+        self.executed.set(pc);
+        if OPCODES[op_code as usize].mode == AddressingMode::Relative {
+            if next == pc + 2usize {
+                self.not_taken.set(pc);
+            } else {
+                self.taken.set(pc);
+            }
+        }
+    }
+
+    /// The bits of the coverage map for `addr`
+    pub fn at(&self, addr: Addr) -> u8 {
+        let mut bits = 0;
+        if self.executed.get(addr) {
+            bits |= EXECUTED;
+        }
+        if self.taken.get(addr) {
+            bits |= TAKEN;
+        }
+        if self.not_taken.get(addr) {
+            bits |= NOT_TAKEN;
+        }
+        bits
+    }
+
+    /// Write the coverage map, one byte per address
+    pub fn write_map(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let map: Vec<u8> = (0..=0xFFFF).map(|addr| self.at(Addr(addr))).collect();
+        out.write_all(&map)
+    }
+
+    /// Write an lcov tracefile for the lines in `sources`, decoding the code
+    /// through `read`
+    pub fn write_lcov(
+        &self,
+        out: &mut impl Write,
+        sources: &Sources,
+        read: impl Fn(Addr) -> Byte,
+    ) -> std::io::Result<()> {
+        let mut lines: BTreeMap<SourceLine, Vec<Addr>> = BTreeMap::new();
+        for (addr, line) in sources.lines() {
+            lines.entry(line).or_default().push(addr);
+        }
+
+        writeln!(out, "TN:")?;
+        for (idx, file) in sources.files().iter().enumerate() {
+            let first = SourceLine { file: idx, line: 0 };
+            let last = SourceLine {
+                file: idx + 1,
+                line: 0,
+            };
+
+            let mut records = vec![];
+            let (mut found, mut hit) = (0, 0);
+            let (mut branches, mut branches_hit) = (0, 0);
+            for (at, addrs) in lines.range(first..last) {
+                let code = instructions(addrs, &read);
+                if code.is_empty() {
+                    continue;
+                }
+
+                let executed = code.iter().any(|ins| self.executed.get(ins.addr));
+                records.push(format!("DA:{},{}", at.line, executed as u8));
+                found += 1;
+                hit += executed as usize;
+
+                let code = code
+                    .iter()
+                    .filter(|ins| ins.mode == AddressingMode::Relative);
+                for (block, ins) in code.enumerate() {
+                    for (branch, outcome) in [&self.taken, &self.not_taken].into_iter().enumerate()
+                    {
+                        let taken = if !self.executed.get(ins.addr) {
+                            "-".to_string()
+                        } else {
+                            (outcome.get(ins.addr) as u8).to_string()
+                        };
+                        records.push(format!("BRDA:{},{block},{branch},{taken}", at.line));
+                        branches += 1;
+                        branches_hit += (taken == "1") as usize;
+                    }
+                }
+            }
+            if found == 0 {
+                continue;
+            }
+
+            writeln!(out, "SF:{}", file.path.display())?;
+            for record in records {
+                writeln!(out, "{record}")?;
+            }
+            writeln!(out, "BRF:{branches}")?;
+            writeln!(out, "BRH:{branches_hit}")?;
+            writeln!(out, "LF:{found}")?;
+            writeln!(out, "LH:{hit}")?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
+
+/// The instructions assembled from the bytes at `addrs`, empty if they don't
+/// start with a known op code
+fn instructions(addrs: &[Addr], read: impl Fn(Addr) -> Byte) -> Vec<Disassembly> {
+    let mut code = vec![];
+    let mut addr = addrs[0];
+    while addrs.binary_search_by_key(&addr.0, |a| a.0).is_ok() {
+        let ins = disasm::disassemble(addr, &read);
+        if matches!(ins.instruction, None | Some(Instruction::XXX(_))) {
+            break;
+        }
+        addr = ins.next();
+        code.push(ins);
+        if addr.0 == 0 {
+            break;
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm6502;
+    use std::path::Path;
+
+    const DBGFILE: &str = "version\tmajor=2,minor=0
+file\tid=0,name=\"loop.s\",size=100,mtime=0x0,mod=0
+seg\tid=0,name=\"CODE\",start=0x000400,size=0x000C,addrsize=absolute,type=ro
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=1
+span\tid=2,seg=0,start=3,size=2
+span\tid=3,seg=0,start=5,size=3
+span\tid=4,seg=0,start=8,size=2
+span\tid=5,seg=0,start=10,size=2
+line\tid=0,file=0,line=1,span=0
+line\tid=1,file=0,line=2,span=1
+line\tid=2,file=0,line=3,span=2
+line\tid=3,file=0,line=4,span=3
+line\tid=4,file=0,line=5,span=4
+line\tid=5,file=0,line=6,span=5
+";
+
+    #[test]
+    fn test_coverage() {
+        let (mut cpu, clk) = asm6502!(
+            "
+start:  ldx #2
+@loop:  dex
+        bne @loop
+        jmp *
+        beq start       ; never reached
+        .byte $FF, $FF"
+        )
+        .prepare();
+        std::thread::spawn(move || loop {
+            clk.tick();
+            clk.wait_tock();
+        });
+
+        let mut coverage = Coverage::new();
+        while coverage.exec(&mut cpu) == Ok(StepOutcome::Continue) {}
+
+        assert_eq!(coverage.at(Addr(0x400)), EXECUTED);
+        assert_eq!(coverage.at(Addr(0x401)), 0);
+        assert_eq!(coverage.at(Addr(0x403)), EXECUTED | TAKEN | NOT_TAKEN);
+        assert_eq!(coverage.at(Addr(0x408)), 0);
+
+        let mut map = vec![];
+        coverage.write_map(&mut map).unwrap();
+        assert_eq!(map.len(), 0x10000);
+        assert_eq!(map[0x405], EXECUTED);
+
+        let mut sources = Sources::new();
+        sources.parse_dbgfile(DBGFILE, Path::new("/src")).unwrap();
+        let mut lcov = vec![];
+        coverage
+            .write_lcov(&mut lcov, &sources, |addr| cpu.peek(addr))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "TN:
+SF:/src/loop.s
+DA:1,1
+DA:2,1
+DA:3,1
+BRDA:3,0,0,1
+BRDA:3,0,1,1
+DA:4,1
+DA:5,0
+BRDA:5,0,0,-
+BRDA:5,0,1,-
+BRF:4
+BRH:2
+LF:5
+LH:4
+end_of_record
+"
+        );
+    }
+}
@@ -0,0 +1,822 @@
+//! Debug Adapter Protocol server, so editors like VS Code and Neovim can
+//! drive the debugger
+//!
+//! Messages are JSON bodies behind a `Content-Length` header, read from and
+//! written to any byte stream: stdio or a TCP connection.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::{json, Value};
+
+use super::expr::{Context, Expr};
+use super::source::SourceLine;
+use super::Debugger;
+use crate::disasm;
+use crate::hardware::cpu::{Flag, Register, CPU};
+use crate::hardware::display::Display;
+use crate::types::{Addr, Byte};
+
+/// There is only one CPU, so only one thread
+const THREAD_ID: u64 = 1;
+
+/// Instructions executed between checks for new requests while running
+const BATCH: usize = 1000;
+
+/// `variablesReference` of the scopes
+const REGISTERS: u64 = 1;
+const FLAGS: u64 = 2;
+const ZERO_PAGE: u64 = 3;
+const STACK: u64 = 4;
+
+/// Builds the machine for a `launch` request from the path of the ROM, with
+/// the display connected to `display`
+pub type Machine = Box<dyn Fn(&str, Display) -> Result<CPU, String>>;
+
+/// Read one message, `None` at the end of the stream
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>, String> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(val) = line.strip_prefix("Content-Length:") {
+            len = Some(val.trim().parse::<usize>().map_err(|e| e.to_string())?);
+        }
+    }
+
+    let Some(len) = len else {
+        return Err("Message without Content-Length".to_string());
+    };
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+pub fn write_message(writer: &mut impl Write, msg: &Value) -> std::io::Result<()> {
+    let body = msg.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    writer.flush()
+}
+
+/// How far a `continue` or a step runs
+#[derive(Debug, Clone, Copy)]
+enum Until {
+    /// A breakpoint, or a pause
+    Stopped,
+    /// The next instruction, or the next source line if known
+    StepIn(Option<SourceLine>),
+    /// The next instruction or source line not in a subroutine called from here
+    Next(Option<SourceLine>, usize),
+    /// The return from the current subroutine
+    StepOut(usize),
+}
+
+/// Serve one debugging session
+pub struct Dap<W: Write> {
+    machine: Machine,
+    debugger: Option<Debugger>,
+    writer: W,
+    seq: u64,
+    running: Option<Until>,
+    stop_on_entry: bool,
+    /// Breakpoints set per source file, by function name and by address
+    source_breakpoints: HashMap<String, Vec<Addr>>,
+    function_breakpoints: Vec<Addr>,
+    instruction_breakpoints: Vec<Addr>,
+    output: Receiver<u8>,
+    output_tx: Sender<u8>,
+}
+
+impl<W: Write> Dap<W> {
+    pub fn new(machine: Machine, writer: W) -> Self {
+        let (output_tx, output) = mpsc::channel();
+        Self {
+            machine,
+            debugger: None,
+            writer,
+            seq: 0,
+            running: None,
+            stop_on_entry: false,
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: vec![],
+            instruction_breakpoints: vec![],
+            output,
+            output_tx,
+        }
+    }
+
+    /// Handle requests read from `reader` until the client disconnects
+    pub fn serve(mut self, reader: impl BufRead + Send + 'static) -> Result<(), String> {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = reader;
+            while let Ok(Some(msg)) = read_message(&mut reader) {
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+
+        loop {
+            let msg = if self.running.is_some() {
+                self.run_batch()?;
+                match rx.try_recv() {
+                    Ok(msg) => msg,
+                    Err(TryRecvError::Empty) => continue,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match rx.recv() {
+                    Ok(msg) => msg,
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if !self.handle(msg)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send(&mut self, mut msg: Value) -> Result<(), String> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &msg).map_err(|e| e.to_string())
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Result<(), String> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    fn stopped(&mut self, reason: &str) -> Result<(), String> {
+        self.running = None;
+        self.event(
+            "stopped",
+            json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true}),
+        )
+    }
+
+    /// Send what the program printed since the last call
+    fn flush_output(&mut self) -> Result<(), String> {
+        let text: Vec<u8> = self.output.try_iter().collect();
+        if text.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&text).into_owned();
+        self.event("output", json!({"category": "stdout", "output": text}))
+    }
+
+    fn run_batch(&mut self) -> Result<(), String> {
+        let Some(until) = self.running else {
+            return Ok(());
+        };
+        let dbg = self.debugger.as_mut().unwrap();
+
+        let mut reason = None;
+        let mut error = None;
+        for _ in 0..BATCH {
+            if !dbg.step() {
+                if let Some(e) = dbg.error.take() {
+                    error = Some(e);
+                    reason = Some("exception");
+                    break;
+                }
+                reason = Some(if dbg.cpu.is_trapped() {
+                    "exception"
+                } else if dbg.watch_hit.take().is_some() {
+                    "data breakpoint"
+                } else {
+                    "breakpoint"
+                });
+                break;
+            }
+
+            let line = dbg.sources.line_at(dbg.pc());
+            let done = match until {
+                Until::Stopped => false,
+                Until::StepIn(None) => true,
+                Until::StepIn(start) => line.is_some() && line != start,
+                Until::Next(start, depth) => {
+                    dbg.frames.len() <= depth
+                        && (start.is_none() || (line.is_some() && line != start))
+                }
+                Until::StepOut(depth) => dbg.frames.len() < depth,
+            };
+            if done {
+                reason = Some("step");
+                break;
+            }
+        }
+
+        self.flush_output()?;
+        if let Some(e) = error {
+            self.event(
+                "output",
+                json!({"category": "stderr", "output": format!("{e}\n")}),
+            )?;
+        }
+        match reason {
+            Some(reason) => self.stopped(reason),
+            None => Ok(()),
+        }
+    }
+
+    /// Handle one message, returns false once the session is over
+    fn handle(&mut self, msg: Value) -> Result<bool, String> {
+        if msg["type"] != "request" {
+            return Ok(true);
+        }
+        let command = msg["command"].as_str().unwrap_or_default().to_string();
+        let args = &msg["arguments"];
+
+        let body = match self.request(&command, args) {
+            Ok(body) => body,
+            Err(e) => {
+                self.send(json!({
+                    "type": "response",
+                    "request_seq": msg["seq"],
+                    "command": command,
+                    "success": false,
+                    "message": e,
+                }))?;
+                return Ok(true);
+            }
+        };
+
+        self.send(json!({
+            "type": "response",
+            "request_seq": msg["seq"],
+            "command": command,
+            "success": true,
+            "body": body,
+        }))?;
+
+        match command.as_str() {
+            "launch" => self.event("initialized", json!({}))?,
+            "configurationDone" if self.stop_on_entry => self.stopped("entry")?,
+            "configurationDone" => self.running = Some(Until::Stopped),
+            "pause" if self.running.is_some() => self.stopped("pause")?,
+            "disconnect" | "terminate" => {
+                self.event("terminated", json!({}))?;
+                return Ok(false);
+            }
+            _ => (),
+        }
+        Ok(true)
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger, String> {
+        self.debugger
+            .as_mut()
+            .ok_or_else(|| "No program launched".to_string())
+    }
+
+    fn request(&mut self, command: &str, args: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsSteppingGranularity": true,
+                "supportsReadMemoryRequest": true,
+                "supportsWriteMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "configurationDone" | "disconnect" | "terminate" => Ok(json!({})),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setFunctionBreakpoints" | "setInstructionBreakpoints" => {
+                self.set_other_breakpoints(command, args)
+            }
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "6502"}]})),
+            "continue" => {
+                self.debugger()?;
+                self.running = Some(Until::Stopped);
+                Ok(json!({"allThreadsContinued": true}))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let instruction = args["granularity"] == "instruction";
+                let dbg = self.debugger()?;
+                let line = match instruction {
+                    true => None,
+                    false => dbg.sources.line_at(dbg.pc()),
+                };
+                let depth = dbg.frames.len();
+                self.running = Some(match command {
+                    "next" => Until::Next(line, depth),
+                    "stepIn" => Until::StepIn(line),
+                    _ => Until::StepOut(depth),
+                });
+                Ok(json!({}))
+            }
+            "pause" => {
+                self.debugger()?;
+                Ok(json!({}))
+            }
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({"scopes": [
+                {"name": "Registers", "variablesReference": REGISTERS, "expensive": false},
+                {"name": "Flags", "variablesReference": FLAGS, "expensive": false},
+                {"name": "Zero page", "variablesReference": ZERO_PAGE, "expensive": false},
+                {"name": "Stack", "variablesReference": STACK, "expensive": false},
+            ]})),
+            "variables" => self.variables(args["variablesReference"].as_u64().unwrap_or(0)),
+            "evaluate" => {
+                let dbg = self.debugger()?;
+                let v = Expr::parse(args["expression"].as_str().unwrap_or_default())?.eval(dbg)?;
+                Ok(json!({"result": format!("${v:X} ({v})"), "variablesReference": 0}))
+            }
+            "readMemory" => self.read_memory(args),
+            "writeMemory" => self.write_memory(args),
+            "disassemble" => self.disassemble(args),
+            _ => Err(format!("Unsupported request: {command}")),
+        }
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let Some(program) = args["program"].as_str() else {
+            return Err("launch needs a \"program\", the ROM to run".to_string());
+        };
+        let output = self.output_tx.clone();
+        let display = Display::with_output(move |c| {
+            let _ = output.send(c);
+        });
+        let cpu = (self.machine)(program, display)?;
+
+        let mut dbg = Debugger::new(cpu, true);
+        let mut loaded = vec![];
+        if let Some(dbgfile) = args["dbgfile"].as_str() {
+            let (n, m) = dbg.load_dbgfile(dbgfile)?;
+            loaded.push(format!("Loaded {n} symbols and {m} line spans\n"));
+        }
+        if let Some(labels) = args["labels"].as_str() {
+            let n = dbg.load_labels(labels)?;
+            loaded.push(format!("Loaded {n} symbols\n"));
+        }
+        self.debugger = Some(dbg);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        for text in loaded {
+            self.event("output", json!({"category": "console", "output": text}))?;
+        }
+        Ok(json!({}))
+    }
+
+    fn update_breakpoints(&mut self, old: &[Addr]) {
+        let keep: Vec<Addr> = self
+            .source_breakpoints
+            .values()
+            .flatten()
+            .chain(&self.function_breakpoints)
+            .chain(&self.instruction_breakpoints)
+            .copied()
+            .collect();
+        let dbg = self.debugger.as_mut().unwrap();
+        for addr in old {
+            dbg.cpu.remove_breakpoint(*addr);
+        }
+        for addr in keep {
+            dbg.cpu.breakpoint(addr);
+        }
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .or(args["source"]["name"].as_str())
+            .unwrap_or_default()
+            .to_string();
+        let dbg = self.debugger()?;
+
+        let mut addrs = vec![];
+        let mut result = vec![];
+        for bp in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = bp["line"].as_u64().unwrap_or(0) as usize;
+            match dbg.sources.addr_of(&path, line) {
+                Ok(addr) => {
+                    let actual = dbg.sources.line_at(addr).map_or(line, |at| at.line);
+                    addrs.push(addr);
+                    result.push(json!({
+                        "verified": true,
+                        "line": actual,
+                        "instructionReference": reference(addr),
+                    }));
+                }
+                Err(e) => result.push(json!({"verified": false, "line": line, "message": e})),
+            }
+        }
+
+        let old = self
+            .source_breakpoints
+            .insert(path, addrs)
+            .unwrap_or_default();
+        self.update_breakpoints(&old);
+        Ok(json!({"breakpoints": result}))
+    }
+
+    /// Function breakpoints name a symbol or an address expression, instruction
+    /// breakpoints a memory reference and offset
+    fn set_other_breakpoints(&mut self, command: &str, args: &Value) -> Result<Value, String> {
+        let dbg = self.debugger()?;
+
+        let mut addrs = vec![];
+        let mut result = vec![];
+        for bp in args["breakpoints"].as_array().into_iter().flatten() {
+            let addr = if command == "setFunctionBreakpoints" {
+                Expr::parse(bp["name"].as_str().unwrap_or_default())
+                    .and_then(|e| e.eval_addr(&*dbg))
+            } else {
+                parse_reference(&bp["instructionReference"], &bp["offset"])
+            };
+            match addr {
+                Ok(addr) => {
+                    addrs.push(addr);
+                    result.push(json!({"verified": true, "instructionReference": reference(addr)}));
+                }
+                Err(e) => result.push(json!({"verified": false, "message": e})),
+            }
+        }
+
+        // The client always sends the full set of one kind
+        let kind = match command {
+            "setFunctionBreakpoints" => &mut self.function_breakpoints,
+            _ => &mut self.instruction_breakpoints,
+        };
+        let old = std::mem::replace(kind, addrs);
+        self.update_breakpoints(&old);
+        Ok(json!({"breakpoints": result}))
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let dbg = self.debugger()?;
+
+        let mut pcs = vec![dbg.pc()];
+        pcs.extend(dbg.frames.iter().rev().map(|f| f.call_site));
+
+        let frames: Vec<Value> = pcs
+            .iter()
+            .enumerate()
+            .map(|(id, &pc)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": dbg.location(pc),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": reference(pc),
+                });
+                if let Some(at) = dbg.sources.line_at(pc) {
+                    let file = dbg.sources.file(at.file);
+                    let path = std::fs::canonicalize(&file.path).unwrap_or(file.path.clone());
+                    frame["line"] = json!(at.line);
+                    frame["column"] = json!(1);
+                    frame["source"] = json!({
+                        "name": Path::new(&file.name).file_name().map(|n| n.to_string_lossy()),
+                        "path": path,
+                    });
+                }
+                frame
+            })
+            .collect();
+
+        Ok(json!({"stackFrames": frames, "totalFrames": pcs.len()}))
+    }
+
+    fn variables(&mut self, reference: u64) -> Result<Value, String> {
+        let dbg = self.debugger()?;
+        let reg = |reg| dbg.reg(reg);
+        let byte = |name: String, v: u8| json!({"name": name, "value": format!("${v:02X}"), "variablesReference": 0});
+
+        let vars: Vec<Value> = match reference {
+            REGISTERS => vec![
+                byte("A".into(), reg(Register::A) as u8),
+                byte("X".into(), reg(Register::X) as u8),
+                byte("Y".into(), reg(Register::Y) as u8),
+                byte("PS".into(), reg(Register::PS) as u8),
+                byte("SP".into(), reg(Register::SP) as u8),
+                json!({"name": "PC", "value": dbg.location(dbg.pc()), "variablesReference": 0,
+                       "memoryReference": reference_of(dbg.pc())}),
+                json!({"name": "Cycle", "value": dbg.cycle().to_string(), "variablesReference": 0}),
+            ],
+            FLAGS => [
+                ("N", Flag::Negative),
+                ("V", Flag::Overflow),
+                ("B", Flag::Break),
+                ("D", Flag::DecimalMode),
+                ("I", Flag::InterruptDisable),
+                ("Z", Flag::Zero),
+                ("C", Flag::Carry),
+            ]
+            .into_iter()
+            .map(|(name, flag)| {
+                let set = dbg.cpu.is_set(flag);
+                json!({"name": name, "value": (set as u8).to_string(), "variablesReference": 0})
+            })
+            .collect(),
+            ZERO_PAGE => (0..16u16)
+                .map(|row| {
+                    let addr = Addr(row * 16);
+                    let bytes = dbg.read_mem(addr, 16).unwrap_or_default();
+                    let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+                    json!({"name": format!("${:02X}", addr.0), "value": hex.join(" "),
+                           "variablesReference": 0, "memoryReference": reference_of(addr)})
+                })
+                .collect(),
+            STACK => {
+                let sp = reg(Register::SP) as u8;
+                (sp.saturating_add(1)..=0xFF)
+                    .filter(|_| sp != 0xFF)
+                    .map(|low| {
+                        let addr = Addr(0x100 | low as u16);
+                        let v = dbg.cpu.peek(addr).0;
+                        json!({"name": format!("${:04X}", addr.0), "value": format!("${v:02X}"),
+                               "variablesReference": 0, "memoryReference": reference_of(addr)})
+                    })
+                    .collect()
+            }
+            _ => return Err(format!("Unknown variablesReference {reference}")),
+        };
+
+        Ok(json!({"variables": vars}))
+    }
+
+    fn read_memory(&mut self, args: &Value) -> Result<Value, String> {
+        let start = parse_reference(&args["memoryReference"], &args["offset"])?;
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+        let count = count.min(0x10000 - start.0 as usize);
+        let dbg = self.debugger()?;
+
+        let mut data = vec![];
+        for i in 0..count {
+            match Context::peek(&*dbg, start + i) {
+                Ok(byte) => data.push(byte.0),
+                Err(_) => break,
+            }
+        }
+
+        Ok(json!({
+            "address": reference(start),
+            "data": BASE64.encode(&data),
+            "unreadableBytes": count - data.len(),
+        }))
+    }
+
+    fn write_memory(&mut self, args: &Value) -> Result<Value, String> {
+        let start = parse_reference(&args["memoryReference"], &args["offset"])?;
+        let data = BASE64
+            .decode(args["data"].as_str().unwrap_or_default())
+            .map_err(|e| e.to_string())?;
+        self.debugger()?.write_mem(start, &data)?;
+        Ok(json!({"bytesWritten": data.len()}))
+    }
+
+    fn disassemble(&mut self, args: &Value) -> Result<Value, String> {
+        let addr = parse_reference(&args["memoryReference"], &args["offset"])?;
+        let skip = args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_u64().unwrap_or(0) as usize;
+        let dbg = self.debugger()?;
+        let read = |addr: Addr| Context::peek(&*dbg, addr).unwrap_or(Byte(0));
+
+        // Instructions before `addr` are found by decoding forward from far
+        // enough back, which lines up with the real instruction stream in most code
+        let mut start = addr;
+        if skip < 0 {
+            let back = skip.unsigned_abs() as usize;
+            let mut at = Addr(addr.0.saturating_sub((back * 3) as u16));
+            let mut before = vec![];
+            while at.0 < addr.0 {
+                before.push(at);
+                at = disasm::disassemble(at, read).next();
+                if at.0 < before[before.len() - 1].0 {
+                    break;
+                }
+            }
+            start = before
+                .len()
+                .checked_sub(back)
+                .map_or(Addr(0), |i| before[i]);
+        } else {
+            for _ in 0..skip {
+                start = disasm::disassemble(start, read).next();
+            }
+        }
+
+        let mut instructions = vec![];
+        let mut at = start;
+        for _ in 0..count {
+            let ins = disasm::disassemble(at, read);
+            let mut item = json!({
+                "address": reference(at),
+                "instructionBytes": ins.hex(),
+                "instruction": ins.format(|addr| dbg.symbols.resolve(addr)),
+            });
+            if let Some(label) = dbg.symbols.at(at) {
+                item["symbol"] = json!(label);
+            }
+            if let Some(line) = dbg.sources.line_at(at) {
+                let file = dbg.sources.file(line.file);
+                item["location"] = json!({"name": file.name, "path": file.path});
+                item["line"] = json!(line.line);
+            }
+            instructions.push(item);
+            at = ins.next();
+        }
+
+        Ok(json!({"instructions": instructions}))
+    }
+}
+
+/// The memory and instruction reference of `addr`
+fn reference(addr: Addr) -> String {
+    format!("{:#06X}", addr.0)
+}
+
+fn reference_of(addr: Addr) -> Value {
+    json!(reference(addr))
+}
+
+/// Parse a memory or instruction reference plus an optional byte offset
+fn parse_reference(reference: &Value, offset: &Value) -> Result<Addr, String> {
+    let Some(reference) = reference.as_str() else {
+        return Err("Missing memory reference".to_string());
+    };
+    let addr = Addr::try_from(reference)?;
+    let offset = offset.as_i64().unwrap_or(0);
+    u16::try_from(addr.0 as i64 + offset)
+        .map(Addr)
+        .map_err(|_| format!("{reference}{offset:+} is outside the address space"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm6502;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer the test keeps a handle on
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn machine() -> Machine {
+        Box::new(|_, _| {
+            let (cpu, _) = asm6502!(
+                "
+loop:   jsr count
+        jmp loop
+        .org $0410
+count:  inx
+        stx $10
+        rts
+        .org $FFFC
+        .word loop"
+            )
+            .prepare();
+            Ok(cpu)
+        })
+    }
+
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let mut input = vec![];
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            write_message(&mut input, &request).unwrap();
+        }
+
+        let out = Shared::default();
+        let dap = Dap::new(machine(), out.clone());
+        dap.serve(Cursor::new(input)).unwrap();
+
+        let out = out.0.lock().unwrap().clone();
+        let mut reader = Cursor::new(out);
+        std::iter::from_fn(|| read_message(&mut reader).unwrap()).collect()
+    }
+
+    fn response<'a>(messages: &'a [Value], command: &str) -> &'a Value {
+        messages
+            .iter()
+            .find(|m| m["type"] == "response" && m["command"] == command)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {"adapterID": "e6502"}}),
+            json!({"command": "launch", "arguments": {"program": "test.rom"}}),
+            json!({"command": "setInstructionBreakpoints",
+                   "arguments": {"breakpoints": [{"instructionReference": "0x0411"}]}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+            json!({"command": "readMemory", "arguments": {"memoryReference": "0x0410", "count": 4}}),
+            json!({"command": "disassemble", "arguments": {"memoryReference": "0x0403",
+                   "instructionOffset": -1, "instructionCount": 2}}),
+            json!({"command": "stepOut", "arguments": {"threadId": 1}}),
+            json!({"command": "variables", "arguments": {"variablesReference": REGISTERS}}),
+            json!({"command": "disconnect"}),
+        ]);
+
+        assert!(messages
+            .iter()
+            .filter(|m| m["type"] == "response")
+            .all(|m| m["success"] == true));
+
+        let stops: Vec<&Value> = messages
+            .iter()
+            .filter(|m| m["event"] == "stopped")
+            .map(|m| &m["body"]["reason"])
+            .collect();
+        assert_eq!(stops, [&json!("breakpoint"), &json!("step")]);
+
+        let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+        assert_eq!(frames[0]["instructionPointerReference"], "0x0411");
+        assert_eq!(frames[1]["instructionPointerReference"], "0x0400");
+
+        let data = &response(&messages, "readMemory")["body"]["data"];
+        assert_eq!(
+            BASE64.decode(data.as_str().unwrap()).unwrap(),
+            [0xE8, 0x86, 0x10, 0x60]
+        );
+
+        let instructions = &response(&messages, "disassemble")["body"]["instructions"];
+        assert_eq!(instructions[0]["instruction"], "JSR $0410");
+        assert_eq!(instructions[1]["instruction"], "JMP $0400");
+
+        let registers = &response(&messages, "variables")["body"]["variables"];
+        assert_eq!(registers[5]["value"], "0x0403");
+    }
+
+    #[test]
+    fn test_exception() {
+        let jam = BASE64.encode([0x02]);
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {"adapterID": "e6502"}}),
+            json!({"command": "launch", "arguments": {"program": "test.rom"}}),
+            json!({"command": "writeMemory", "arguments": {"memoryReference": "0x0410", "data": jam}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+            json!({"command": "disconnect"}),
+        ]);
+
+        let stops: Vec<&Value> = messages
+            .iter()
+            .filter(|m| m["event"] == "stopped")
+            .map(|m| &m["body"]["reason"])
+            .collect();
+        assert_eq!(stops, [&json!("exception")]);
+        let output = messages
+            .iter()
+            .find(|m| m["event"] == "output" && m["body"]["category"] == "stderr")
+            .unwrap();
+        assert!(output["body"]["output"]
+            .as_str()
+            .unwrap()
+            .starts_with("Jammed by op code 0x02 at 0x410"));
+    }
+
+    #[test]
+    fn test_breakpoint_kinds() {
+        let messages = session(&[
+            json!({"command": "initialize", "arguments": {"adapterID": "e6502"}}),
+            json!({"command": "launch", "arguments": {"program": "test.rom"}}),
+            json!({"command": "setInstructionBreakpoints",
+                   "arguments": {"breakpoints": [{"instructionReference": "0x0411"}]}}),
+            json!({"command": "setFunctionBreakpoints",
+                   "arguments": {"breakpoints": [{"name": "$0413"}]}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+            json!({"command": "continue", "arguments": {"threadId": 1}}),
+            json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+            json!({"command": "disconnect"}),
+        ]);
+
+        let pcs: Vec<&Value> = messages
+            .iter()
+            .filter(|m| m["type"] == "response" && m["command"] == "stackTrace")
+            .map(|m| &m["body"]["stackFrames"][0]["instructionPointerReference"])
+            .collect();
+        assert_eq!(pcs, [&json!("0x0411"), &json!("0x0413")]);
+    }
+}
@@ -0,0 +1,84 @@
+//! Reader for the debug info files written by `ld65 --dbgfile`
+//!
+//! Every line is a record: a type followed by a tab and a comma separated
+//! list of `key=value` pairs, e.g.
+//!
+//! ```text
+//! sym <TAB> id=3,name="RESET",addrsize=absolute,scope=0,def=5,val=0xFF00,seg=0,type=lab
+//! ```
+
+/// One line of a debug info file
+#[derive(Debug)]
+pub struct Record<'a> {
+    pub kind: &'a str,
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Record<'a> {
+    /// Raw value of `key`, with surrounding quotes removed
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.fields.iter().find(|(k, _)| *k == key).map(|(_, v)| {
+            v.strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(v)
+        })
+    }
+
+    /// Value of `key` as a number, either decimal or `0x` prefixed hex
+    pub fn int(&self, key: &str) -> Option<u32> {
+        let val = self.get(key)?;
+        match val.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => val.parse().ok(),
+        }
+    }
+}
+
+/// Split a debug info file into its records
+pub fn parse(text: &str) -> Result<Vec<Record<'_>>, String> {
+    let mut records = vec![];
+    for (n, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let Some((kind, rest)) = line.split_once('\t') else {
+            return Err(format!("line {}: malformed record", n + 1));
+        };
+
+        let mut fields = vec![];
+        for field in split_fields(rest) {
+            let Some((key, val)) = field.split_once('=') else {
+                return Err(format!("line {}: malformed field {field:?}", n + 1));
+            };
+            fields.push((key, val));
+        }
+
+        records.push(Record { kind, fields });
+    }
+
+    if records.first().is_none_or(|r| r.kind != "version") {
+        return Err("not an ld65 debug info file".to_string());
+    }
+
+    Ok(records)
+}
+
+/// Split on commas that are not inside a quoted string
+fn split_fields(line: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(&line[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    fields.push(&line[start..]);
+    fields
+}
@@ -0,0 +1,379 @@
+//! Expressions accepted wherever the debugger wants a value
+//!
+//! ```text
+//! $FF00  0xFF00  65280  %1010  0b1010  'A'   numbers and characters
+//! a x y ps sp pc                            registers
+//! RESET                                     symbols
+//! [$24]  w[$FFFC]                           byte and little endian word at an address
+//! + - * / % & | ^ << >>                     arithmetic, with the usual precedence
+//! -x  ~x  <x  >x                            negate, invert, low byte, high byte
+//! ```
+
+use crate::hardware::cpu::Register;
+use crate::types::{Addr, Byte};
+
+/// What an expression is evaluated against
+pub trait Context {
+    fn reg(&self, reg: Register) -> u16;
+    fn peek(&self, addr: Addr) -> Result<Byte, String>;
+    fn symbol(&self, name: &str) -> Option<Addr>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(i64),
+    Reg(Register),
+    Symbol(String),
+    Byte(Box<Expr>),
+    Word(Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            text,
+            chars: text.char_indices().collect(),
+            pos: 0,
+        };
+        let expr = parser.expr(0)?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(expr),
+            Some(c) => Err(format!("Unexpected {c:?} in {text:?}")),
+        }
+    }
+
+    pub fn eval(&self, ctx: &impl Context) -> Result<i64, String> {
+        Ok(match self {
+            Expr::Num(n) => *n,
+            Expr::Reg(reg) => ctx.reg(*reg) as i64,
+            Expr::Symbol(name) => match ctx.symbol(name) {
+                Some(addr) => addr.0 as i64,
+                None => return Err(format!("Unknown symbol: {name}")),
+            },
+            Expr::Byte(addr) => ctx.peek(addr.eval_addr(ctx)?)?.0 as i64,
+            Expr::Word(addr) => {
+                let addr = addr.eval_addr(ctx)?;
+                Addr::new(ctx.peek(addr + 1)?, ctx.peek(addr)?).0 as i64
+            }
+            Expr::Unary(op, e) => {
+                let v = e.eval(ctx)?;
+                match op {
+                    UnOp::Neg => v.wrapping_neg(),
+                    UnOp::Not => !v,
+                    UnOp::Low => v & 0xFF,
+                    UnOp::High => (v >> 8) & 0xFF,
+                }
+            }
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.eval(ctx)?, r.eval(ctx)?);
+                match op {
+                    BinOp::Add => l.wrapping_add(r),
+                    BinOp::Sub => l.wrapping_sub(r),
+                    BinOp::Mul => l.wrapping_mul(r),
+                    BinOp::Div | BinOp::Rem if r == 0 => return Err("Division by zero".into()),
+                    BinOp::Div => l / r,
+                    BinOp::Rem => l % r,
+                    BinOp::And => l & r,
+                    BinOp::Or => l | r,
+                    BinOp::Xor => l ^ r,
+                    BinOp::Shl => l.wrapping_shl(r as u32),
+                    BinOp::Shr => l.wrapping_shr(r as u32),
+                }
+            }
+        })
+    }
+
+    /// Evaluate to an address, which must fit in 16 bits
+    pub fn eval_addr(&self, ctx: &impl Context) -> Result<Addr, String> {
+        let v = self.eval(ctx)?;
+        u16::try_from(v)
+            .map(Addr)
+            .map_err(|_| format!("{v} is not an address"))
+    }
+
+    /// Evaluate to a byte, negative values down to -128 are taken as two's complement
+    pub fn eval_byte(&self, ctx: &impl Context) -> Result<Byte, String> {
+        let v = self.eval(ctx)?;
+        match v {
+            0..=255 => Ok(Byte(v as u8)),
+            -128..=-1 => Ok(Byte(v as i8 as u8)),
+            _ => Err(format!("{v} does not fit in a byte")),
+        }
+    }
+}
+
+/// An address range, `start..end` or `start..=end`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range {
+    start: Expr,
+    end: Expr,
+    inclusive: bool,
+}
+
+impl Range {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let Some((start, end)) = text.split_once("..") else {
+            return Err(format!(
+                "Expected <start>..<end> or <start>..=<end>, got {text:?}"
+            ));
+        };
+        let (end, inclusive) = match end.strip_prefix('=') {
+            Some(end) => (end, true),
+            None => (end, false),
+        };
+
+        Ok(Self {
+            start: Expr::parse(start)?,
+            end: Expr::parse(end)?,
+            inclusive,
+        })
+    }
+
+    /// The first address and the number of bytes in the range
+    pub fn eval(&self, ctx: &impl Context) -> Result<(Addr, usize), String> {
+        let start = self.start.eval_addr(ctx)?;
+        let end = self.end.eval_addr(ctx)?.0 as usize + self.inclusive as usize;
+        match end.checked_sub(start.0 as usize) {
+            Some(len) => Ok((start, len)),
+            None => Err("The range ends before it starts".to_string()),
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+}
+
+/// Binding power of the binary operators, loosest first
+const PRECEDENCE: &[&[(&str, BinOp)]] = &[
+    &[("|", BinOp::Or)],
+    &[("^", BinOp::Xor)],
+    &[("&", BinOp::And)],
+    &[("<<", BinOp::Shl), (">>", BinOp::Shr)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+];
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|(_, c)| *c)
+    }
+
+    fn rest(&self) -> &str {
+        match self.chars.get(self.pos) {
+            Some((i, _)) => &self.text[*i..],
+            None => "",
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.chars().count();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("Expected {token:?} in {:?}", self.text))
+        }
+    }
+
+    fn expr(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(ops) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+
+        let mut lhs = self.expr(level + 1)?;
+        'outer: loop {
+            for (token, op) in *ops {
+                if self.eat(token) {
+                    let rhs = self.expr(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for (token, op) in [
+            ("-", UnOp::Neg),
+            ("~", UnOp::Not),
+            ("<", UnOp::Low),
+            (">", UnOp::High),
+        ] {
+            if self.eat(token) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        if self.eat("(") {
+            let e = self.expr(0)?;
+            self.expect(")")?;
+            return Ok(e);
+        }
+        if self.eat("[") || self.eat("b[") {
+            let e = self.expr(0)?;
+            self.expect("]")?;
+            return Ok(Expr::Byte(Box::new(e)));
+        }
+        if self.eat("w[") {
+            let e = self.expr(0)?;
+            self.expect("]")?;
+            return Ok(Expr::Word(Box::new(e)));
+        }
+        if self.eat("'") {
+            let c = self.peek().ok_or("Unterminated character")?;
+            self.pos += 1;
+            self.expect("'")?;
+            return Ok(Expr::Num(c as i64));
+        }
+
+        // `$` and `%` only start a number, anywhere else `%` is an operator
+        let mut chars = self.rest().chars().peekable();
+        let prefix = chars.next_if(|c| matches!(c, '$' | '%'));
+        let word: String = prefix
+            .into_iter()
+            .chain(chars.take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '@' | '.')))
+            .collect();
+        if word.is_empty() {
+            return Err(match self.peek() {
+                Some(c) => format!("Unexpected {c:?} in {:?}", self.text),
+                None => format!("Unexpected end of {:?}", self.text),
+            });
+        }
+        self.pos += word.chars().count();
+
+        if let Some(n) = number(&word) {
+            return n.map(Expr::Num);
+        }
+        if let Ok(reg) = Register::try_from(word.as_str()) {
+            return Ok(Expr::Reg(reg));
+        }
+        Ok(Expr::Symbol(word))
+    }
+}
+
+/// Parse `word` if it looks like a number: `None` if it does not, an error if it
+/// looks like one but is malformed
+fn number(word: &str) -> Option<Result<i64, String>> {
+    let (digits, radix) = if let Some(hex) = word.strip_prefix('$').or(word.strip_prefix("0x")) {
+        (hex, 16)
+    } else if let Some(bin) = word.strip_prefix('%').or(word.strip_prefix("0b")) {
+        (bin, 2)
+    } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+        (word, 10)
+    } else {
+        return None;
+    };
+
+    Some(i64::from_str_radix(digits, radix).map_err(|e| format!("Invalid number {word:?}: {e}")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Machine;
+
+    impl Context for Machine {
+        fn reg(&self, reg: Register) -> u16 {
+            match reg {
+                Register::X => 0x02,
+                Register::PC => 0xFF00,
+                _ => 0,
+            }
+        }
+        fn peek(&self, addr: Addr) -> Result<Byte, String> {
+            Ok(Byte(addr.0 as u8 ^ 0x5A))
+        }
+        fn symbol(&self, name: &str) -> Option<Addr> {
+            (name == "XAML").then_some(Addr(0x24))
+        }
+    }
+
+    fn eval(text: &str) -> Result<i64, String> {
+        Expr::parse(text)?.eval(&Machine)
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("$10 + 0x10 + 16 + %10000"), Ok(0x40));
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("1 << 4 | 1"), Ok(0x11));
+        assert_eq!(eval("pc - 1"), Ok(0xFEFF));
+        assert_eq!(eval("<pc + >pc"), Ok(0xFF));
+        assert_eq!(eval("XAML+x"), Ok(0x26));
+        assert_eq!(eval("[XAML]"), Ok(0x24 ^ 0x5A));
+        assert_eq!(eval("w[$FFFC]"), Ok(0xA7A6));
+        assert_eq!(eval("'A'"), Ok(0x41));
+        assert_eq!(eval("10%3"), Ok(1));
+        assert_eq!(eval("x%2"), Ok(0));
+        assert_eq!(eval("%11%%10"), Ok(1));
+        assert!(eval("NOPE").is_err());
+        assert!(eval("1 +").is_err());
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("$FG").is_err());
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert!(Expr::parse("$10000").unwrap().eval_addr(&Machine).is_err());
+        assert_eq!(
+            Expr::parse("-1").unwrap().eval_byte(&Machine),
+            Ok(Byte(0xFF))
+        );
+        assert!(Expr::parse("256").unwrap().eval_byte(&Machine).is_err());
+
+        let range = |text| Range::parse(text).unwrap().eval(&Machine);
+        assert_eq!(range("$10..$20"), Ok((Addr(0x10), 0x10)));
+        assert_eq!(range("XAML..=XAML+1"), Ok((Addr(0x24), 2)));
+        assert_eq!(range("0..=$FFFF"), Ok((Addr(0), 0x10000)));
+        assert!(range("2..1").is_err());
+        assert!(Range::parse("$10").is_err());
+    }
+}
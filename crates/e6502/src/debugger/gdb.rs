@@ -0,0 +1,654 @@
+//! GDB remote serial protocol stub, so GDB and other tools speaking RSP can
+//! drive the CPU
+//!
+//! Packets are `$<data>#<checksum>`, acknowledged with `+` until the client
+//! asks for no-ack mode. A lone `0x03` byte interrupts a running CPU.
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, TryRecvError};
+
+use either::Either;
+
+use super::expr::Context;
+use super::Debugger;
+use crate::hardware::cpu::Register;
+use crate::hardware::error::ErrorKind;
+use crate::types::{Addr, Byte};
+
+/// Instructions executed between checks for an interrupt while running
+const BATCH: usize = 1000;
+
+/// Registers in the order of the target description, as numbered by `p`/`P`
+const REGISTERS: [Register; 6] = [
+    Register::A,
+    Register::X,
+    Register::Y,
+    Register::PS,
+    Register::SP,
+    Register::PC,
+];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.e6502.cpu">
+    <flags id="status" size="1">
+      <field name="C" start="0" end="0"/>
+      <field name="Z" start="1" end="1"/>
+      <field name="I" start="2" end="2"/>
+      <field name="D" start="3" end="3"/>
+      <field name="B" start="4" end="4"/>
+      <field name="V" start="6" end="6"/>
+      <field name="N" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="status"/>
+    <reg name="sp" bitsize="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Kinds of `Z` packets
+const SOFTWARE: u8 = 0;
+const HARDWARE: u8 = 1;
+const WRITE: u8 = 2;
+const READ: u8 = 3;
+const ACCESS: u8 = 4;
+
+enum Input {
+    Packet(String),
+    /// A packet with a bad checksum, to be sent again
+    Corrupt,
+    Interrupt,
+}
+
+/// Read the next packet or interrupt, skipping acknowledgements; `None` at
+/// the end of the stream
+fn read_input(reader: &mut impl BufRead) -> Result<Option<Input>, String> {
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            b'$' => break,
+            0x03 => return Ok(Some(Input::Interrupt)),
+            _ => continue,
+        }
+    }
+
+    let mut data = vec![];
+    if reader
+        .read_until(b'#', &mut data)
+        .map_err(|e| e.to_string())?
+        == 0
+        || data.pop() != Some(b'#')
+    {
+        return Ok(None);
+    }
+    let mut checksum = [0; 2];
+    reader
+        .read_exact(&mut checksum)
+        .map_err(|e| e.to_string())?;
+
+    let expected = std::str::from_utf8(&checksum)
+        .ok()
+        .and_then(|s| u8::from_str_radix(s, 16).ok());
+    if expected != Some(checksum_of(&data)) {
+        return Ok(Some(Input::Corrupt));
+    }
+    Ok(Some(Input::Packet(
+        String::from_utf8_lossy(&data).into_owned(),
+    )))
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Frame `data` as a packet, escaping the characters with special meaning
+pub fn packet(data: &str) -> Vec<u8> {
+    let mut body = vec![];
+    for b in data.bytes() {
+        if matches!(b, b'$' | b'#' | b'}' | b'*') {
+            body.extend([b'}', b ^ 0x20]);
+        } else {
+            body.push(b);
+        }
+    }
+    let mut out = vec![b'$'];
+    out.extend_from_slice(&body);
+    out.extend(format!("#{:02x}", checksum_of(&body)).bytes());
+    out
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 16).map_err(|_| format!("Invalid hex number: {s}"))
+}
+
+fn parse_addr(s: &str) -> Result<Addr, String> {
+    let addr = parse_hex(s)?;
+    u16::try_from(addr)
+        .map(Addr)
+        .map_err(|_| format!("Address out of range: {s}"))
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("Odd number of hex digits: {s}"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or(format!("Invalid hex data: {s}"))
+        })
+        .collect()
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Serve one GDB session over `debugger`
+pub struct GdbStub<W: Write> {
+    debugger: Debugger,
+    writer: W,
+    ack: bool,
+    running: bool,
+    /// Reply to `?`
+    stop: String,
+    /// Whether the client understands `swbreak`/`hwbreak` stop reasons
+    swbreak: bool,
+    hwbreak: bool,
+    /// Breakpoints and watchpoints by `Z` kind, several kinds may share an address
+    points: HashSet<(u8, Addr)>,
+}
+
+impl<W: Write> GdbStub<W> {
+    pub fn new(debugger: Debugger, writer: W) -> Self {
+        Self {
+            debugger,
+            writer,
+            ack: true,
+            running: false,
+            stop: "S05".to_string(),
+            swbreak: false,
+            hwbreak: false,
+            points: HashSet::new(),
+        }
+    }
+
+    /// Handle packets read from `reader` until the client detaches or kills
+    /// the session
+    pub fn serve(mut self, reader: impl Read + Send + 'static) -> Result<(), String> {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            while let Ok(Some(input)) = read_input(&mut reader) {
+                if tx.send(input).is_err() {
+                    break;
+                }
+            }
+        });
+
+        loop {
+            let input = if self.running {
+                if let Some(stop) = self.run_batch() {
+                    self.stopped(stop)?;
+                    continue;
+                }
+                match rx.try_recv() {
+                    Ok(input) => input,
+                    Err(TryRecvError::Empty) => continue,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match rx.recv() {
+                    Ok(input) => input,
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            match input {
+                Input::Packet(data) => {
+                    if self.ack {
+                        self.write(b"+")?;
+                    }
+                    if !self.handle(&data)? {
+                        return Ok(());
+                    }
+                }
+                Input::Corrupt => self.write(b"-")?,
+                // SIGINT
+                Input::Interrupt if self.running => self.stopped("T02".to_string())?,
+                Input::Interrupt => (),
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.writer
+            .write_all(data)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| e.to_string())
+    }
+
+    fn send(&mut self, data: &str) -> Result<(), String> {
+        self.write(&packet(data))
+    }
+
+    fn stopped(&mut self, stop: String) -> Result<(), String> {
+        self.running = false;
+        self.send(&stop)?;
+        self.stop = stop;
+        Ok(())
+    }
+
+    /// Run up to `BATCH` instructions, returns the stop reply if the CPU stopped
+    fn run_batch(&mut self) -> Option<String> {
+        for _ in 0..BATCH {
+            if !self.debugger.step() {
+                return Some(self.stop_reply(false));
+            }
+        }
+        None
+    }
+
+    /// The stop reply for where the CPU is now, after a step or a stop while
+    /// running
+    fn stop_reply(&mut self, stepped: bool) -> String {
+        let dbg = &mut self.debugger;
+        match dbg.error.take().map(|e| e.kind) {
+            Some(ErrorKind::IllegalOpcode(_) | ErrorKind::Jam(_)) => return "S04".to_string(),
+            // SIGBUS for the bus and the stack
+            Some(_) => return "S0a".to_string(),
+            None => (),
+        }
+        if dbg.cpu.is_trapped() {
+            return "S05".to_string();
+        }
+
+        if let Some(write) = dbg.watch_hit.take() {
+            let kind = if self.points.contains(&(ACCESS, write.addr)) {
+                "awatch"
+            } else {
+                "watch"
+            };
+            return format!("T05{kind}:{:x};", write.addr.0);
+        }
+
+        if let Some(addr) = dbg.cpu.read_hit() {
+            let kind = if self.points.contains(&(ACCESS, addr)) {
+                "awatch"
+            } else {
+                "rwatch"
+            };
+            return format!("T05{kind}:{:x};", addr.0);
+        }
+
+        let pc = dbg.pc();
+        if !stepped {
+            if self.swbreak && self.points.contains(&(SOFTWARE, pc)) {
+                return "T05swbreak:;".to_string();
+            }
+            if self.hwbreak && self.points.contains(&(HARDWARE, pc)) {
+                return "T05hwbreak:;".to_string();
+            }
+        }
+        "T05".to_string()
+    }
+
+    /// Handle one packet, returns false once the session is over
+    fn handle(&mut self, data: &str) -> Result<bool, String> {
+        match data {
+            "k" => return Ok(false),
+            "D" => {
+                self.send("OK")?;
+                return Ok(false);
+            }
+            _ => (),
+        }
+
+        match self.request(data) {
+            Ok(Some(reply)) => self.send(&reply)?,
+            // Running, the stop reply comes later
+            Ok(None) => (),
+            Err(e) => {
+                eprintln!("gdb: {data}: {e}");
+                self.send("E01")?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn request(&mut self, data: &str) -> Result<Option<String>, String> {
+        let (cmd, args) = data.split_at(1);
+        let reply = match cmd {
+            "?" => self.stop.clone(),
+            "g" => self.read_registers(),
+            "G" => {
+                let bytes = decode_hex(args)?;
+                if bytes.len() != 7 {
+                    return Err("Expected 7 bytes of registers".to_string());
+                }
+                for (i, reg) in REGISTERS.into_iter().enumerate() {
+                    self.set_register(reg, &bytes[i..])?;
+                }
+                "OK".to_string()
+            }
+            "p" => {
+                let reg = register(args)?;
+                self.read_register(reg)
+            }
+            "P" => {
+                let (n, val) = args.split_once('=').ok_or("Expected n=value")?;
+                let reg = register(n)?;
+                self.set_register(reg, &decode_hex(val)?)?;
+                "OK".to_string()
+            }
+            "m" => {
+                let (addr, len) = args.split_once(',').ok_or("Expected addr,length")?;
+                let start = parse_addr(addr)?;
+                let len = (parse_hex(len)? as usize).min(0x10000 - start.0 as usize);
+                let mut data = vec![];
+                for i in 0..len {
+                    match Context::peek(&self.debugger, start + i) {
+                        Ok(byte) => data.push(byte.0),
+                        Err(_) => break,
+                    }
+                }
+                if data.is_empty() && len > 0 {
+                    return Err(format!("Nothing mapped at {:#06X}", start.0));
+                }
+                encode_hex(&data)
+            }
+            "M" => {
+                let (addr, data) = args.split_once(':').ok_or("Expected addr,length:data")?;
+                let (addr, len) = addr.split_once(',').ok_or("Expected addr,length")?;
+                let data = decode_hex(data)?;
+                if data.len() != parse_hex(len)? as usize {
+                    return Err("Length does not match the data".to_string());
+                }
+                self.debugger.write_mem(parse_addr(addr)?, &data)?;
+                "OK".to_string()
+            }
+            "Z" | "z" => self.breakpoint(cmd == "Z", args)?,
+            "c" => return self.resume(false, args),
+            "s" => return self.resume(true, args),
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "v" => match data {
+                "vCont?" => "vCont;c;C;s;S".to_string(),
+                _ if data.starts_with("vCont;") => {
+                    // Only one thread, the first action applies to it
+                    let action = data["vCont;".len()..].split([';', ':']).next();
+                    match action.and_then(|a| a.chars().next()) {
+                        Some('c' | 'C') => return self.resume(false, ""),
+                        Some('s' | 'S') => return self.resume(true, ""),
+                        _ => String::new(),
+                    }
+                }
+                _ => String::new(),
+            },
+            "q" | "Q" => self.query(data)?,
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&mut self, data: &str) -> Result<String, String> {
+        if let Some(features) = data.strip_prefix("qSupported") {
+            let features = features.trim_start_matches(':');
+            self.swbreak = features.split(';').any(|f| f == "swbreak+");
+            self.hwbreak = features.split(';').any(|f| f == "hwbreak+");
+            return Ok(
+                "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+"
+                    .to_string(),
+            );
+        }
+
+        if let Some(range) = data.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = range.split_once(',').ok_or("Expected offset,length")?;
+            let offset = (parse_hex(offset)? as usize).min(TARGET_XML.len());
+            let end = (offset + parse_hex(len)? as usize).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { "m" } else { "l" };
+            return Ok(format!("{more}{}", &TARGET_XML[offset..end]));
+        }
+
+        Ok(match data {
+            "QStartNoAckMode" => {
+                self.ack = false;
+                "OK"
+            }
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .to_string())
+    }
+
+    /// Continue or step, from `addr` if given
+    fn resume(&mut self, step: bool, addr: &str) -> Result<Option<String>, String> {
+        if !addr.is_empty() {
+            let addr = parse_addr(addr)?;
+            self.debugger.set_reg(Register::PC, Either::Right(addr));
+        }
+
+        if step {
+            self.debugger.step_over_breakpoint();
+            let stop = self.stop_reply(true);
+            self.stop = stop.clone();
+            return Ok(Some(stop));
+        }
+
+        self.running = true;
+        Ok(None)
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Result<String, String> {
+        let mut parts = args.splitn(3, ',');
+        let (Some(kind), Some(addr), Some(len)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err("Expected type,addr,kind".to_string());
+        };
+        let kind = match kind.parse::<u8>() {
+            Ok(kind) if kind <= ACCESS => kind,
+            _ => return Ok(String::new()),
+        };
+        let addr = parse_addr(addr)?;
+        // For breakpoints the length is the instruction size, which doesn't matter
+        let len = match kind {
+            SOFTWARE | HARDWARE => 1,
+            _ => parse_hex(len.split(';').next().unwrap_or_default())?,
+        };
+        if len > 0x10000 - addr.0 as u32 {
+            return Err("Past the end of the address space".to_string());
+        }
+
+        for i in 0..len as usize {
+            let addr = addr + i;
+            if insert {
+                self.points.insert((kind, addr));
+            } else {
+                self.points.remove(&(kind, addr));
+            }
+            self.sync_point(addr);
+        }
+        Ok("OK".to_string())
+    }
+
+    /// Set the CPU's breakpoints and watchpoints at `addr` from the `Z` points there
+    fn sync_point(&mut self, addr: Addr) {
+        let has = |kinds: &[u8]| kinds.iter().any(|&k| self.points.contains(&(k, addr)));
+        let (breakpoint, write, read) = (
+            has(&[SOFTWARE, HARDWARE]),
+            has(&[WRITE, ACCESS]),
+            has(&[READ, ACCESS]),
+        );
+
+        let cpu = &mut self.debugger.cpu;
+        if breakpoint {
+            cpu.breakpoint(addr);
+        } else {
+            cpu.remove_breakpoint(addr);
+        }
+        if write {
+            cpu.watchpoint(addr);
+        } else {
+            cpu.remove_watchpoint(addr);
+        }
+        if read {
+            cpu.read_watchpoint(addr);
+        } else {
+            cpu.remove_read_watchpoint(addr);
+        }
+    }
+
+    fn read_register(&self, reg: Register) -> String {
+        match self.debugger.cpu.get_reg(reg) {
+            Either::Left(val) => encode_hex(&[val.0]),
+            Either::Right(addr) => encode_hex(&addr.0.to_le_bytes()),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        REGISTERS
+            .into_iter()
+            .map(|reg| self.read_register(reg))
+            .collect()
+    }
+
+    /// Set `reg` from the start of `bytes`, little endian
+    fn set_register(&mut self, reg: Register, bytes: &[u8]) -> Result<(), String> {
+        let val = match (reg, bytes) {
+            (Register::PC, [low, high, ..]) => {
+                Either::Right(Addr(u16::from_le_bytes([*low, *high])))
+            }
+            (Register::PC, _) => return Err("Expected 2 bytes for pc".to_string()),
+            (_, [val, ..]) => Either::Left(Byte(*val)),
+            (_, []) => return Err("Expected a byte".to_string()),
+        };
+        self.debugger.set_reg(reg, val);
+        Ok(())
+    }
+}
+
+fn register(n: &str) -> Result<Register, String> {
+    REGISTERS
+        .get(parse_hex(n)? as usize)
+        .copied()
+        .ok_or(format!("No register {n}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm6502;
+    use std::net::{TcpListener, TcpStream};
+
+    /// Just enough of an RSP client to drive the stub
+    struct Client {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            self.stream.write_all(&packet(data)).unwrap();
+            let mut ack = [0];
+            self.reader.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+', "{data} not acknowledged");
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            match read_input(&mut self.reader).unwrap() {
+                Some(Input::Packet(reply)) => {
+                    self.stream.write_all(b"+").unwrap();
+                    reply
+                }
+                _ => panic!("Expected a reply"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let (cpu, _) = asm6502!(
+            "
+loop:   inx
+        stx $10
+        lda $20
+        jmp loop"
+        )
+        .prepare();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let stub = GdbStub::new(Debugger::new(cpu, false), stream.try_clone().unwrap());
+            stub.serve(stream)
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut client = Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        };
+
+        let supported = client.request("qSupported:swbreak+;hwbreak+");
+        assert!(supported.contains("qXfer:features:read+"));
+        let xml = client.request("qXfer:features:read:target.xml:0,fff");
+        assert!(xml.starts_with("l<?xml"));
+        assert!(xml.contains(r#"<reg name="pc" bitsize="16""#));
+
+        assert_eq!(client.request("?"), "S05");
+        // a, x, y, p, sp, pc
+        assert_eq!(client.request("g"), "00000024fd0004");
+        assert_eq!(client.request("P0=42"), "OK");
+        assert_eq!(client.request("p0"), "42");
+        assert_eq!(client.request("M10,2:aabb"), "OK");
+        assert_eq!(client.request("m10,2"), "aabb");
+
+        assert_eq!(client.request("Z0,403,1"), "OK");
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        assert_eq!(client.request("p5"), "0304");
+        assert_eq!(client.request("m10,1"), "01");
+        assert_eq!(client.request("z0,403,1"), "OK");
+
+        assert_eq!(client.request("s"), "T05");
+        assert_eq!(client.request("p5"), "0504");
+
+        assert_eq!(client.request("Z2,FFFF,2"), "E01");
+        assert_eq!(client.request("Z2,10,FFFFFFFF"), "E01");
+        assert_eq!(client.request("Z2,10,1"), "OK");
+        assert_eq!(client.request("vCont;c"), "T05watch:10;");
+        assert_eq!(client.request("p5"), "0304");
+        assert_eq!(client.request("z2,10,1"), "OK");
+
+        assert_eq!(client.request("Z3,20,1"), "OK");
+        assert_eq!(client.request("c"), "T05rwatch:20;");
+        assert_eq!(client.request("p5"), "0504");
+        assert_eq!(client.request("z3,20,1"), "OK");
+
+        // Runs forever until interrupted
+        client.stream.write_all(&packet("c")).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        client.stream.write_all(&[0x03]).unwrap();
+        let mut ack = [0];
+        client.reader.read_exact(&mut ack).unwrap();
+        assert_eq!(client.reply(), "T02");
+        assert_eq!(client.request("?"), "T02");
+
+        client.stream.write_all(&packet("k")).unwrap();
+        server.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+
+use super::Frame;
+use crate::hardware::cpu::CpuState;
+use crate::types::{Addr, Byte};
+
+/// Default memory budget for the recorded history
+pub const DEFAULT_BUDGET: usize = 64 << 20;
+
+/// Cycles between two snapshots
+const SNAPSHOT_INTERVAL: u64 = 10_000;
+
+/// The machine at an instruction boundary
+#[derive(Clone)]
+pub struct Snapshot {
+    pub cycle: u64,
+    pub cpu: CpuState,
+    pub devices: Vec<Option<Vec<u8>>>,
+    /// The last byte on the data bus
+    pub latch: Byte,
+    pub frames: Vec<Frame>,
+}
+
+impl Snapshot {
+    fn size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.devices.iter().flatten().map(Vec::len).sum::<usize>()
+            + self.frames.len() * std::mem::size_of::<Frame>()
+    }
+}
+
+/// A bus write made by the instruction at `pc`, which ended at `cycle`
+#[derive(Debug, Clone, Copy)]
+pub struct Write {
+    pub cycle: u64,
+    pub pc: Addr,
+    pub addr: Addr,
+    pub data: Byte,
+}
+
+/// Periodic snapshots and every bus write in between, the timeline for
+/// reverse execution
+///
+/// Once the history takes more than its memory budget the oldest snapshots,
+/// and the writes before them, are dropped.
+pub struct History {
+    budget: usize,
+    size: usize,
+    snapshots: VecDeque<Snapshot>,
+    writes: VecDeque<Write>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUDGET)
+    }
+}
+
+impl History {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            size: 0,
+            snapshots: VecDeque::new(),
+            writes: VecDeque::new(),
+        }
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.trim();
+    }
+
+    /// Whether a snapshot is due at `cycle`
+    pub fn wants_snapshot(&self, cycle: u64) -> bool {
+        self.snapshots
+            .back()
+            .is_none_or(|last| cycle >= last.cycle + SNAPSHOT_INTERVAL)
+    }
+
+    /// Record `snapshot`, replacing one taken at the same cycle
+    pub fn push_snapshot(&mut self, snapshot: Snapshot) {
+        if self
+            .snapshots
+            .back()
+            .is_some_and(|last| last.cycle == snapshot.cycle)
+        {
+            let last = self.snapshots.pop_back().unwrap();
+            self.size -= last.size();
+        }
+        self.size += snapshot.size();
+        self.snapshots.push_back(snapshot);
+        self.trim();
+    }
+
+    pub fn push_write(&mut self, write: Write) {
+        self.size += std::mem::size_of::<Write>();
+        self.writes.push_back(write);
+    }
+
+    /// The latest snapshot taken at or before `cycle`
+    pub fn snapshot_at(&self, cycle: u64) -> Option<&Snapshot> {
+        self.snapshots.iter().rev().find(|s| s.cycle <= cycle)
+    }
+
+    /// Cycles of all snapshots taken before `cycle`, latest first
+    pub fn snapshots_before(&self, cycle: u64) -> Vec<u64> {
+        self.snapshots
+            .iter()
+            .rev()
+            .map(|s| s.cycle)
+            .filter(|&c| c < cycle)
+            .collect()
+    }
+
+    /// The latest write that ended before `cycle` to an address matching `filter`
+    pub fn last_write(&self, cycle: u64, filter: impl Fn(Addr) -> bool) -> Option<&Write> {
+        self.writes
+            .iter()
+            .rev()
+            .skip_while(|w| w.cycle >= cycle)
+            .find(|w| filter(w.addr))
+    }
+
+    /// Forget everything recorded after `cycle`
+    pub fn truncate(&mut self, cycle: u64) {
+        while self.snapshots.back().is_some_and(|s| s.cycle > cycle) {
+            let snapshot = self.snapshots.pop_back().unwrap();
+            self.size -= snapshot.size();
+        }
+        while self.writes.back().is_some_and(|w| w.cycle > cycle) {
+            self.writes.pop_back();
+            self.size -= std::mem::size_of::<Write>();
+        }
+    }
+
+    /// Forget everything recorded
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.writes.clear();
+        self.size = 0;
+    }
+
+    /// The earliest cycle that can be travelled back to
+    pub fn first_cycle(&self) -> Option<u64> {
+        self.snapshots.front().map(|s| s.cycle)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn snapshot_count(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    fn trim(&mut self) {
+        while self.size > self.budget && self.snapshots.len() > 1 {
+            let snapshot = self.snapshots.pop_front().unwrap();
+            self.size -= snapshot.size();
+
+            let first = self.snapshots.front().unwrap().cycle;
+            while self.writes.front().is_some_and(|w| w.cycle <= first) {
+                self.writes.pop_front();
+                self.size -= std::mem::size_of::<Write>();
+            }
+        }
+    }
+}
@@ -1,14 +1,57 @@
+pub mod dap;
+mod dbginfo;
+pub mod expr;
+pub mod gdb;
+pub mod history;
+mod repl;
+pub mod source;
+pub mod symbols;
+pub mod vice;
+
 use either::Either;
-use rustyline::{error::ReadlineError, DefaultEditor};
+use std::path::Path;
 use std::sync::atomic::AtomicBool;
 
+use crate::disasm;
+use crate::savestate::SaveState;
+use crate::trace::Tracer;
 use crate::types::*;
 
-use crate::hardware::cpu::{Register, CPU};
+use crate::hardware::cpu::{Register, StepOutcome, CPU};
+use crate::hardware::error::EmuError;
+use expr::{Expr, Range};
+use history::{History, Snapshot, Write};
+use source::Sources;
+use symbols::Symbols;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+
+/// Lines of source shown on either side of the current line
+const SOURCE_CONTEXT: usize = 2;
+
+/// Give up `step source` after this many instructions without reaching a new line
+const MAX_SOURCE_STEPS: usize = 1_000_000;
+
+/// A subroutine call made with `JSR`
+#[derive(Clone)]
+pub struct Frame {
+    call_site: Addr,
+    target: Addr,
+}
 
 pub struct Debugger {
     cpu: CPU,
     cmd: Option<Command>,
+    symbols: Symbols,
+    sources: Sources,
+    frames: Vec<Frame>,
+    history: History,
+    /// Last write that hit a watchpoint, shown when execution stops
+    watch_hit: Option<Write>,
+    /// Error the CPU stopped with, shown when execution stops
+    error: Option<EmuError>,
+    tracer: Option<Tracer>,
 }
 
 pub static RUNNING: AtomicBool = const { AtomicBool::new(false) };
@@ -19,37 +62,491 @@ impl Debugger {
         if reset {
             cpu.reset();
         }
-        Self { cpu, cmd: None }
+        Self {
+            cpu,
+            cmd: None,
+            symbols: Symbols::new(),
+            sources: Sources::new(),
+            frames: vec![],
+            history: History::default(),
+            watch_hit: None,
+            error: None,
+            tracer: None,
+        }
     }
 
-    pub fn start(mut self) -> ! {
-        let mut rl = rustyline::DefaultEditor::new().unwrap();
-        loop {
-            if RUNNING.load(std::sync::atomic::Ordering::Acquire) {
-                if !self.cpu.debug_exec() {
-                    RUNNING.store(false, std::sync::atomic::Ordering::Release)
-                }
-                continue;
+    /// Log every executed instruction to `tracer`
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Limit the memory used to record history for reverse execution
+    pub fn set_history_budget(&mut self, bytes: usize) {
+        self.history.set_budget(bytes);
+    }
+
+    /// Load symbols and line information from an `ld65 --dbgfile` file
+    ///
+    /// Source files are looked up relative to the directory of `path`.
+    /// Returns the number of symbols and line spans loaded.
+    pub fn load_dbgfile(&mut self, path: impl AsRef<Path>) -> Result<(usize, usize), String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        let n = self.symbols.parse_dbgfile(&text)?;
+        let m = self.sources.parse_dbgfile(&text, dir)?;
+        Ok((n, m))
+    }
+
+    /// Load symbols from a VICE label file, as written by `ld65 -Ln`
+    ///
+    /// Returns the number of symbols loaded.
+    pub fn load_labels(&mut self, path: impl AsRef<Path>) -> Result<usize, String> {
+        self.symbols.load_labels(path)
+    }
+
+    /// Reset the CPU as the RESET line does, after powering the machine off
+    /// and on again if `cold`
+    ///
+    /// The call stack is forgotten, the history is kept.
+    pub fn reset(&mut self, cold: bool) {
+        if cold {
+            self.cpu.power_on();
+        }
+        self.cpu.reset();
+        self.frames.clear();
+        self.watch_hit = None;
+        self.take_snapshot();
+    }
+
+    /// Put the machine in a state saved with `save-state` or `--save-state`
+    ///
+    /// The call stack and the history of the old state are forgotten.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), String> {
+        state.apply(&mut self.cpu)?;
+        self.frames.clear();
+        self.history.clear();
+        self.watch_hit = None;
+        self.take_snapshot();
+        Ok(())
+    }
+
+    /// Execute one instruction, keeping track of subroutine calls and
+    /// recording history
+    fn step(&mut self) -> bool {
+        if self.history.wants_snapshot(self.cycle()) {
+            self.take_snapshot();
+        }
+
+        let pc = self.pc();
+        let op_code = self.cpu.peek(pc).0;
+        let trace = self.tracer.as_mut().and_then(|t| t.next_line(&self.cpu));
+        let start = self.cycle();
+
+        let ok = match self.cpu.debug_exec() {
+            Ok(StepOutcome::Continue) => true,
+            Ok(_) => false,
+            Err(e) => {
+                self.error = Some(e);
+                false
             }
+        };
 
-            let cmd = match Command::get_cmd(&mut rl) {
-                Err(e) => {
-                    println!("ERROR: {e}");
-                    continue;
+        let cycle = self.cycle();
+        if let (Some(tracer), Some(line)) = (self.tracer.as_mut(), trace) {
+            // Nothing ran when stopped at a breakpoint
+            if cycle > start {
+                if let Err(e) = tracer.write_line(&line) {
+                    println!("ERROR: trace: {e}");
+                    self.tracer = None;
                 }
-                Ok(cmd) => cmd,
+            }
+        }
+
+        for (addr, data) in self.cpu.take_writes() {
+            let write = Write {
+                cycle,
+                pc,
+                addr,
+                data,
             };
+            if self.cpu.is_watchpoint(addr) {
+                self.watch_hit = Some(write);
+            }
+            self.history.push_write(write);
+        }
+
+        if self.pc() == pc {
+            // Stopped at a breakpoint, or trapped
+            return ok;
+        }
+
+        match op_code {
+            JSR => self.frames.push(Frame {
+                call_site: pc,
+                target: self.pc(),
+            }),
+            RTS => {
+                self.frames.pop();
+            }
+            _ => (),
+        }
+
+        ok
+    }
+
+    /// Execute one instruction, even if there is a breakpoint on it
+    fn step_over_breakpoint(&mut self) {
+        let pc = self.pc();
+        if !self.step() && self.pc() == pc && !self.cpu.is_trapped() && self.error.is_none() {
+            self.step();
+        }
+    }
+
+    fn cycle(&self) -> u64 {
+        self.cpu.clock().ticks()
+    }
+
+    fn take_snapshot(&mut self) {
+        self.history.push_snapshot(Snapshot {
+            cycle: self.cycle(),
+            cpu: self.cpu.state(),
+            devices: self.cpu.bus().snapshot(),
+            latch: self.cpu.bus().latch(),
+            frames: self.frames.clone(),
+        });
+    }
+
+    /// Set `reg` and take a snapshot, so that going back in time doesn't
+    /// replay the history from before the change and undo it
+    fn set_reg(&mut self, reg: Register, val: Either<Byte, Addr>) {
+        self.cpu.set_reg(reg, val);
+        self.take_snapshot();
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        self.cpu.restore(snapshot.cpu);
+        self.cpu.bus().restore(&snapshot.devices);
+        self.cpu.bus().set_latch(snapshot.latch);
+        self.cpu.clock().set_ticks(snapshot.cycle);
+        self.cpu.take_writes();
+        self.frames = snapshot.frames.clone();
+    }
+
+    /// Run ignoring breakpoints and watchpoints until `cycle` has been reached
+    ///
+    /// Returns the cycle and PC of the instruction boundaries passed on the way.
+    fn run_until(&mut self, cycle: u64) -> Vec<(u64, Addr)> {
+        // Replayed instructions were traced the first time round
+        let tracer = self.tracer.take();
+        let mut boundaries = vec![];
+        while self.cycle() < cycle && !self.cpu.is_trapped() {
+            boundaries.push((self.cycle(), self.pc()));
+            self.step_over_breakpoint();
+        }
+        self.watch_hit = None;
+        self.tracer = tracer;
+        boundaries
+    }
 
-            if matches!(cmd, Command::Nothing) {
-                if let Some(cmd) = self.cmd {
-                    self.handle_cmd(cmd);
+    /// Travel to the instruction boundary at or after `cycle`
+    ///
+    /// Going back restores the latest snapshot before `cycle` and replays from
+    /// there; everything recorded after `cycle` is forgotten. Going forward
+    /// simply runs.
+    fn goto(&mut self, cycle: u64) -> Result<(), String> {
+        if cycle < self.cycle() {
+            let Some(snapshot) = self.history.snapshot_at(cycle).cloned() else {
+                return Err(format!("Cycle {cycle} is no longer in the history"));
+            };
+            self.restore(&snapshot);
+            self.history.truncate(snapshot.cycle);
+        }
+
+        self.run_until(cycle);
+        if self.cpu.is_breakpoint(self.pc()) {
+            self.cpu.skip_breakpoint();
+        }
+        Ok(())
+    }
+
+    /// Go back to the previous instruction
+    fn reverse_step(&mut self) -> Result<(), String> {
+        let now = self.cycle();
+        let Some(&start) = self.history.snapshots_before(now).first() else {
+            return Err("No history to step back into".to_string());
+        };
+
+        self.goto(start)?;
+        let Some(&(previous, _)) = self.run_until(now).last() else {
+            return Err("No instruction to step back to".to_string());
+        };
+        self.goto(previous)
+    }
+
+    /// Go back to the last time a breakpoint was reached or a watchpoint was
+    /// written, or to the start of the history if neither happened
+    fn reverse_continue(&mut self) -> Result<(), String> {
+        let now = self.cycle();
+        let watch = self
+            .history
+            .last_write(now, |addr| self.cpu.is_watchpoint(addr))
+            .map(|w| w.cycle);
+
+        // Replay the history one snapshot at a time, latest first, looking for breakpoints
+        let mut breakpoint = None;
+        let mut end = now;
+        for start in self.history.snapshots_before(now) {
+            if watch.is_some_and(|watch| watch >= end) {
+                break;
+            }
+
+            self.goto(start)?;
+            breakpoint = self
+                .run_until(end)
+                .into_iter()
+                .filter(|&(_, pc)| self.cpu.is_breakpoint(pc))
+                .map(|(cycle, _)| cycle)
+                .next_back();
+            if breakpoint.is_some() {
+                break;
+            }
+            end = start;
+        }
+
+        match breakpoint.max(watch) {
+            Some(cycle) => self.goto(cycle),
+            None => {
+                let Some(first) = self.history.first_cycle() else {
+                    return Err("No history to go back into".to_string());
+                };
+                println!("Reached the start of the history");
+                self.goto(first)
+            }
+        }
+    }
+
+    /// Execute instructions until the source line changes
+    ///
+    /// Stops early at breakpoints and traps.
+    fn step_source(&mut self) {
+        let start = self.sources.line_at(self.pc());
+        for _ in 0..MAX_SOURCE_STEPS {
+            if !self.step() {
+                return;
+            }
+
+            if let Some(line) = self.sources.line_at(self.pc()) {
+                if Some(line) != start {
+                    return;
+                }
+            }
+        }
+
+        println!("No new source line after {MAX_SOURCE_STEPS} instructions");
+    }
+
+    /// Show where execution stopped: the source line with context if it is
+    /// known, the current instruction otherwise
+    fn show_stop(&mut self) {
+        if let Some(e) = self.error.take() {
+            println!("ERROR: {e}");
+        }
+        if self.cpu.is_trapped() {
+            println!("CPU TRAPPED\n{}", self.cpu);
+        }
+        if let Some(write) = self.watch_hit.take() {
+            println!(
+                "Watchpoint {} = {}, written by {}",
+                self.location(write.addr),
+                write.data,
+                self.location(write.pc)
+            );
+        }
+
+        let pc = self.pc();
+        let Some(at) = self.sources.line_at(pc) else {
+            self.disassemble(pc, 1);
+            return;
+        };
+
+        let file = self.sources.file(at.file);
+        println!("{}:{}, {}", file.name, at.line, self.location(pc));
+        match self.sources.context(at, SOURCE_CONTEXT) {
+            Some(lines) => {
+                for (n, text) in lines {
+                    let marker = if n == at.line { "=>" } else { "  " };
+                    println!("{marker} {n:>5}  {text}");
                 }
-                continue;
             }
+            None => println!("   ({} not found)", file.path.display()),
+        }
+    }
+
+    fn pc(&self) -> Addr {
+        self.cpu.get_reg(Register::PC).unwrap_right()
+    }
+
+    /// Format `addr` as `0x1234 <label+offset>`
+    fn location(&self, addr: Addr) -> String {
+        match self.symbols.resolve(addr) {
+            Some(name) => format!("{:#06X} <{name}>", addr.0),
+            None => format!("{:#06X}", addr.0),
+        }
+    }
+
+    fn disassemble(&self, start: Addr, count: usize) {
+        let pc = self.pc();
+        let mut addr = start;
+        for _ in 0..count {
+            let ins = disasm::disassemble(addr, |addr| self.cpu.peek(addr));
+            if let Some(label) = self.symbols.at(addr) {
+                println!("{label}:");
+            }
+            println!(
+                "{} {:#06X}  {:<9} {}",
+                if addr == pc { "=>" } else { "  " },
+                addr.0,
+                ins.hex(),
+                ins.format(|addr| self.symbols.resolve(addr))
+            );
+            addr = ins.next();
+        }
+    }
 
-            self.handle_cmd(cmd);
-            self.cmd = Some(cmd);
+    fn backtrace(&self) {
+        println!("#0  {}", self.location(self.pc()));
+        for (i, frame) in self.frames.iter().rev().enumerate() {
+            println!(
+                "#{}  {} called {}",
+                i + 1,
+                self.location(frame.call_site),
+                self.location(frame.target)
+            );
+        }
+    }
+
+    /// Read `len` bytes at `start`, which all have to be mapped
+    fn read_mem(&self, start: Addr, len: usize) -> Result<Vec<u8>, String> {
+        (0..len)
+            .map(|i| expr::Context::peek(self, start + i).map(|b| b.0))
+            .collect()
+    }
+
+    /// Write `data` at `start`, which must fit below the end of the address
+    /// space, and take a snapshot as `set_reg` does
+    fn write_mem(&mut self, start: Addr, data: &[u8]) -> Result<(), String> {
+        if start.0 as usize + data.len() > 0x10000 {
+            return Err("Past the end of the address space".to_string());
+        }
+        for (i, byte) in data.iter().enumerate() {
+            self.cpu.poke(start + i, *byte);
         }
+        self.take_snapshot();
+        Ok(())
+    }
+
+    /// `x/<count><format><size> <addr>`
+    fn examine(&self, count: usize, format: Format, addr: &Expr) -> Result<(), String> {
+        let start = addr.eval_addr(self)?;
+        let size = if format.word { 2 } else { 1 };
+        let per_row = 16 / size;
+        let len = (count * size).min(0x10000 - start.0 as usize);
+        let data = self.read_mem(start, len)?;
+
+        for (row, chunk) in data.chunks(per_row * size).enumerate() {
+            let mut line = format!("{}:", self.location(start + row * per_row * size));
+            for item in chunk.chunks(size) {
+                let v = if format.word {
+                    item[0] as u16 | (*item.get(1).unwrap_or(&0) as u16) << 8
+                } else {
+                    item[0] as u16
+                };
+                line += &match (format.radix, format.word) {
+                    ('x', false) => format!(" {v:02X}"),
+                    ('x', true) => format!(" {v:04X}"),
+                    ('d', false) => format!(" {v:>3}"),
+                    ('d', true) => format!(" {v:>5}"),
+                    _ => format!(" {}", printable(v as u8)),
+                };
+            }
+            if format.radix == 'x' && !format.word {
+                let text: String = chunk.iter().map(|b| printable(*b)).collect();
+                line += &format!("{:pad$}  |{text}|", "", pad = (per_row - chunk.len()) * 3);
+            }
+            println!("{line}");
+        }
+        Ok(())
+    }
+
+    /// `fill`, `find`, `copy`, `load` and `save`
+    fn memory_cmd(&mut self, cmd: Command) -> Result<(), String> {
+        match cmd {
+            Command::Fill(range, pattern) => {
+                let (start, len) = range.eval(self)?;
+                let pattern = self.pattern(&pattern)?;
+                let data: Vec<u8> = pattern.iter().copied().cycle().take(len).collect();
+                self.write_mem(start, &data)?;
+            }
+            Command::Find(range, pattern) => {
+                let (start, len) = range.eval(self)?;
+                let pattern = self.pattern(&pattern)?;
+                let data = self.read_mem(start, len)?;
+                let mut found = 0;
+                for (i, window) in data.windows(pattern.len()).enumerate() {
+                    if window == pattern.as_slice() {
+                        println!("{}", self.location(start + i));
+                        found += 1;
+                    }
+                }
+                println!("{found} found");
+            }
+            Command::Copy(src, dst, len) => {
+                let src = src.eval_addr(self)?;
+                let dst = dst.eval_addr(self)?;
+                let len = len.eval(self)?;
+                let len = usize::try_from(len).map_err(|_| format!("Invalid length {len}"))?;
+                let data = self.read_mem(src, len.min(0x10000 - src.0 as usize))?;
+                self.write_mem(dst, &data)?;
+            }
+            Command::Load(path, addr, len) => {
+                let addr = addr.eval_addr(self)?;
+                let mut data = std::fs::read(&path).map_err(|e| format!("{path}: {e}"))?;
+                if let Some(len) = len {
+                    data.truncate(len.eval(self)?.max(0) as usize);
+                }
+                self.write_mem(addr, &data)?;
+                println!("Loaded {} bytes at {}", data.len(), self.location(addr));
+            }
+            Command::Save(path, addr, len) => {
+                let addr = addr.eval_addr(self)?;
+                let len = match len {
+                    Some(len) => len.eval(self)?.max(0) as usize,
+                    None => 0x10000 - addr.0 as usize,
+                };
+                let data = self.read_mem(addr, len.min(0x10000 - addr.0 as usize))?;
+                std::fs::write(&path, &data).map_err(|e| format!("{path}: {e}"))?;
+                println!("Saved {} bytes from {}", data.len(), self.location(addr));
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// The bytes of a `fill` or `find` pattern
+    fn pattern(&self, pattern: &[Pattern]) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![];
+        for item in pattern {
+            match item {
+                Pattern::Text(text) => bytes.extend(text.bytes()),
+                Pattern::Byte(expr) => bytes.push(expr.eval_byte(self)?.0),
+            }
+        }
+        if bytes.is_empty() {
+            return Err("Empty pattern".to_string());
+        }
+        Ok(bytes)
     }
 
     fn handle_cmd(&mut self, cmd: Command) {
@@ -58,13 +555,21 @@ impl Debugger {
             Break(addr) => self.cpu.breakpoint(addr),
             Run => RUNNING.store(true, std::sync::atomic::Ordering::Release),
             Step => {
-                self.cpu.debug_exec();
+                self.step();
+                self.show_stop();
             }
-            StoreMem(addr, val) => self.cpu.write(addr, val),
-            StoreReg(reg, val) => self.cpu.set_reg(reg, Either::Left(val)),
-            StoreRegLong(reg, val) => self.cpu.set_reg(reg, Either::Right(val)),
+            StepSource => {
+                self.step_source();
+                self.show_stop();
+            }
+            StoreMem(addr, val) => {
+                self.cpu.poke(addr, val);
+                self.take_snapshot();
+            }
+            StoreReg(reg, val) => self.set_reg(reg, Either::Left(val)),
+            StoreRegLong(reg, val) => self.set_reg(reg, Either::Right(val)),
             LoadMem(addr) => {
-                let val = self.cpu.read(*addr);
+                let val = self.cpu.peek(addr);
                 println!("{val}");
             }
             LoadReg(reg) => {
@@ -76,15 +581,67 @@ impl Debugger {
             }
             ShowRegs => {
                 println!(
-                    "A:  {}\nX:  {}\nY:  {}\nPS: 0b{:b}\nSP: {}\nPC: {}\n",
+                    "A:  {}\nX:  {}\nY:  {}\nPS: 0b{:b}\nSP: {}\nPC: {}\nCYC: {}\n",
                     self.cpu.get_reg(Register::A).unwrap_left(),
                     self.cpu.get_reg(Register::X).unwrap_left(),
                     self.cpu.get_reg(Register::Y).unwrap_left(),
                     self.cpu.get_reg(Register::PS).unwrap_left().0,
                     self.cpu.get_reg(Register::SP).unwrap_left(),
-                    self.cpu.get_reg(Register::PC).unwrap_right(),
+                    self.location(self.pc()),
+                    self.cycle(),
                 )
             }
+            Disassemble(addr, count) => self.disassemble(addr.unwrap_or(self.pc()), count),
+            Backtrace => self.backtrace(),
+            Watch(addr) => self.cpu.watchpoint(addr),
+            ReverseStep | ReverseContinue | Goto(_) => {
+                let res = match cmd {
+                    ReverseStep => self.reverse_step(),
+                    ReverseContinue => self.reverse_continue(),
+                    Goto(cycle) => self.goto(cycle),
+                    _ => unreachable!(),
+                };
+                match res {
+                    Ok(()) => self.show_stop(),
+                    Err(e) => println!("ERROR: {e}"),
+                }
+            }
+            Examine(count, format, addr) => {
+                if let Err(e) = self.examine(count, format, &addr) {
+                    println!("ERROR: {e}");
+                }
+            }
+            Print(expr) => match expr.eval(self) {
+                Ok(v) if (0..=0xFFFF).contains(&v) => println!("{v:#X} ({v})"),
+                Ok(v) => println!("{v}"),
+                Err(e) => println!("ERROR: {e}"),
+            },
+            Fill(..) | Find(..) | Copy(..) | Load(..) | Save(..) => {
+                if let Err(e) = self.memory_cmd(cmd) {
+                    println!("ERROR: {e}");
+                }
+            }
+            SaveMachine(path) => match SaveState::capture(&self.cpu).save(&path) {
+                Ok(()) => println!("Saved the machine at cycle {} to {path}", self.cycle()),
+                Err(e) => println!("ERROR: {e}"),
+            },
+            Reset(cold) => {
+                self.reset(cold);
+                self.show_stop();
+            }
+            LoadMachine(path) => match SaveState::load(&path).and_then(|s| self.load_state(&s)) {
+                Ok(()) => self.show_stop(),
+                Err(e) => println!("ERROR: {e}"),
+            },
+            ShowHistory => match self.history.first_cycle() {
+                Some(first) => println!(
+                    "Recorded from cycle {first}, {} snapshots in {} KiB\nNow at cycle {}",
+                    self.history.snapshot_count(),
+                    self.history.size() / 1024,
+                    self.cycle()
+                ),
+                None => println!("Nothing recorded yet"),
+            },
             Nothing => (),
         }
     }
@@ -105,12 +662,66 @@ impl TryFrom<&str> for Register {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+impl expr::Context for Debugger {
+    fn reg(&self, reg: Register) -> u16 {
+        match self.cpu.get_reg(reg) {
+            Either::Left(val) => val.0 as u16,
+            Either::Right(val) => val.0,
+        }
+    }
+
+    fn peek(&self, addr: Addr) -> Result<Byte, String> {
+        self.cpu
+            .bus()
+            .peek(addr)
+            .ok_or(format!("Nothing mapped at {:#06X}", addr.0))
+    }
+
+    fn symbol(&self, name: &str) -> Option<Addr> {
+        self.symbols.get(name)
+    }
+}
+
+fn printable(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        byte as char
+    } else {
+        '.'
+    }
+}
+
+/// How `x` shows memory
+#[derive(Debug, Clone, Copy)]
+struct Format {
+    /// `x` hex, `d` decimal or `c` characters
+    radix: char,
+    /// Words instead of bytes
+    word: bool,
+}
+
+/// Part of a `fill` or `find` pattern
+#[derive(Debug, Clone)]
+enum Pattern {
+    Byte(Expr),
+    Text(String),
+}
+
+impl Pattern {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            Some(text) => Ok(Pattern::Text(text.to_string())),
+            None => Ok(Pattern::Byte(Expr::parse(value)?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 enum Command {
     Help,
     Break(Addr),                  // break <word>
     Run,                          // run
     Step,                         // step
+    StepSource,                   // step source
     StoreMem(Addr, Byte),         // set mem <word> <byte>
     StoreReg(Register, Byte),     // set reg [AXY(PS)] <byte>
     StoreRegLong(Register, Addr), // set reg [(PC)(SP)] <word>
@@ -118,6 +729,23 @@ enum Command {
     LoadReg(Register),            // get reg [AXY(PS)(PC)(SP)]
     Nothing,
     ShowRegs,
+    Disassemble(Option<Addr>, usize), // disas [<word>] [<count>]
+    Backtrace,                        // bt
+    Watch(Addr),                      // watch <word>
+    ReverseStep,                      // reverse-step
+    ReverseContinue,                  // reverse-continue
+    Goto(u64),                        // goto <cycle>
+    ShowHistory,                      // history
+    Examine(usize, Format, Expr),     // x/<count><format><size> <expr>
+    Print(Expr),                      // print <expr>
+    Fill(Range, Vec<Pattern>),        // fill <range> <pattern>
+    Find(Range, Vec<Pattern>),        // find <range> <pattern>
+    Copy(Expr, Expr, Expr),           // copy <src> <dst> <len>
+    Load(String, Expr, Option<Expr>), // load <file> <addr> [<len>]
+    Save(String, Expr, Option<Expr>), // save <file> <addr> [<len>]
+    SaveMachine(String),              // save-state <file>
+    LoadMachine(String),              // load-state <file>
+    Reset(bool),                      // reset [cold]
 }
 
 impl TryFrom<&str> for Addr {
@@ -125,6 +753,8 @@ impl TryFrom<&str> for Addr {
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         let val = if value.starts_with("0x") {
             u16::from_str_radix(value.strip_prefix("0x").unwrap(), 16)
+        } else if value.starts_with('$') {
+            u16::from_str_radix(value.strip_prefix('$').unwrap(), 16)
         } else if value.starts_with("0b") {
             u16::from_str_radix(value.strip_prefix("0b").unwrap(), 2)
         } else {
@@ -139,9 +769,11 @@ impl TryFrom<&str> for Byte {
     type Error = String;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         let val = if value.starts_with("0x") {
-            u8::from_str_radix(value, 16)
+            u8::from_str_radix(value.strip_prefix("0x").unwrap(), 16)
+        } else if value.starts_with('$') {
+            u8::from_str_radix(value.strip_prefix('$').unwrap(), 16)
         } else if value.starts_with("0b") {
-            u8::from_str_radix(value, 2)
+            u8::from_str_radix(value.strip_prefix("0b").unwrap(), 2)
         } else {
             value.parse::<u8>()
         };
@@ -151,50 +783,122 @@ impl TryFrom<&str> for Byte {
 }
 
 impl Command {
-    pub fn get_cmd(rl: &mut DefaultEditor) -> Result<Self, String> {
-        let readline = rl.readline("cmd> ");
-
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(line.as_str()).unwrap();
-                if line.is_empty() {
-                    Ok(Self::Nothing)
-                } else {
-                    Self::parse_line(&line)
-                }
-            }
-            Err(ReadlineError::Eof) => {
-                std::process::exit(0);
-            }
-            Err(ReadlineError::Interrupted) => Err(String::new()),
-            Err(err) => {
-                println!("Error: {:?}", err);
-                std::process::exit(1);
+    /// Parse the `16xb` of `x/16xb`
+    fn parse_format(spec: &str) -> Result<(usize, Format), String> {
+        let digits = spec.chars().take_while(char::is_ascii_digit).count();
+        let count = match digits {
+            0 => 1,
+            _ => spec[..digits]
+                .parse()
+                .map_err(|e| format!("Invalid count: {e}"))?,
+        };
+
+        let mut format = Format {
+            radix: 'x',
+            word: false,
+        };
+        for c in spec[digits..].chars() {
+            match c {
+                'x' | 'd' | 'c' => format.radix = c,
+                'b' => format.word = false,
+                'w' => format.word = true,
+                _ => return Err(format!("Invalid format: {c:?}")),
             }
         }
+        Ok((count, format))
     }
 
-    pub fn parse_line(line: &str) -> Result<Self, String> {
+    pub fn parse_line(line: &str, symbols: &Symbols, sources: &Sources) -> Result<Self, String> {
         use Command::*;
         match line.split_whitespace().collect::<Vec<_>>().as_slice() {
             ["help"] => Ok(Help),
-            ["break", addr] | ["b", addr] => Ok(Break(Addr::try_from(*addr)?)),
+            ["break", location] | ["b", location] => match location.rsplit_once(':') {
+                Some((file, line)) if line.parse::<usize>().is_ok() => {
+                    Ok(Break(sources.addr_of(file, line.parse().unwrap())?))
+                }
+                _ => Ok(Break(symbols.parse_addr(location)?)),
+            },
             ["run"] | ["r"] => Ok(Run),
             ["step"] | ["s"] => Ok(Step),
+            ["step", "source"] | ["ss"] => Ok(StepSource),
             ["set", "mem", addr, val] => {
-                Ok(StoreMem(Addr::try_from(*addr)?, Byte::try_from(*val)?))
+                Ok(StoreMem(symbols.parse_addr(addr)?, Byte::try_from(*val)?))
             }
             ["set", "reg", reg, val] => {
                 let reg = Register::try_from(*reg)?;
                 if matches!(reg, Register::PC | Register::SP) {
-                    Ok(StoreRegLong(reg, Addr::try_from(*val)?))
+                    Ok(StoreRegLong(reg, symbols.parse_addr(val)?))
                 } else {
                     Ok(StoreReg(reg, Byte::try_from(*val)?))
                 }
             }
-            ["get", "mem", addr] => Ok(LoadMem(Addr::try_from(*addr)?)),
+            ["get", "mem", addr] => Ok(LoadMem(symbols.parse_addr(addr)?)),
             ["get", "reg", reg] => Ok(LoadReg(Register::try_from(*reg)?)),
             ["regs"] => Ok(ShowRegs),
+            ["disas"] => Ok(Disassemble(None, 10)),
+            ["disas", addr] => Ok(Disassemble(Some(symbols.parse_addr(addr)?), 10)),
+            ["disas", addr, count] => Ok(Disassemble(
+                Some(symbols.parse_addr(addr)?),
+                count.parse().map_err(|e| format!("Invalid count: {e}"))?,
+            )),
+            ["bt"] | ["backtrace"] => Ok(Backtrace),
+            ["watch", addr] | ["w", addr] => Ok(Watch(symbols.parse_addr(addr)?)),
+            ["reverse-step"] | ["rs"] => Ok(ReverseStep),
+            ["reverse-continue"] | ["rc"] => Ok(ReverseContinue),
+            ["goto", cycle] => Ok(Goto(
+                cycle.parse().map_err(|e| format!("Invalid cycle: {e}"))?,
+            )),
+            ["history"] => Ok(ShowHistory),
+            [x, addr] if x.starts_with("x/") => {
+                let (count, format) = Self::parse_format(&x[2..])?;
+                Ok(Examine(count, format, Expr::parse(addr)?))
+            }
+            ["x", addr] => Ok(Examine(
+                1,
+                Format {
+                    radix: 'x',
+                    word: false,
+                },
+                Expr::parse(addr)?,
+            )),
+            ["print" | "p", expr @ ..] if !expr.is_empty() => {
+                Ok(Print(Expr::parse(&expr.join(" "))?))
+            }
+            ["fill", range, pattern @ ..] if !pattern.is_empty() => Ok(Fill(
+                Range::parse(range)?,
+                pattern
+                    .iter()
+                    .map(|p| Pattern::parse(p))
+                    .collect::<Result<_, _>>()?,
+            )),
+            ["find", range, pattern @ ..] if !pattern.is_empty() => Ok(Find(
+                Range::parse(range)?,
+                pattern
+                    .iter()
+                    .map(|p| Pattern::parse(p))
+                    .collect::<Result<_, _>>()?,
+            )),
+            ["copy", src, dst, len] => Ok(Copy(
+                Expr::parse(src)?,
+                Expr::parse(dst)?,
+                Expr::parse(len)?,
+            )),
+            ["load", file, addr] => Ok(Load(file.to_string(), Expr::parse(addr)?, None)),
+            ["load", file, addr, len] => Ok(Load(
+                file.to_string(),
+                Expr::parse(addr)?,
+                Some(Expr::parse(len)?),
+            )),
+            ["save", file, addr] => Ok(Save(file.to_string(), Expr::parse(addr)?, None)),
+            ["save", file, addr, len] => Ok(Save(
+                file.to_string(),
+                Expr::parse(addr)?,
+                Some(Expr::parse(len)?),
+            )),
+            ["save-state", file] => Ok(SaveMachine(file.to_string())),
+            ["load-state", file] => Ok(LoadMachine(file.to_string())),
+            ["reset"] => Ok(Reset(false)),
+            ["reset", "cold"] => Ok(Reset(true)),
             [] => Ok(Nothing),
             cmd => Err(format!("Invalid cmd: {cmd:?}")),
         }
@@ -204,10 +908,214 @@ impl Command {
 const USAGE: &str = r#"
     help                    Print this message
     break <addr>            Set a breakpoint at address <addr>
+    break <file>:<line>     Set a breakpoint at the first instruction of a source line
     run                     Resume execution
     step                    Step through one instruction   
+    step source             Step until the source line changes
     set mem <addr> <val>    Store <val> at address <addr>
     set reg <reg> <val>     Store <val> into register <reg> (A(u8), X(u8), Y(u8), PS(u8), PC(u16), SP(u8))
     get mem <addr>          Load <val> from address <addr>
     get reg <reg>           Load <val> from register <reg> (A, X, Y, PS, PC, SP)
+    regs                    Show all registers
+    disas [<addr>] [<n>]    Disassemble <n> instructions at <addr> (default: PC)
+    bt                      Show the subroutine call stack
+    watch <addr>            Stop after any instruction that writes to <addr>
+    reverse-step            Go back one instruction
+    reverse-continue        Go back to the last breakpoint or write to a watched address
+    goto <cycle>            Go back, or forward, to cycle <cycle>
+    history                 Show how far back execution can go
+    x/<n><f><s> <expr>      Examine <n> units of memory at <expr>, <f> is x(hex), d(decimal)
+                            or c(char), <s> is b(byte) or w(word); default x/1xb
+    print <expr>            Evaluate and print <expr>
+    fill <range> <pattern>  Fill <range> repeating <pattern>
+    find <range> <pattern>  Find all occurences of <pattern> in <range>
+    copy <src> <dst> <len>  Copy <len> bytes from <src> to <dst>
+    load <file> <addr> [<len>]
+                            Load the contents of <file>, or its first <len> bytes, at <addr>
+    save <file> <addr> [<len>]
+                            Save <len> bytes at <addr>, or all up to $FFFF, to <file>
+    save-state <file>       Save the whole machine to <file>
+    load-state <file>       Put the machine back in the state saved in <file>
+    reset [cold]            Reset the CPU, after powering the machine off and on with cold
+
+    Wherever an <addr> is expected a symbol, or symbol+offset, can be used
+    once symbols have been loaded with --dbgfile or --labels. Source lines are
+    only known from --dbgfile.
+
+    Expressions are made of numbers ($FF, 0xFF, %101, 0b101, 255, 'A'), registers
+    (a, x, y, ps, sp, pc), symbols, [<addr>] for the byte and w[<addr>] for the
+    word at <addr>, and the operators + - * / % & | ^ << >>, unary - ~ and < >
+    for the low and high byte. Only print allows spaces inside an expression.
+    A <range> is <start>..<end> or <start>..=<end>, a <pattern> is a list of
+    byte expressions and "strings".
+
+    Execution is recorded so it can be reversed, within the memory budget
+    set by --history-mb. Going back and then running again forgets the old
+    future. Keyboard input is not recorded, and display output is repeated
+    while replaying.
 "#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::asm6502;
+    use crate::hardware::bus::Bus;
+    use crate::hardware::clock::Clock;
+    use crate::hardware::memory::Memory;
+    use std::sync::Arc;
+
+    fn debugger() -> Debugger {
+        let (cpu, _) = asm6502!("loop: inx\n stx $10\n jmp loop").prepare();
+        Debugger::new(cpu, false)
+    }
+
+    fn now(dbg: &Debugger) -> (u64, Addr, Byte) {
+        (dbg.cycle(), dbg.pc(), dbg.cpu.peek(Addr(0x10)))
+    }
+
+    #[test]
+    fn test_parse_byte() {
+        assert_eq!(Byte::try_from("0x1F"), Ok(Byte(0x1F)));
+        assert_eq!(Byte::try_from("$ff"), Ok(Byte(0xFF)));
+        assert_eq!(Byte::try_from("0b101"), Ok(Byte(5)));
+        assert_eq!(Byte::try_from("42"), Ok(Byte(42)));
+        assert!(Byte::try_from("0x100").is_err());
+    }
+
+    #[test]
+    fn test_memory_commands() {
+        let mut dbg = debugger();
+        let parse = |line| Command::parse_line(line, &Symbols::new(), &Sources::new()).unwrap();
+
+        assert!(dbg.memory_cmd(parse("fill $10..=$17 1 2")).is_ok());
+        assert_eq!(
+            dbg.read_mem(Addr(0x10), 9),
+            Ok(vec![1, 2, 1, 2, 1, 2, 1, 2, 0])
+        );
+
+        assert!(dbg.memory_cmd(parse("copy $10 $13 4")).is_ok());
+        assert_eq!(
+            dbg.read_mem(Addr(0x10), 8),
+            Ok(vec![1, 2, 1, 1, 2, 1, 2, 2])
+        );
+
+        assert!(dbg.memory_cmd(parse("fill $20..$22 \"hi\"")).is_ok());
+        assert_eq!(Expr::parse("w[$20]").unwrap().eval(&dbg), Ok(0x6968));
+        assert!(dbg.memory_cmd(parse("fill $FFFF..=$FFFF 1 2")).is_ok());
+        assert!(dbg.memory_cmd(parse("copy $10 $FFFF 2")).is_err());
+    }
+
+    #[test]
+    fn test_reverse_step() {
+        let mut dbg = debugger();
+        let mut trace = vec![];
+        for _ in 0..30 {
+            trace.push(now(&dbg));
+            assert!(dbg.step());
+        }
+
+        for expected in trace.iter().rev() {
+            assert!(dbg.reverse_step().is_ok());
+            assert_eq!(now(&dbg), *expected);
+        }
+        assert!(dbg.reverse_step().is_err());
+
+        // Forward again, through the same states
+        assert!(dbg.goto(trace[10].0).is_ok());
+        assert_eq!(now(&dbg), trace[10]);
+    }
+
+    #[test]
+    fn test_reverse_step_open_bus() {
+        let mut bus = Bus::new();
+        bus.register(Memory::new(Addr(0), Addr(0x7FFF))).unwrap();
+        bus.register(Memory::new(Addr(0xFF00), Addr(0xFFFF)))
+            .unwrap();
+        let program = assemble(
+            "
+        .org $0400
+loop:   lda #$55
+        sta $8000       ; nothing there
+        lda $8000
+        jmp loop
+        .org $FFFC
+        .word loop",
+        )
+        .unwrap();
+        for (addr, byte) in program.bytes() {
+            bus.poke(addr, byte);
+        }
+        let mut dbg = Debugger::new(CPU::new(bus, Arc::new(Clock::new())), true);
+
+        let now = |dbg: &Debugger| {
+            (
+                dbg.cycle(),
+                dbg.pc(),
+                dbg.cpu.get_reg(Register::A).unwrap_left(),
+                dbg.cpu.bus().latch(),
+            )
+        };
+        let mut trace = vec![];
+        for _ in 0..8 {
+            trace.push(now(&dbg));
+            assert!(dbg.step());
+        }
+        for expected in trace.iter().rev() {
+            assert!(dbg.reverse_step().is_ok());
+            assert_eq!(now(&dbg), *expected);
+        }
+    }
+
+    #[test]
+    fn test_reverse_step_trapped() {
+        let (cpu, _) = asm6502!("jmp *").prepare();
+        let mut dbg = Debugger::new(cpu, false);
+        dbg.step();
+        assert!(dbg.cpu.is_trapped());
+        dbg.take_snapshot();
+        // Replaying from the snapshot runs nothing
+        dbg.cpu.clock().set_ticks(dbg.cycle() + 10);
+        assert_eq!(
+            dbg.reverse_step(),
+            Err("No instruction to step back to".to_string())
+        );
+    }
+
+    #[test]
+    fn test_reverse_step_after_set_reg() {
+        let mut dbg = debugger();
+        for _ in 0..5 {
+            assert!(dbg.step());
+        }
+        dbg.set_reg(Register::PC, Either::Right(Addr(0x400)));
+        let before = now(&dbg);
+        assert!(dbg.step());
+        assert!(dbg.reverse_step().is_ok());
+        assert_eq!(now(&dbg), before);
+    }
+
+    #[test]
+    fn test_reverse_continue() {
+        let mut dbg = debugger();
+        dbg.cpu.breakpoint(Addr(0x403));
+
+        let mut hits = vec![];
+        for _ in 0..30 {
+            if dbg.pc() == Addr(0x403) {
+                hits.push(now(&dbg));
+            }
+            dbg.step_over_breakpoint();
+        }
+
+        assert!(dbg.reverse_continue().is_ok());
+        assert_eq!(now(&dbg), hits[hits.len() - 1]);
+        assert!(dbg.reverse_continue().is_ok());
+        assert_eq!(now(&dbg), hits[hits.len() - 2]);
+
+        // The write to $10 ends where the breakpoint before it is hit
+        dbg.cpu.watchpoint(Addr(0x10));
+        assert!(dbg.reverse_continue().is_ok());
+        assert_eq!(now(&dbg), hits[hits.len() - 3]);
+    }
+}
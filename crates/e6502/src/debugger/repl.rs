@@ -0,0 +1,67 @@
+//! The interactive command line of the debugger
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use super::{Command, Debugger, Sources, Symbols, RUNNING};
+
+impl Debugger {
+    pub fn start(mut self) -> ! {
+        let mut rl = rustyline::DefaultEditor::new().unwrap();
+        loop {
+            if RUNNING.load(std::sync::atomic::Ordering::Acquire) {
+                if !self.step() {
+                    RUNNING.store(false, std::sync::atomic::Ordering::Release);
+                    self.show_stop();
+                }
+                continue;
+            }
+
+            let cmd = match Command::get_cmd(&mut rl, &self.symbols, &self.sources) {
+                Err(e) => {
+                    println!("ERROR: {e}");
+                    continue;
+                }
+                Ok(cmd) => cmd,
+            };
+
+            if matches!(cmd, Command::Nothing) {
+                if let Some(cmd) = self.cmd.clone() {
+                    self.handle_cmd(cmd);
+                }
+                continue;
+            }
+
+            self.cmd = Some(cmd.clone());
+            self.handle_cmd(cmd);
+        }
+    }
+}
+
+impl Command {
+    pub fn get_cmd(
+        rl: &mut DefaultEditor,
+        symbols: &Symbols,
+        sources: &Sources,
+    ) -> Result<Self, String> {
+        let readline = rl.readline("cmd> ");
+
+        match readline {
+            Ok(line) => {
+                rl.add_history_entry(line.as_str()).unwrap();
+                if line.is_empty() {
+                    Ok(Self::Nothing)
+                } else {
+                    Self::parse_line(&line, symbols, sources)
+                }
+            }
+            Err(ReadlineError::Eof) => {
+                std::process::exit(0);
+            }
+            Err(ReadlineError::Interrupted) => Err(String::new()),
+            Err(err) => {
+                println!("Error: {:?}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use super::dbginfo;
+use crate::types::Addr;
+
+/// `type` of a `line` record produced by a macro expansion
+const LINE_TYPE_MACRO: u32 = 2;
+
+pub struct SourceFile {
+    pub name: String,
+    pub path: PathBuf,
+    lines: Option<Vec<String>>,
+}
+
+/// A line in one of the loaded source files, `line` counts from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceLine {
+    pub file: usize,
+    pub line: usize,
+}
+
+/// Address to source line mapping loaded from ld65 debug info
+#[derive(Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+    /// Source line of every byte that was assembled, and whether it came from a macro
+    by_addr: BTreeMap<u16, (SourceLine, bool)>,
+    /// Lowest address assembled from a source line
+    by_line: BTreeMap<SourceLine, Addr>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    /// Load the line information of an `ld65 --dbgfile` file at `path`
+    ///
+    /// Source files are looked up relative to the directory of `path`.
+    pub fn load_dbgfile(&mut self, path: impl AsRef<Path>) -> Result<usize, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.parse_dbgfile(&text, path.parent().unwrap_or(Path::new(".")))
+    }
+
+    /// Load the line information of an `ld65 --dbgfile` file
+    ///
+    /// Relative source file names are looked up relative to `dir`.
+    pub fn parse_dbgfile(&mut self, text: &str, dir: &Path) -> Result<usize, String> {
+        let records = dbginfo::parse(text)?;
+
+        let mut files = HashMap::new();
+        let mut segs = HashMap::new();
+        let mut spans = HashMap::new();
+        for record in &records {
+            let Some(id) = record.int("id") else {
+                continue;
+            };
+            match record.kind {
+                "file" => {
+                    let name = record.get("name").unwrap_or_default();
+                    let path = dir.join(name);
+                    let lines = std::fs::read_to_string(&path)
+                        .ok()
+                        .map(|text| text.lines().map(str::to_string).collect());
+                    files.insert(id, self.files.len());
+                    self.files.push(SourceFile {
+                        name: name.to_string(),
+                        path,
+                        lines,
+                    });
+                }
+                "seg" => {
+                    segs.insert(id, record.int("start").unwrap_or_default());
+                }
+                "span" => {
+                    let seg = record.int("seg").unwrap_or_default();
+                    let start = record.int("start").unwrap_or_default();
+                    let size = record.int("size").unwrap_or_default();
+                    spans.insert(id, (seg, start, size));
+                }
+                _ => (),
+            }
+        }
+
+        let mut mapped = 0;
+        for record in records.iter().filter(|r| r.kind == "line") {
+            let (Some(file), Some(line)) = (record.int("file"), record.int("line")) else {
+                continue;
+            };
+            let Some(&file) = files.get(&file) else {
+                return Err(format!("line record refers to unknown file {file}"));
+            };
+            let at = SourceLine {
+                file,
+                line: line as usize,
+            };
+            let from_macro = record.int("type") == Some(LINE_TYPE_MACRO);
+
+            for span in record.get("span").unwrap_or_default().split('+') {
+                let Some(&(seg, start, size)) = span.parse().ok().and_then(|id| spans.get(&id))
+                else {
+                    continue;
+                };
+                let Some(&base) = segs.get(&seg) else {
+                    continue;
+                };
+
+                let start = base.saturating_add(start);
+                if start.saturating_add(size) > 0x10000 {
+                    return Err(format!(
+                        "span {span} runs past the end of the address space"
+                    ));
+                }
+                for addr in start..start + size {
+                    let addr = addr as u16;
+                    // Prefer the line that invoked a macro over the lines of its body
+                    match self.by_addr.get(&addr) {
+                        Some((_, false)) if from_macro => continue,
+                        _ => self.by_addr.insert(addr, (at, from_macro)),
+                    };
+                }
+
+                let start = start as u16;
+                let first = self.by_line.entry(at).or_insert(Addr(start));
+                if start < first.0 {
+                    *first = Addr(start);
+                }
+                mapped += 1;
+            }
+        }
+
+        Ok(mapped)
+    }
+
+    /// The source line the byte at `addr` was assembled from
+    pub fn line_at(&self, addr: Addr) -> Option<SourceLine> {
+        self.by_addr.get(&addr.0).map(|(line, _)| *line)
+    }
+
+    pub fn file(&self, idx: usize) -> &SourceFile {
+        &self.files[idx]
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Every assembled byte with its source line, by address
+    pub fn lines(&self) -> impl Iterator<Item = (Addr, SourceLine)> + '_ {
+        self.by_addr
+            .iter()
+            .map(|(addr, (line, _))| (Addr(*addr), *line))
+    }
+
+    /// The first address of `file`:`line`, or of the first line after it that has code
+    ///
+    /// `file` matches a loaded file by name or by the last components of its path.
+    pub fn addr_of(&self, file: &str, line: usize) -> Result<Addr, String> {
+        let Some(idx) = self.files.iter().position(|f| {
+            f.name == file
+                || Path::new(&f.name).ends_with(file)
+                || f.path.ends_with(file)
+                || Path::new(file).ends_with(&f.name)
+        }) else {
+            return Err(format!("No source file named {file}"));
+        };
+
+        self.by_line
+            .range(SourceLine { file: idx, line }..)
+            .next()
+            .filter(|(at, _)| at.file == idx)
+            .map(|(_, addr)| *addr)
+            .ok_or_else(|| format!("No code at or after {file}:{line}"))
+    }
+
+    /// Text of the lines surrounding `at`, `context` lines on each side
+    pub fn context(&self, at: SourceLine, context: usize) -> Option<Vec<(usize, &str)>> {
+        let lines = self.files[at.file].lines.as_ref()?;
+        let first = at.line.saturating_sub(context).max(1);
+        let last = (at.line + context).min(lines.len());
+
+        Some((first..=last).map(|n| (n, lines[n - 1].as_str())).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DBGFILE: &str = "version\tmajor=2,minor=0
+file\tid=0,name=\"flag.s\",size=100,mtime=0x0,mod=0
+file\tid=1,name=\"macros.s\",size=100,mtime=0x0,mod=0
+seg\tid=0,name=\"CODE\",start=0x000400,size=0x0010,addrsize=absolute,type=rw
+span\tid=0,seg=0,start=0,size=2
+span\tid=1,seg=0,start=2,size=1
+span\tid=2,seg=0,start=3,size=3
+line\tid=0,file=0,line=10,span=0
+line\tid=1,file=0,line=11,span=1+2
+line\tid=2,file=1,line=7,type=2,count=1,span=2
+";
+
+    #[test]
+    fn test_lines() {
+        let mut sources = Sources::new();
+        assert!(sources.parse_dbgfile(DBGFILE, Path::new(".")).is_ok());
+
+        let flag = SourceLine { file: 0, line: 10 };
+        assert_eq!(sources.line_at(Addr(0x400)), Some(flag));
+        assert_eq!(sources.line_at(Addr(0x401)), Some(flag));
+        // The macro body does not hide the line that invoked it
+        assert_eq!(
+            sources.line_at(Addr(0x403)),
+            Some(SourceLine { file: 0, line: 11 })
+        );
+        assert_eq!(sources.line_at(Addr(0x406)), None);
+
+        assert_eq!(sources.addr_of("flag.s", 11), Ok(Addr(0x402)));
+        assert_eq!(sources.addr_of("flag.s", 1), Ok(Addr(0x400)));
+        assert!(sources.addr_of("flag.s", 12).is_err());
+        assert_eq!(sources.addr_of("macros.s", 7), Ok(Addr(0x403)));
+    }
+
+    #[test]
+    fn test_top_of_memory() {
+        let vectors = "version\tmajor=2,minor=0
+file\tid=0,name=\"vectors.s\",size=100,mtime=0x0,mod=0
+seg\tid=0,name=\"VECTORS\",start=0x00FFFA,size=0x0006,addrsize=absolute,type=ro
+span\tid=0,seg=0,start=0,size=6
+line\tid=0,file=0,line=3,span=0
+";
+        let mut sources = Sources::new();
+        assert_eq!(sources.parse_dbgfile(vectors, Path::new(".")), Ok(1));
+        let line = Some(SourceLine { file: 0, line: 3 });
+        assert_eq!(sources.line_at(Addr(0xFFFA)), line);
+        assert_eq!(sources.line_at(Addr(0xFFFF)), line);
+        assert_eq!(sources.line_at(Addr(0x0000)), None);
+
+        let past = vectors.replace("size=6", "size=7");
+        let mut sources = Sources::new();
+        assert!(sources.parse_dbgfile(&past, Path::new(".")).is_err());
+    }
+}
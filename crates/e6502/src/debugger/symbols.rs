@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use super::dbginfo;
+use crate::types::Addr;
+
+/// Addresses further than this past a label are not shown as `label+offset`
+const MAX_OFFSET: u16 = 0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A location in code or data
+    Label,
+    /// A constant, e.g. `XAML = $24`
+    Equate,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: Addr,
+    pub kind: SymbolKind,
+}
+
+/// Symbol table loaded from ld65 debug info or VICE label files
+#[derive(Default)]
+pub struct Symbols {
+    symbols: Vec<Symbol>,
+    by_name: HashMap<String, usize>,
+    by_addr: BTreeMap<u16, Vec<usize>>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Add symbol `name`, unless there is one by that name already
+    ///
+    /// Loading the same symbol twice, e.g. from a debug info and a label
+    /// file, is fine. A name already taken at another address is a warning,
+    /// and the first one stays.
+    pub fn insert(&mut self, name: &str, addr: Addr, kind: SymbolKind) {
+        if let Some(&idx) = self.by_name.get(name) {
+            let first = self.symbols[idx].addr;
+            if first != addr {
+                eprintln!("WARNING: {name} at {addr} is already defined at {first}");
+            }
+            return;
+        }
+
+        let idx = self.symbols.len();
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            addr,
+            kind,
+        });
+        self.by_name.insert(name.to_string(), idx);
+        self.by_addr.entry(addr.0).or_default().push(idx);
+    }
+
+    /// Load the symbols of an `ld65 --dbgfile` file
+    pub fn load_dbgfile(&mut self, path: impl AsRef<Path>) -> Result<usize, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.parse_dbgfile(&text)
+    }
+
+    /// Load a VICE label file, as written by `ld65 -Ln`
+    pub fn load_labels(&mut self, path: impl AsRef<Path>) -> Result<usize, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.parse_labels(&text)
+    }
+
+    /// Add the symbols in `text`, the contents of an `ld65 --dbgfile` file
+    ///
+    /// Cheap locals (`@name`) are named `label@name` after the label they
+    /// belong to, as the assembler does.
+    pub fn parse_dbgfile(&mut self, text: &str) -> Result<usize, String> {
+        let before = self.symbols.len();
+        let records = dbginfo::parse(text)?;
+        let names: HashMap<_, _> = records
+            .iter()
+            .filter(|r| r.kind == "sym")
+            .filter_map(|r| Some((r.int("id")?, r.get("name")?)))
+            .collect();
+
+        for record in records.iter().filter(|r| r.kind == "sym") {
+            // Imports carry no value of their own, the export defines it
+            let (Some(name), Some(val)) = (record.get("name"), record.int("val")) else {
+                continue;
+            };
+            let parent = record.int("parent").and_then(|id| names.get(&id));
+            let name = match parent {
+                Some(parent) if name.starts_with('@') => format!("{parent}{name}"),
+                _ => name.to_string(),
+            };
+            let kind = match record.get("type") {
+                Some("lab") => SymbolKind::Label,
+                _ => SymbolKind::Equate,
+            };
+            self.insert(&name, Addr(val as u16), kind);
+        }
+
+        Ok(self.symbols.len() - before)
+    }
+
+    pub fn parse_labels(&mut self, text: &str) -> Result<usize, String> {
+        let before = self.symbols.len();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let ["al", addr, name] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(format!("line {}: expected `al <addr> .<label>`", n + 1));
+            };
+            let addr = addr.strip_prefix("C:").unwrap_or(addr);
+            let addr = u32::from_str_radix(addr, 16).map_err(|e| format!("line {}: {e}", n + 1))?;
+            let name = name.strip_prefix('.').unwrap_or(name);
+
+            self.insert(name, Addr(addr as u16), SymbolKind::Label);
+        }
+
+        Ok(self.symbols.len() - before)
+    }
+
+    /// Look up the address of symbol `name`
+    pub fn get(&self, name: &str) -> Option<Addr> {
+        self.by_name.get(name).map(|&idx| self.symbols[idx].addr)
+    }
+
+    /// The name of a symbol at exactly `addr`, labels preferred over equates
+    pub fn at(&self, addr: Addr) -> Option<&str> {
+        let indices = self.by_addr.get(&addr.0)?;
+        indices
+            .iter()
+            .map(|&idx| &self.symbols[idx])
+            .min_by_key(|sym| sym.kind != SymbolKind::Label)
+            .map(|sym| sym.name.as_str())
+    }
+
+    /// Describe `addr` as `label` or `label+offset` relative to the closest label before it
+    pub fn resolve(&self, addr: Addr) -> Option<String> {
+        if let Some(name) = self.at(addr) {
+            return Some(name.to_string());
+        }
+
+        let (base, sym) = self
+            .by_addr
+            .range(addr.0.saturating_sub(MAX_OFFSET)..addr.0)
+            .rev()
+            .find_map(|(base, indices)| {
+                indices
+                    .iter()
+                    .map(|&idx| &self.symbols[idx])
+                    .find(|sym| sym.kind == SymbolKind::Label)
+                    .map(|sym| (*base, sym))
+            })?;
+
+        Some(format!("{}+{}", sym.name, addr.0 - base))
+    }
+
+    /// Parse an address given as a number, a symbol, or `symbol+offset`/`symbol-offset`
+    pub fn parse_addr(&self, value: &str) -> Result<Addr, String> {
+        if let Ok(addr) = Addr::try_from(value) {
+            return Ok(addr);
+        }
+
+        if let Some(addr) = self.get(value) {
+            return Ok(addr);
+        }
+
+        if let Some(idx) = value.rfind(['+', '-']).filter(|&idx| idx > 0) {
+            let (name, offset) = value.split_at(idx);
+            if let Some(addr) = self.get(name) {
+                let delta = Addr::try_from(&offset[1..])?;
+                return Ok(if offset.starts_with('+') {
+                    Addr(addr.0.wrapping_add(delta.0))
+                } else {
+                    Addr(addr.0.wrapping_sub(delta.0))
+                });
+            }
+        }
+
+        Err(format!("Invalid address or unknown symbol: {value}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DBGFILE: &str = "version\tmajor=2,minor=0
+info\tcsym=0,file=1,lib=0,line=3,mod=1,scope=1,seg=2,span=3,sym=3,type=1
+file\tid=0,name=\"wozmon.s\",size=5340,mtime=0x65A1B2C3,mod=0
+seg\tid=0,name=\"WOZMON\",start=0x00FF00,size=0x00FA,addrsize=absolute,type=ro
+sym\tid=0,name=\"XAML\",addrsize=zeropage,scope=0,def=0,val=0x24,type=equ
+sym\tid=1,name=\"RESET\",addrsize=absolute,scope=0,def=1,val=0xFF00,seg=0,type=lab
+sym\tid=2,name=\"NOTCR\",addrsize=absolute,scope=0,def=2,val=0xFF04,seg=0,type=lab
+";
+
+    #[test]
+    fn test_dbgfile() {
+        let mut symbols = Symbols::new();
+        assert_eq!(symbols.parse_dbgfile(DBGFILE), Ok(3));
+
+        assert_eq!(symbols.get("RESET"), Some(Addr(0xff00)));
+        assert_eq!(symbols.at(Addr(0x24)), Some("XAML"));
+        assert_eq!(symbols.resolve(Addr(0xff02)).as_deref(), Some("RESET+2"));
+        assert_eq!(symbols.resolve(Addr(0xff04)).as_deref(), Some("NOTCR"));
+        // Equates are only ever matched exactly
+        assert_eq!(symbols.resolve(Addr(0x25)), None);
+    }
+
+    #[test]
+    fn test_dbgfile_locals() {
+        let dbgfile = "version\tmajor=2,minor=0
+sym\tid=0,name=\"RESET\",addrsize=absolute,scope=0,def=0,val=0xFF00,seg=0,type=lab
+sym\tid=1,name=\"@loop\",addrsize=absolute,scope=0,def=1,val=0xFF02,seg=0,type=lab,parent=0
+sym\tid=2,name=\"NOTCR\",addrsize=absolute,scope=0,def=2,val=0xFF04,seg=0,type=lab
+sym\tid=3,name=\"@loop\",addrsize=absolute,scope=0,def=3,val=0xFF06,seg=0,type=lab,parent=2
+";
+        let mut symbols = Symbols::new();
+        assert_eq!(symbols.parse_dbgfile(dbgfile), Ok(4));
+
+        assert_eq!(symbols.get("RESET@loop"), Some(Addr(0xff02)));
+        assert_eq!(symbols.get("NOTCR@loop"), Some(Addr(0xff06)));
+        assert_eq!(symbols.get("@loop"), None);
+        assert_eq!(
+            symbols.resolve(Addr(0xff07)).as_deref(),
+            Some("NOTCR@loop+1")
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut symbols = Symbols::new();
+        let labels = "al 00FF00 .RESET\nal C:ff1f .GETLINE\n";
+        assert_eq!(symbols.parse_labels(labels), Ok(2));
+
+        assert_eq!(symbols.parse_addr("GETLINE"), Ok(Addr(0xff1f)));
+        assert_eq!(symbols.parse_addr("RESET+0x10"), Ok(Addr(0xff10)));
+        assert_eq!(symbols.parse_addr("GETLINE-1"), Ok(Addr(0xff1e)));
+        assert_eq!(symbols.parse_addr("$FF00"), Ok(Addr(0xff00)));
+        assert!(symbols.parse_addr("NOPE").is_err());
+    }
+}
@@ -0,0 +1,795 @@
+//! VICE binary monitor protocol server, so frontends made for `x64sc
+//! -binarymonitor` can drive e6502
+//!
+//! Only the main CPU memory space exists, with a single bank. Commands are
+//! answered while the CPU is stopped; a command arriving while it runs stops
+//! it first, as in VICE. Unlike VICE the CPU starts out stopped, so
+//! checkpoints can be set before anything runs.
+
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Write};
+use std::sync::mpsc::{self, TryRecvError};
+
+use either::Either;
+
+use super::expr::Context;
+use super::Debugger;
+use crate::hardware::cpu::Register;
+use crate::types::{Addr, Byte};
+
+const STX: u8 = 0x02;
+const API_VERSION: u8 = 0x02;
+
+/// Request ID of events
+const EVENT: u32 = 0xffff_ffff;
+
+/// Instructions executed between checks for new commands while running
+const BATCH: usize = 1000;
+
+/// Command and response types
+const MEMORY_GET: u8 = 0x01;
+const MEMORY_SET: u8 = 0x02;
+const CHECKPOINT_INFO: u8 = 0x11;
+const CHECKPOINT_SET: u8 = 0x12;
+const CHECKPOINT_DELETE: u8 = 0x13;
+const CHECKPOINT_LIST: u8 = 0x14;
+const CHECKPOINT_TOGGLE: u8 = 0x15;
+const REGISTERS_GET: u8 = 0x31;
+const REGISTERS_SET: u8 = 0x32;
+const STOPPED: u8 = 0x62;
+const RESUMED: u8 = 0x63;
+const ADVANCE_INSTRUCTIONS: u8 = 0x71;
+const EXECUTE_UNTIL_RETURN: u8 = 0x73;
+const PING: u8 = 0x81;
+const BANKS_AVAILABLE: u8 = 0x82;
+const REGISTERS_AVAILABLE: u8 = 0x83;
+const VICE_INFO: u8 = 0x85;
+const EXIT: u8 = 0xaa;
+const QUIT: u8 = 0xbb;
+const RESET: u8 = 0xcc;
+
+/// Error codes
+const OK: u8 = 0x00;
+const OBJECT_MISSING: u8 = 0x01;
+const INVALID_MEMSPACE: u8 = 0x02;
+const INVALID_LENGTH: u8 = 0x80;
+const INVALID_PARAMETER: u8 = 0x81;
+const INVALID_COMMAND: u8 = 0x83;
+
+/// The main CPU memory space
+const MAIN_MEMORY: u8 = 0x00;
+
+/// CPU operations a checkpoint triggers on
+const LOAD: u8 = 0x01;
+const STORE: u8 = 0x02;
+const EXEC: u8 = 0x04;
+
+/// Registers by VICE register ID, with their size in bits
+const REGISTERS: [(u8, Register, &str, u8); 6] = [
+    (0x00, Register::A, "A", 8),
+    (0x01, Register::X, "X", 8),
+    (0x02, Register::Y, "Y", 8),
+    (0x03, Register::PC, "PC", 16),
+    (0x04, Register::SP, "SP", 8),
+    (0x05, Register::PS, "FL", 8),
+];
+
+/// Version reported by the VICE info command, the protocol is that of VICE 3.7
+const VICE_VERSION: [u8; 4] = [3, 7, 0, 0];
+
+struct Command {
+    request_id: u32,
+    kind: u8,
+    body: Vec<u8>,
+}
+
+/// Read one command, `None` at the end of the stream
+fn read_command(reader: &mut impl Read) -> Result<Option<Command>, String> {
+    let mut header = [0; 11];
+    match reader.read_exact(&mut header) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    if header[0] != STX {
+        return Err(format!("Expected STX, got {:#04X}", header[0]));
+    }
+
+    let len = u32::from_le_bytes(header[2..6].try_into().unwrap()) as usize;
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    Ok(Some(Command {
+        request_id: u32::from_le_bytes(header[6..10].try_into().unwrap()),
+        kind: header[10],
+        body,
+    }))
+}
+
+/// Encode one response or event
+fn response(kind: u8, error: u8, request_id: u32, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![STX, API_VERSION];
+    out.extend((body.len() as u32).to_le_bytes());
+    out.extend([kind, error]);
+    out.extend(request_id.to_le_bytes());
+    out.extend_from_slice(body);
+    out
+}
+
+/// Reads the fields of a command body, failing with `INVALID_LENGTH` when
+/// it is too short
+struct Body<'a>(&'a [u8]);
+
+impl Body<'_> {
+    fn bytes(&mut self, n: usize) -> Result<&[u8], u8> {
+        if self.0.len() < n {
+            return Err(INVALID_LENGTH);
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, u8> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, u8> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn memspace(&mut self) -> Result<(), u8> {
+        match self.u8()? {
+            MAIN_MEMORY => Ok(()),
+            _ => Err(INVALID_MEMSPACE),
+        }
+    }
+}
+
+struct Checkpoint {
+    start: Addr,
+    end: Addr,
+    stop: bool,
+    enabled: bool,
+    op: u8,
+    temporary: bool,
+    hits: u32,
+}
+
+impl Checkpoint {
+    fn contains(&self, addr: Addr) -> bool {
+        self.start.0 <= addr.0 && addr.0 <= self.end.0
+    }
+
+    fn info(&self, number: u32, hit: bool) -> Vec<u8> {
+        let mut body = number.to_le_bytes().to_vec();
+        body.push(hit as u8);
+        body.extend(self.start.0.to_le_bytes());
+        body.extend(self.end.0.to_le_bytes());
+        body.extend([
+            self.stop as u8,
+            self.enabled as u8,
+            self.op,
+            self.temporary as u8,
+        ]);
+        body.extend(self.hits.to_le_bytes());
+        // Ignore count, and no condition
+        body.extend(0u32.to_le_bytes());
+        body.extend([0, MAIN_MEMORY]);
+        body
+    }
+}
+
+/// How far the CPU runs
+#[derive(Clone, Copy)]
+enum Until {
+    /// A checkpoint, or the next command
+    Stopped,
+    /// This many more instructions, not counting those in subroutines
+    /// called below the given depth
+    Advance(u16, Option<usize>),
+    /// The return from the subroutine at this depth
+    Return(usize),
+}
+
+/// Serve one binary monitor session over `debugger`
+pub struct BinaryMonitor<W: Write> {
+    debugger: Debugger,
+    writer: W,
+    running: Option<Until>,
+    checkpoints: BTreeMap<u32, Checkpoint>,
+    next_checkpoint: u32,
+    /// Responses and events waiting to be written
+    pending: Vec<u8>,
+}
+
+impl<W: Write> BinaryMonitor<W> {
+    pub fn new(debugger: Debugger, writer: W) -> Self {
+        Self {
+            debugger,
+            writer,
+            running: None,
+            checkpoints: BTreeMap::new(),
+            next_checkpoint: 1,
+            pending: vec![],
+        }
+    }
+
+    /// Handle commands read from `reader` until the client quits or disconnects
+    pub fn serve(mut self, reader: impl Read + Send + 'static) -> Result<(), String> {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            while let Ok(Some(cmd)) = read_command(&mut reader) {
+                if tx.send(cmd).is_err() {
+                    break;
+                }
+            }
+        });
+
+        loop {
+            let cmd = if self.running.is_some() {
+                self.run_batch();
+                self.flush()?;
+                match rx.try_recv() {
+                    Ok(cmd) => cmd,
+                    Err(TryRecvError::Empty) => continue,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match rx.recv() {
+                    Ok(cmd) => cmd,
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            if self.running.is_some() {
+                self.stopped();
+            }
+            let quit = cmd.kind == QUIT;
+            if let Err(error) = self.handle(&cmd) {
+                self.respond(cmd.kind, error, cmd.request_id, &[]);
+            }
+            self.flush()?;
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        self.writer
+            .write_all(&pending)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| e.to_string())
+    }
+
+    fn respond(&mut self, kind: u8, error: u8, request_id: u32, body: &[u8]) {
+        self.pending.extend(response(kind, error, request_id, body));
+    }
+
+    fn event(&mut self, kind: u8, body: &[u8]) {
+        self.respond(kind, OK, EVENT, body);
+    }
+
+    fn pc(&self) -> Addr {
+        self.debugger.pc()
+    }
+
+    fn resume(&mut self, until: Until) {
+        self.running = Some(until);
+        self.event(RESUMED, &self.pc().0.to_le_bytes());
+    }
+
+    /// Stop running, with the registers and the stopped event
+    fn stopped(&mut self) {
+        self.running = None;
+        let registers = self.registers();
+        self.event(REGISTERS_GET, &registers);
+        self.event(STOPPED, &self.pc().0.to_le_bytes());
+    }
+
+    fn run_batch(&mut self) {
+        for _ in 0..BATCH {
+            let pc = self.pc();
+            if !self.debugger.step() {
+                if self.checkpoint_hit() || self.debugger.error.take().is_some() {
+                    self.stopped();
+                    return;
+                }
+                if self.pc() == pc {
+                    // Nothing ran at a checkpoint that doesn't stop
+                    continue;
+                }
+            }
+
+            let frames = self.debugger.frames.len();
+            let done = match self.running {
+                Some(Until::Advance(n, over)) => {
+                    // Instructions inside subroutines being stepped over don't count
+                    if over.is_some_and(|over| frames > over) {
+                        false
+                    } else {
+                        self.running = Some(Until::Advance(n - 1, over));
+                        n == 1
+                    }
+                }
+                Some(Until::Return(depth)) => frames < depth,
+                Some(Until::Stopped) | None => false,
+            };
+            if done {
+                self.stopped();
+                return;
+            }
+        }
+    }
+
+    /// Report the checkpoints behind the last stop, returns whether any of
+    /// them stops the CPU
+    fn checkpoint_hit(&mut self) -> bool {
+        let dbg = &mut self.debugger;
+        if dbg.cpu.is_trapped() {
+            return true;
+        }
+
+        let (addr, op) = if let Some(write) = dbg.watch_hit.take() {
+            (write.addr, STORE)
+        } else if let Some(addr) = dbg.cpu.read_hit() {
+            (addr, LOAD)
+        } else {
+            (dbg.pc(), EXEC)
+        };
+
+        let hits: Vec<u32> = self
+            .checkpoints
+            .iter()
+            .filter(|(_, cp)| cp.enabled && cp.op & op != 0 && cp.contains(addr))
+            .map(|(n, _)| *n)
+            .collect();
+
+        let mut stop = hits.is_empty();
+        for n in hits {
+            let cp = self.checkpoints.get_mut(&n).unwrap();
+            cp.hits += 1;
+            stop |= cp.stop;
+            let info = cp.info(n, true);
+            self.event(CHECKPOINT_INFO, &info);
+            if self.checkpoints[&n].temporary {
+                self.delete_checkpoint(n);
+            }
+        }
+        stop
+    }
+
+    fn handle(&mut self, cmd: &Command) -> Result<(), u8> {
+        let mut body = Body(&cmd.body);
+        let id = cmd.request_id;
+        match cmd.kind {
+            MEMORY_GET => {
+                // Reading never has side effects here
+                let (_side_effects, start, end) = (body.u8()?, body.u16()?, body.u16()?);
+                body.memspace()?;
+                self.bank(body.u16()?)?;
+                if end < start {
+                    return Err(INVALID_PARAMETER);
+                }
+
+                let len = (end - start) as usize + 1;
+                // The length wraps to 0 for the whole address space, as in VICE
+                let mut data = (len as u16).to_le_bytes().to_vec();
+                for i in 0..len {
+                    let byte = Context::peek(&self.debugger, Addr(start) + i);
+                    data.push(byte.unwrap_or(Byte(0)).0);
+                }
+                self.respond(MEMORY_GET, OK, id, &data);
+            }
+            MEMORY_SET => {
+                let (_side_effects, start, end) = (body.u8()?, body.u16()?, body.u16()?);
+                body.memspace()?;
+                self.bank(body.u16()?)?;
+                if end < start {
+                    return Err(INVALID_PARAMETER);
+                }
+                let data = body.bytes((end - start) as usize + 1)?;
+                self.debugger
+                    .write_mem(Addr(start), data)
+                    .map_err(|_| INVALID_PARAMETER)?;
+                self.respond(MEMORY_SET, OK, id, &[]);
+            }
+            CHECKPOINT_INFO => {
+                let n = body.u32()?;
+                let cp = self.checkpoints.get(&n).ok_or(OBJECT_MISSING)?;
+                let info = cp.info(n, false);
+                self.respond(CHECKPOINT_INFO, OK, id, &info);
+            }
+            CHECKPOINT_SET => {
+                let (start, end) = (Addr(body.u16()?), Addr(body.u16()?));
+                let (stop, enabled, op, temporary) =
+                    (body.u8()?, body.u8()?, body.u8()?, body.u8()?);
+                if !body.0.is_empty() {
+                    body.memspace()?;
+                }
+                if end.0 < start.0 || op == 0 || op & !(LOAD | STORE | EXEC) != 0 {
+                    return Err(INVALID_PARAMETER);
+                }
+
+                let n = self.next_checkpoint;
+                self.next_checkpoint += 1;
+                let cp = Checkpoint {
+                    start,
+                    end,
+                    stop: stop != 0,
+                    enabled: enabled != 0,
+                    op,
+                    temporary: temporary != 0,
+                    hits: 0,
+                };
+                let info = cp.info(n, false);
+                self.checkpoints.insert(n, cp);
+                self.sync_checkpoints(start, end);
+                self.respond(CHECKPOINT_INFO, OK, id, &info);
+            }
+            CHECKPOINT_DELETE => {
+                let n = body.u32()?;
+                if !self.checkpoints.contains_key(&n) {
+                    return Err(OBJECT_MISSING);
+                }
+                self.delete_checkpoint(n);
+                self.respond(CHECKPOINT_DELETE, OK, id, &[]);
+            }
+            CHECKPOINT_LIST => {
+                let infos: Vec<Vec<u8>> = self
+                    .checkpoints
+                    .iter()
+                    .map(|(n, cp)| cp.info(*n, false))
+                    .collect();
+                for info in &infos {
+                    self.respond(CHECKPOINT_INFO, OK, id, info);
+                }
+                self.respond(CHECKPOINT_LIST, OK, id, &(infos.len() as u32).to_le_bytes());
+            }
+            CHECKPOINT_TOGGLE => {
+                let (n, enabled) = (body.u32()?, body.u8()?);
+                let cp = self.checkpoints.get_mut(&n).ok_or(OBJECT_MISSING)?;
+                cp.enabled = enabled != 0;
+                let (start, end) = (cp.start, cp.end);
+                self.sync_checkpoints(start, end);
+                self.respond(CHECKPOINT_TOGGLE, OK, id, &[]);
+            }
+            REGISTERS_GET => {
+                body.memspace()?;
+                let registers = self.registers();
+                self.respond(REGISTERS_GET, OK, id, &registers);
+            }
+            REGISTERS_SET => {
+                body.memspace()?;
+                let count = body.u16()?;
+                for _ in 0..count {
+                    let size = body.u8()? as usize;
+                    let mut item = Body(body.bytes(size)?);
+                    let (reg_id, val) = (item.u8()?, item.u16()?);
+                    let (_, reg, _, bits) = REGISTERS
+                        .into_iter()
+                        .find(|(id, ..)| *id == reg_id)
+                        .ok_or(INVALID_PARAMETER)?;
+                    let val = match bits {
+                        16 => Either::Right(Addr(val)),
+                        _ => Either::Left(Byte(val as u8)),
+                    };
+                    self.debugger.set_reg(reg, val);
+                }
+                let registers = self.registers();
+                self.respond(REGISTERS_GET, OK, id, &registers);
+            }
+            ADVANCE_INSTRUCTIONS => {
+                let (over, count) = (body.u8()? != 0, body.u16()?);
+                self.respond(ADVANCE_INSTRUCTIONS, OK, id, &[]);
+                if count > 0 {
+                    let over = over.then_some(self.debugger.frames.len());
+                    self.resume(Until::Advance(count, over));
+                }
+            }
+            EXECUTE_UNTIL_RETURN => {
+                self.respond(EXECUTE_UNTIL_RETURN, OK, id, &[]);
+                let depth = self.debugger.frames.len();
+                self.resume(Until::Return(depth));
+            }
+            PING => self.respond(PING, OK, id, &[]),
+            BANKS_AVAILABLE => {
+                let mut data = 1u16.to_le_bytes().to_vec();
+                data.extend([6, 0, 0, 3]);
+                data.extend(b"cpu");
+                self.respond(BANKS_AVAILABLE, OK, id, &data);
+            }
+            REGISTERS_AVAILABLE => {
+                body.memspace()?;
+                let mut data = (REGISTERS.len() as u16).to_le_bytes().to_vec();
+                for (reg_id, _, name, bits) in REGISTERS {
+                    data.extend([3 + name.len() as u8, reg_id, bits, name.len() as u8]);
+                    data.extend(name.bytes());
+                }
+                self.respond(REGISTERS_AVAILABLE, OK, id, &data);
+            }
+            VICE_INFO => {
+                let mut data = vec![4];
+                data.extend(VICE_VERSION);
+                data.extend([4, 0, 0, 0, 0]);
+                self.respond(VICE_INFO, OK, id, &data);
+            }
+            EXIT => {
+                self.respond(EXIT, OK, id, &[]);
+                self.resume(Until::Stopped);
+            }
+            QUIT => self.respond(QUIT, OK, id, &[]),
+            RESET => {
+                // Soft or hard, drives have no reset here
+                match body.u8()? {
+                    0 => self.debugger.reset(false),
+                    1 => self.debugger.reset(true),
+                    _ => return Err(INVALID_PARAMETER),
+                }
+                self.respond(RESET, OK, id, &[]);
+            }
+            _ => return Err(INVALID_COMMAND),
+        }
+        Ok(())
+    }
+
+    fn bank(&self, bank: u16) -> Result<(), u8> {
+        match bank {
+            0 => Ok(()),
+            _ => Err(INVALID_PARAMETER),
+        }
+    }
+
+    fn delete_checkpoint(&mut self, n: u32) {
+        if let Some(cp) = self.checkpoints.remove(&n) {
+            self.sync_checkpoints(cp.start, cp.end);
+        }
+    }
+
+    /// Set the CPU's breakpoints and watchpoints in `start..=end` from the
+    /// enabled checkpoints there
+    fn sync_checkpoints(&mut self, start: Addr, end: Addr) {
+        for addr in (start.0..=end.0).map(Addr) {
+            let op = self
+                .checkpoints
+                .values()
+                .filter(|cp| cp.enabled && cp.contains(addr))
+                .fold(0, |op, cp| op | cp.op);
+
+            let cpu = &mut self.debugger.cpu;
+            if op & EXEC != 0 {
+                cpu.breakpoint(addr);
+            } else {
+                cpu.remove_breakpoint(addr);
+            }
+            if op & STORE != 0 {
+                cpu.watchpoint(addr);
+            } else {
+                cpu.remove_watchpoint(addr);
+            }
+            if op & LOAD != 0 {
+                cpu.read_watchpoint(addr);
+            } else {
+                cpu.remove_read_watchpoint(addr);
+            }
+        }
+    }
+
+    /// Body of a registers response
+    fn registers(&self) -> Vec<u8> {
+        let mut data = (REGISTERS.len() as u16).to_le_bytes().to_vec();
+        for (reg_id, reg, ..) in REGISTERS {
+            let val = match self.debugger.cpu.get_reg(reg) {
+                Either::Left(val) => val.0 as u16,
+                Either::Right(addr) => addr.0,
+            };
+            data.extend([3, reg_id]);
+            data.extend(val.to_le_bytes());
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm6502;
+    use std::net::{TcpListener, TcpStream};
+
+    struct Response {
+        kind: u8,
+        error: u8,
+        request_id: u32,
+        body: Vec<u8>,
+    }
+
+    /// Just enough of a binary monitor client to drive the server
+    struct Client {
+        stream: TcpStream,
+        next_id: u32,
+    }
+
+    impl Client {
+        fn read(&mut self) -> Response {
+            let mut header = [0; 12];
+            self.stream.read_exact(&mut header).unwrap();
+            assert_eq!(header[..2], [STX, API_VERSION]);
+            let len = u32::from_le_bytes(header[2..6].try_into().unwrap());
+            let mut body = vec![0; len as usize];
+            self.stream.read_exact(&mut body).unwrap();
+            Response {
+                kind: header[6],
+                error: header[7],
+                request_id: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+                body,
+            }
+        }
+
+        /// Send a command, returns its responses and the events before and
+        /// after them, up to the last one of `until`
+        fn command(&mut self, kind: u8, body: &[u8], until: u8) -> Vec<Response> {
+            self.next_id += 1;
+            let mut msg = vec![STX, API_VERSION];
+            msg.extend((body.len() as u32).to_le_bytes());
+            msg.extend(self.next_id.to_le_bytes());
+            msg.push(kind);
+            msg.extend_from_slice(body);
+            self.stream.write_all(&msg).unwrap();
+
+            let mut responses = vec![];
+            loop {
+                let response = self.read();
+                let done = response.kind == until;
+                responses.push(response);
+                if done {
+                    return responses;
+                }
+            }
+        }
+
+        fn pc(&mut self) -> u16 {
+            let registers = self.command(REGISTERS_GET, &[MAIN_MEMORY], REGISTERS_GET);
+            let body = &registers[0].body;
+            u16::from_le_bytes([body[2 + 3 * 4 + 2], body[2 + 3 * 4 + 3]])
+        }
+    }
+
+    fn checkpoint(start: u16, end: u16, op: u8) -> Vec<u8> {
+        let mut body = start.to_le_bytes().to_vec();
+        body.extend(end.to_le_bytes());
+        body.extend([1, 1, op, 0, MAIN_MEMORY]);
+        body
+    }
+
+    #[test]
+    fn test_session() {
+        // RESET goes back to the start
+        let (cpu, _) = asm6502!(
+            "
+loop:   inx
+        stx $10
+        lda $20
+        jmp loop
+        .org $FFFC
+        .word loop"
+        )
+        .prepare();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let monitor =
+                BinaryMonitor::new(Debugger::new(cpu, false), stream.try_clone().unwrap());
+            monitor.serve(stream)
+        });
+
+        let mut client = Client {
+            stream: TcpStream::connect(("127.0.0.1", port)).unwrap(),
+            next_id: 0,
+        };
+
+        let ping = client.command(PING, &[], PING);
+        assert_eq!((ping[0].error, ping[0].request_id), (OK, 1));
+
+        let available = client.command(REGISTERS_AVAILABLE, &[MAIN_MEMORY], REGISTERS_AVAILABLE);
+        assert_eq!(available[0].body[..2], [6, 0]);
+
+        // A, X, Y, PC, SP, FL
+        let registers = client.command(REGISTERS_GET, &[MAIN_MEMORY], REGISTERS_GET);
+        #[rustfmt::skip]
+        assert_eq!(
+            registers[0].body,
+            [6, 0, 3, 0x00, 0, 0, 3, 0x01, 0, 0, 3, 0x02, 0, 0,
+             3, 0x03, 0x00, 0x04, 3, 0x04, 0xFD, 0, 3, 0x05, 0x24, 0]
+        );
+
+        let set = client.command(
+            REGISTERS_SET,
+            &[MAIN_MEMORY, 1, 0, 3, 0x00, 0x42, 0],
+            REGISTERS_GET,
+        );
+        assert_eq!(set[0].body[4], 0x42);
+
+        let memory = client.command(
+            MEMORY_SET,
+            &[0, 0x10, 0, 0x11, 0, MAIN_MEMORY, 0, 0, 0xAA, 0xBB],
+            MEMORY_SET,
+        );
+        assert_eq!(memory[0].error, OK);
+        let memory = client.command(
+            MEMORY_GET,
+            &[0, 0x10, 0, 0x11, 0, MAIN_MEMORY, 0, 0],
+            MEMORY_GET,
+        );
+        assert_eq!(memory[0].body, [2, 0, 0xAA, 0xBB]);
+        let memory = client.command(MEMORY_GET, &[0, 0x10, 0, 0x11, 0, 1, 0, 0], MEMORY_GET);
+        assert_eq!(memory[0].error, INVALID_MEMSPACE);
+
+        let set = client.command(
+            CHECKPOINT_SET,
+            &checkpoint(0x403, 0x403, EXEC),
+            CHECKPOINT_INFO,
+        );
+        assert_eq!(set[0].body[..4], 1u32.to_le_bytes());
+
+        let run = client.command(EXIT, &[], STOPPED);
+        let kinds: Vec<u8> = run.iter().map(|r| r.kind).collect();
+        assert_eq!(
+            kinds,
+            [EXIT, RESUMED, CHECKPOINT_INFO, REGISTERS_GET, STOPPED]
+        );
+        // Currently hit, and hit once
+        assert_eq!(run[2].body[4], 1);
+        assert_eq!(run[2].body[13..17], 1u32.to_le_bytes());
+        assert_eq!(run[4].request_id, EVENT);
+        assert_eq!(run[4].body, [0x03, 0x04]);
+
+        let delete = client.command(CHECKPOINT_DELETE, &1u32.to_le_bytes(), CHECKPOINT_DELETE);
+        assert_eq!(delete[0].error, OK);
+        let delete = client.command(CHECKPOINT_DELETE, &1u32.to_le_bytes(), CHECKPOINT_DELETE);
+        assert_eq!(delete[0].error, OBJECT_MISSING);
+
+        client.command(ADVANCE_INSTRUCTIONS, &[0, 2, 0], STOPPED);
+        assert_eq!(client.pc(), 0x400);
+
+        client.command(
+            CHECKPOINT_SET,
+            &checkpoint(0x10, 0x10, STORE),
+            CHECKPOINT_INFO,
+        );
+        client.command(EXIT, &[], STOPPED);
+        assert_eq!(client.pc(), 0x403);
+
+        let list = client.command(CHECKPOINT_LIST, &[], CHECKPOINT_LIST);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[1].body, 1u32.to_le_bytes());
+
+        // Runs until the next command
+        let toggle = client.command(CHECKPOINT_TOGGLE, &[2, 0, 0, 0, 0], CHECKPOINT_TOGGLE);
+        assert_eq!(toggle[0].error, OK);
+        client.command(EXIT, &[], RESUMED);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let stop = client.command(PING, &[], PING);
+        let kinds: Vec<u8> = stop.iter().map(|r| r.kind).collect();
+        assert_eq!(kinds, [REGISTERS_GET, STOPPED, PING]);
+
+        client.command(RESET, &[0], RESET);
+        assert_eq!(client.pc(), 0x400);
+        // RAM, with the reset vector, is cleared by powering off and on
+        client.command(RESET, &[1], RESET);
+        assert_eq!(client.pc(), 0x0000);
+
+        client.command(QUIT, &[], QUIT);
+        server.join().unwrap().unwrap();
+    }
+}
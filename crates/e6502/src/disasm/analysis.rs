@@ -0,0 +1,302 @@
+//! Telling code from data in a ROM image by following the control flow
+//!
+//! Decoding starts at the reset, NMI and IRQ vectors and any other entry
+//! points given, and follows jumps, calls and both ways of every branch. It
+//! stops at `RTS`, `RTI`, `BRK` and `JMP (addr)`, whose targets aren't known
+//! without running the code. Everything never reached is taken to be data.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::{disassemble, Disassembly};
+use crate::hardware::cpu::instructions::{AddressingMode, Instruction};
+use crate::types::{Addr, Byte};
+
+/// The vectors, in the order their targets are named
+const VECTORS: [(u16, &str); 3] = [(0xFFFC, "reset"), (0xFFFA, "nmi"), (0xFFFE, "irq")];
+
+/// Data bytes per `.byte` line
+const BYTES_PER_LINE: usize = 8;
+
+pub struct Analysis {
+    base: u32,
+    image: Vec<u8>,
+    /// The instructions found, by address
+    code: BTreeMap<u32, Disassembly>,
+    /// Whether each byte of the image belongs to an instruction
+    covered: Vec<bool>,
+    labels: BTreeMap<u32, String>,
+}
+
+/// Find the code in `image`, loaded at `base`, reachable from the vectors
+/// and `entries`
+pub fn analyze(image: &[u8], base: Addr, entries: &[Addr]) -> Result<Analysis, String> {
+    let base = base.0 as u32;
+    if image.is_empty() {
+        return Err("The image is empty".to_string());
+    }
+    if base + image.len() as u32 > 0x10000 {
+        return Err(format!(
+            "{} bytes at ${base:04X} go past the end of memory",
+            image.len()
+        ));
+    }
+
+    let mut analysis = Analysis {
+        base,
+        image: image.to_vec(),
+        code: BTreeMap::new(),
+        covered: vec![false; image.len()],
+        labels: BTreeMap::new(),
+    };
+
+    let mut work = vec![];
+    for (vector, name) in VECTORS {
+        if let Some(target) = analysis.word(vector as u32) {
+            if analysis.inside(target) && !analysis.labels.contains_key(&target) {
+                analysis.labels.insert(target, name.to_string());
+            }
+            work.push(target);
+        }
+    }
+    for entry in entries.iter().map(|addr| addr.0 as u32) {
+        if analysis.inside(entry) && !analysis.labels.contains_key(&entry) {
+            analysis.labels.insert(entry, format!("L_{entry:04X}"));
+        }
+        work.push(entry);
+    }
+    work.reverse();
+    analysis.traverse(work);
+    analysis.label_references();
+    Ok(analysis)
+}
+
+impl Analysis {
+    fn inside(&self, addr: u32) -> bool {
+        (self.base..self.base + self.image.len() as u32).contains(&addr)
+    }
+
+    fn byte(&self, addr: u32) -> Option<u8> {
+        match self.inside(addr) {
+            true => Some(self.image[(addr - self.base) as usize]),
+            false => None,
+        }
+    }
+
+    /// The little endian word at `addr`, if it's all in the image
+    fn word(&self, addr: u32) -> Option<u32> {
+        Some(self.byte(addr)? as u32 | (self.byte(addr + 1)? as u32) << 8)
+    }
+
+    /// Decode from every address in `work`, and every address the code there
+    /// can go on to
+    fn traverse(&mut self, mut work: Vec<u32>) {
+        while let Some(addr) = work.pop() {
+            if !self.inside(addr) || self.code.contains_key(&addr) {
+                continue;
+            }
+            let ins = disassemble(Addr(addr as u16), |a| {
+                Byte(self.byte(a.0 as u32).unwrap_or(0))
+            });
+            let Some(instruction) = ins.instruction else {
+                continue;
+            };
+            let end = addr + ins.size() as u32;
+            // Running off the image or into the middle of other code
+            if !self.inside(end - 1) || (addr..end).any(|a| self.covered[(a - self.base) as usize])
+            {
+                continue;
+            }
+
+            (addr..end).for_each(|a| self.covered[(a - self.base) as usize] = true);
+            let target = ins.operand().map(|a| a.0 as u32);
+            match (instruction, ins.mode) {
+                (Instruction::RTS | Instruction::RTI | Instruction::BRK, _) => (),
+                (Instruction::JMP, AddressingMode::Indirect) => (),
+                (Instruction::JMP, _) => work.extend(target),
+                (Instruction::JSR, _) | (_, AddressingMode::Relative) => {
+                    work.push(end);
+                    work.extend(target);
+                }
+                _ => work.push(end),
+            }
+            self.code.insert(addr, ins);
+        }
+    }
+
+    /// Whether a label can go at `addr`: at the start of an instruction or on
+    /// a data byte, but not inside an instruction
+    fn can_label(&self, addr: u32) -> bool {
+        self.inside(addr)
+            && (self.code.contains_key(&addr) || !self.covered[(addr - self.base) as usize])
+    }
+
+    /// Name the targets of jumps, calls and branches `L_xxxx` and the data
+    /// read or written with absolute addresses `D_xxxx`
+    fn label_references(&mut self) {
+        let mut targets = BTreeSet::new();
+        for ins in self.code.values() {
+            let Some(addr) = ins.operand().map(|a| a.0 as u32) else {
+                continue;
+            };
+            if ins.size() == 3 || ins.mode == AddressingMode::Relative {
+                targets.insert(addr);
+            }
+        }
+        for addr in targets {
+            if self.labels.contains_key(&addr) || !self.can_label(addr) {
+                continue;
+            }
+            let prefix = match self.code.contains_key(&addr) {
+                true => "L",
+                false => "D",
+            };
+            self.labels.insert(addr, format!("{prefix}_{addr:04X}"));
+        }
+    }
+
+    /// The name of `addr` in operands, taking care that an absolute address
+    /// in the zero page stays absolute
+    fn operand(&self, ins: &Disassembly, addr: Addr) -> Option<String> {
+        let label = self.labels.get(&(addr.0 as u32));
+        let absolute = ins.size() == 3 && ins.mode != AddressingMode::Indirect;
+        if absolute && addr.0 < 0x100 {
+            let name = label.cloned().unwrap_or(format!("${:04X}", addr.0));
+            return Some(format!("a:{name}"));
+        }
+        label.cloned()
+    }
+
+    /// Number of bytes of code found
+    pub fn code_size(&self) -> usize {
+        self.covered.iter().filter(|&&c| c).count()
+    }
+
+    /// ca65 source for the image, which assembles back to the same bytes
+    pub fn source(&self) -> String {
+        let mut out = String::new();
+        let end = self.base + self.image.len() as u32;
+        let code = self.code_size();
+        let data = self.image.len() - code;
+        writeln!(out, "; {code} bytes of code, {data} of data").unwrap();
+        writeln!(out, "        .org ${:04X}", self.base).unwrap();
+
+        let mut addr = self.base;
+        while addr < end {
+            if let Some(label) = self.labels.get(&addr) {
+                writeln!(out, "{label}:").unwrap();
+            }
+
+            if let Some(ins) = self.code.get(&addr) {
+                let text = ins.format(|target| self.operand(ins, target));
+                line(&mut out, &text, addr, &ins.hex());
+                addr += ins.size() as u32;
+                continue;
+            }
+
+            if let Some(vector) = self.vector(addr) {
+                let target = self.word(addr).unwrap();
+                let name = self.labels.get(&target).cloned();
+                let text = format!(".addr {}", name.unwrap_or(format!("${target:04X}")));
+                let hex = format!("{:02X} {:02X}", target & 0xFF, target >> 8);
+                line(&mut out, &text, addr, &format!("{hex}  {vector}"));
+                addr += 2;
+                continue;
+            }
+
+            // Data up to the next label, instruction or vector
+            let mut bytes = vec![];
+            while addr < end && bytes.len() < BYTES_PER_LINE {
+                bytes.push(self.byte(addr).unwrap());
+                addr += 1;
+                if self.labels.contains_key(&addr)
+                    || self.code.contains_key(&addr)
+                    || self.vector(addr).is_some()
+                {
+                    break;
+                }
+            }
+            let text = bytes
+                .iter()
+                .map(|b| format!("${b:02X}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            line(
+                &mut out,
+                &format!(".byte {text}"),
+                addr - bytes.len() as u32,
+                "",
+            );
+        }
+        out
+    }
+
+    /// The vector at `addr`, if it's a whole one of data
+    fn vector(&self, addr: u32) -> Option<&'static str> {
+        let (_, name) = VECTORS.iter().find(|(v, _)| *v as u32 == addr)?;
+        let data = |a: u32| self.inside(a) && !self.covered[(a - self.base) as usize];
+        match data(addr) && data(addr + 1) && !self.labels.contains_key(&(addr + 1)) {
+            true => Some(name),
+            false => None,
+        }
+    }
+}
+
+/// An indented line of source with its address and bytes in a comment
+fn line(out: &mut String, text: &str, addr: u32, hex: &str) {
+    let comment = format!("; ${addr:04X}  {hex}");
+    writeln!(out, "        {text:<23} {}", comment.trim_end()).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn test_analyze() {
+        let source = "
+            .org $FF00
+            reset:  ldx #$FF
+                    txs
+            @loop:  lda a:$0010
+                    jsr print
+                    bne @loop
+                    jmp (vector)
+            print:  ldy table,x
+                    sta $10
+                    rts
+            table:  .byte 1, 2, $FF
+            vector: .word reset
+            entry:  nop
+                    .res $FFFA - *, $EA
+                    .word reset, reset, $1234
+        ";
+        let program = assemble(source).unwrap();
+        let at = |name| program.symbol(name).unwrap().0;
+        let (base, image) = program.image();
+        assert_eq!(base, Addr(0xFF00));
+
+        let source = analyze(&image, base, &[]).unwrap().source();
+        assert!(source.contains("reset:\n        LDX #$FF"));
+        assert!(source.contains("LDA a:$0010"));
+        assert!(source.contains(&format!("JSR L_{:04X}", at("print"))));
+        assert!(source.contains(&format!("BNE L_{:04X}", at("reset@loop"))));
+        assert!(source.contains(&format!("JMP (D_{:04X})", at("vector"))));
+        let table = at("table");
+        assert!(source.contains(&format!("LDY D_{table:04X},X")));
+        assert!(source.contains(&format!("D_{table:04X}:\n        .byte $01,$02,$FF")));
+        assert!(source.contains(".addr reset             ; $FFFA  00 FF  nmi"));
+        assert!(source.contains(".addr $1234"));
+        // Never reached, so taken for data
+        assert!(!source.contains("NOP"));
+        assert_eq!(assemble(&source).unwrap().image(), (base, image.clone()));
+
+        let entry = program.symbol("entry").unwrap();
+        let source = analyze(&image, base, &[entry]).unwrap().source();
+        assert!(source.contains(&format!("L_{:04X}:\n        NOP", entry.0)));
+        assert_eq!(assemble(&source).unwrap().image(), (base, image));
+
+        assert!(analyze(&[0; 0x100], Addr(0xFF01), &[]).is_err());
+    }
+}
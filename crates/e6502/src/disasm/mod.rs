@@ -0,0 +1,133 @@
+pub mod analysis;
+
+use std::fmt::Display;
+
+use crate::hardware::cpu::instructions::{AddressingMode, Instruction, OPCODES};
+use crate::types::{Addr, Byte};
+
+/// A single decoded instruction
+///
+/// `instruction` is `None` for undocumented op codes; those are shown as a
+/// `.byte` directive.
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    pub addr: Addr,
+    pub bytes: Vec<Byte>,
+    pub instruction: Option<Instruction>,
+    pub mode: AddressingMode,
+}
+
+/// Decode the instruction at `addr`, reading its bytes through `read`
+pub fn disassemble(addr: Addr, read: impl Fn(Addr) -> Byte) -> Disassembly {
+    let op_code = read(addr);
+    let info = &OPCODES[op_code.0 as usize];
+    if !info.documented {
+        return Disassembly {
+            addr,
+            bytes: vec![op_code],
+            instruction: None,
+            mode: AddressingMode::Implied,
+        };
+    }
+
+    let bytes = (0..info.size)
+        .map(|i| read(Addr(addr.0.wrapping_add(i as u16))))
+        .collect();
+
+    Disassembly {
+        addr,
+        bytes,
+        instruction: Some(info.instruction),
+        mode: info.mode,
+    }
+}
+
+impl Disassembly {
+    /// Number of bytes the instruction occupies
+    pub fn size(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    /// Address of the instruction following this one
+    pub fn next(&self) -> Addr {
+        Addr(self.addr.0.wrapping_add(self.size()))
+    }
+
+    /// The address the operand refers to, before indexing
+    ///
+    /// For branches this is the branch target.
+    pub fn operand(&self) -> Option<Addr> {
+        match self.mode {
+            AddressingMode::Implied | AddressingMode::Immediate => None,
+            AddressingMode::Relative => {
+                let offset = self.bytes[1].0 as i8 as u16;
+                Some(Addr(self.next().0.wrapping_add(offset)))
+            }
+            AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY => Some(Addr::from(self.bytes[1])),
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => Some(Addr::new(self.bytes[2], self.bytes[1])),
+        }
+    }
+
+    /// Render the instruction in ca65 syntax, naming operand addresses with `resolve`
+    pub fn format(&self, resolve: impl Fn(Addr) -> Option<String>) -> String {
+        let Some(instruction) = self.instruction else {
+            return format!(".byte ${:02X}", self.bytes[0].0);
+        };
+
+        let operand = match (self.mode, self.operand()) {
+            (AddressingMode::Implied, _) => {
+                return match instruction {
+                    Instruction::ASL | Instruction::LSR | Instruction::ROL | Instruction::ROR => {
+                        format!("{instruction} A")
+                    }
+                    _ => format!("{instruction}"),
+                };
+            }
+            (AddressingMode::Immediate, _) => {
+                return format!("{instruction} #${:02X}", self.bytes[1].0);
+            }
+            (mode, Some(addr)) => {
+                let name = resolve(addr).unwrap_or_else(|| {
+                    if matches!(mode, AddressingMode::Relative) || self.bytes.len() == 3 {
+                        format!("${:04X}", addr.0)
+                    } else {
+                        format!("${:02X}", addr.0)
+                    }
+                });
+                match mode {
+                    AddressingMode::ZeroPageX | AddressingMode::AbsoluteX => format!("{name},X"),
+                    AddressingMode::ZeroPageY | AddressingMode::AbsoluteY => format!("{name},Y"),
+                    AddressingMode::Indirect => format!("({name})"),
+                    AddressingMode::IndirectX => format!("({name},X)"),
+                    AddressingMode::IndirectY => format!("({name}),Y"),
+                    _ => name,
+                }
+            }
+            (_, None) => unreachable!(),
+        };
+
+        format!("{instruction} {operand}")
+    }
+
+    /// The raw bytes as space separated hex
+    pub fn hex(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|b| format!("{:02X}", b.0))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(|_| None))
+    }
+}
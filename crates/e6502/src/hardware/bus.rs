@@ -1,11 +1,48 @@
+use crate::hardware::error::ErrorKind;
+use crate::hardware::memory::Pattern;
 use crate::hardware::Device;
 use crate::types::{Addr, Byte};
+use std::cell::Cell;
 use std::collections::HashMap;
 
+/// What the bus does when nobody answers an access: a read from or a write to
+/// an address no device is registered at, or a write to ROM
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Unmapped {
+    /// Reads return the last byte on the data bus and writes go nowhere, like
+    /// on real hardware
+    #[default]
+    OpenBus,
+    /// Reads return this byte and writes go nowhere
+    Fixed(Byte),
+    /// Like `OpenBus`, reporting every such access on stderr
+    Log,
+    /// Fail the instruction with an error, which stops the debugger
+    Break,
+}
+
+impl TryFrom<&str> for Unmapped {
+    type Error = String;
+    /// `open-bus`, `log`, `break` or a byte to read, e.g. `0xFF`
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "open-bus" => Ok(Unmapped::OpenBus),
+            "log" => Ok(Unmapped::Log),
+            "break" => Ok(Unmapped::Break),
+            _ => Ok(Unmapped::Fixed(Byte::try_from(value).map_err(|e| {
+                format!("Invalid policy {value:?}, not open-bus, log, break or a byte: {e}")
+            })?)),
+        }
+    }
+}
+
 // Encapsulates the 16-bit wide bus
 pub struct Bus {
     devices: Vec<Box<dyn Device>>,
     indices: HashMap<u16, usize>,
+    unmapped: Unmapped,
+    /// The last byte on the data bus, which unmapped reads see
+    latch: Cell<Byte>,
 }
 
 impl Default for Bus {
@@ -19,9 +56,26 @@ impl Bus {
         Self {
             devices: vec![],
             indices: HashMap::new(),
+            unmapped: Unmapped::default(),
+            latch: Cell::new(Byte(0)),
         }
     }
 
+    /// Handle accesses nobody answers by `policy`
+    pub fn set_unmapped(&mut self, policy: Unmapped) {
+        self.unmapped = policy;
+    }
+
+    /// The last byte on the data bus
+    pub fn latch(&self) -> Byte {
+        self.latch.get()
+    }
+
+    /// Put `data` on the data bus, as when restoring a snapshot
+    pub fn set_latch(&self, data: Byte) {
+        self.latch.set(data);
+    }
+
     pub fn register<T: Device + 'static>(&mut self, dev: T) -> Result<(), String> {
         let (start, end) = dev.range();
         for key in self.indices.keys() {
@@ -30,7 +84,7 @@ impl Bus {
             }
         }
 
-        println!("Registering device at {start}..{end}");
+        eprintln!("Registering device at {start}..{end}");
 
         self.devices.push(Box::new(dev));
         let len = self.devices.len();
@@ -41,24 +95,132 @@ impl Bus {
         Ok(())
     }
 
+    /// Whether a device is registered at `addr`
+    pub fn is_mapped(&self, addr: Addr) -> bool {
+        self.indices.contains_key(&addr.0)
+    }
+
     /// Read on bus from address `addr`
-    pub fn read(&self, addr: Addr) -> Byte {
-        if !self.indices.contains_key(&addr.0) {
-            panic!("Nothing registered at {:#06X}", addr.0);
+    pub fn read(&self, addr: Addr) -> Result<Byte, ErrorKind> {
+        let data = match self.indices.get(&addr.0) {
+            Some(index) => self.devices[*index].tx(addr),
+            None => match self.unmapped {
+                Unmapped::OpenBus => self.latch.get(),
+                Unmapped::Fixed(data) => data,
+                Unmapped::Log => {
+                    eprintln!("Read from unmapped {addr}");
+                    self.latch.get()
+                }
+                Unmapped::Break => return Err(ErrorKind::UnmappedRead(addr)),
+            },
+        };
+        self.latch.set(data);
+        Ok(data)
+    }
+
+    /// Inspect `addr` without side effects on the device, `None` if nothing
+    /// is registered there
+    pub fn peek(&self, addr: Addr) -> Option<Byte> {
+        let index = self.indices.get(&addr.0)?;
+        Some(self.devices[*index].peek(addr))
+    }
+
+    /// Change `addr` without side effects on the device, ignored if nothing
+    /// is registered there
+    pub fn poke(&self, addr: Addr, data: Byte) {
+        if let Some(index) = self.indices.get(&addr.0) {
+            self.devices[*index].poke(addr, data);
+        }
+    }
+
+    /// The address ranges of every device, in registration order
+    pub fn ranges(&self) -> Vec<(Addr, Addr)> {
+        self.devices.iter().map(|dev| dev.range()).collect()
+    }
+
+    /// Power on every device, with RAM holding `pattern`
+    pub fn power_on(&self, pattern: Pattern) {
+        for dev in &self.devices {
+            dev.power_on(pattern);
+        }
+    }
+
+    /// Capture the state of every device, in registration order
+    pub fn snapshot(&self) -> Vec<Option<Vec<u8>>> {
+        self.devices.iter().map(|dev| dev.snapshot()).collect()
+    }
+
+    /// Restore the state of every device captured with `snapshot`
+    pub fn restore(&self, snapshot: &[Option<Vec<u8>>]) {
+        for (dev, data) in self.devices.iter().zip(snapshot) {
+            if let Some(data) = data {
+                dev.restore(data);
+            }
         }
-        let dev = &self.devices[self.indices[&addr.0]];
-        dev.tx(addr)
     }
 
     /// Write on bus `data` to address `addr`
-    pub fn write(&mut self, addr: impl Into<Addr>, data: impl Into<Byte>) {
+    pub fn write(&mut self, addr: impl Into<Addr>, data: impl Into<Byte>) -> Result<(), ErrorKind> {
         let addr = addr.into();
         let data = data.into();
-        if !self.indices.contains_key(&addr.0) {
-            eprintln!("Nothing registered at {:#06X}", addr.0);
-            return;
+        self.latch.set(data);
+        let error = match self.indices.get(&addr.0).map(|index| &self.devices[*index]) {
+            Some(dev) if !dev.read_only() => {
+                dev.rx(addr, data);
+                return Ok(());
+            }
+            Some(_) => ErrorKind::RomWrite(addr),
+            None => ErrorKind::UnmappedWrite(addr),
+        };
+        match self.unmapped {
+            Unmapped::OpenBus | Unmapped::Fixed(_) => Ok(()),
+            Unmapped::Log => {
+                eprintln!("{error}");
+                Ok(())
+            }
+            Unmapped::Break => Err(error),
         }
-        let dev = &mut self.devices[self.indices[&addr.0]];
-        dev.rx(addr, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hardware::memory::Memory;
+    use crate::hardware::rom::Rom;
+
+    #[test]
+    fn test_unmapped() {
+        let mut bus = Bus::new();
+        bus.register(Memory::new(Addr(0x0000), Addr(0x00ff)))
+            .unwrap();
+        bus.register(Rom::new(None).unwrap()).unwrap();
+        bus.poke(Addr(0x10), Byte(0x42));
+        bus.poke(Addr(0x8000), Byte(0xEA));
+
+        // The last byte on the bus, read or written
+        assert_eq!(bus.read(Addr(0x10)), Ok(Byte(0x42)));
+        assert_eq!(bus.read(Addr(0x4000)), Ok(Byte(0x42)));
+        assert_eq!(bus.write(Addr(0x4000), Byte(0x17)), Ok(()));
+        assert_eq!(bus.read(Addr(0x4000)), Ok(Byte(0x17)));
+        assert_eq!(bus.write(Addr(0x8000), Byte(0x00)), Ok(()));
+        assert_eq!(bus.read(Addr(0x8000)), Ok(Byte(0xEA)));
+        assert_eq!(bus.latch(), Byte(0xEA));
+
+        bus.set_unmapped(Unmapped::try_from("0xFF").unwrap());
+        assert_eq!(bus.read(Addr(0x4000)), Ok(Byte(0xFF)));
+
+        bus.set_unmapped(Unmapped::try_from("break").unwrap());
+        assert_eq!(
+            bus.read(Addr(0x4000)),
+            Err(ErrorKind::UnmappedRead(Addr(0x4000)))
+        );
+        assert_eq!(
+            bus.write(Addr(0x8000), Byte(0)),
+            Err(ErrorKind::RomWrite(Addr(0x8000)))
+        );
+        assert_eq!(bus.read(Addr(0x8000)), Ok(Byte(0xEA)));
+
+        assert!(Unmapped::try_from("floating").is_err());
     }
 }
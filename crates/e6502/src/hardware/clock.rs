@@ -15,6 +15,8 @@ pub struct Clock {
     ticks: AtomicU64,
     state: Mutex<bool>,
     cvar: Condvar,
+    /// Never wait to be driven, see `free_running`
+    free: bool,
 }
 
 impl Default for Clock {
@@ -29,12 +31,31 @@ impl Clock {
             ticks: AtomicU64::new(0),
             state: Mutex::new(false),
             cvar: Condvar::new(),
+            free: false,
+        }
+    }
+
+    /// A clock that only counts cycles, letting the CPU run as fast as it can
+    pub fn free_running() -> Self {
+        Clock {
+            free: true,
+            ..Self::new()
         }
     }
     pub fn ticks(&self) -> u64 {
         self.ticks.load(Ordering::Relaxed)
     }
 
+    /// Count a cycle without waiting for anyone to drive the clock
+    pub fn step(&self) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Move the cycle count, e.g. when travelling back in time
+    pub fn set_ticks(&self, ticks: u64) {
+        self.ticks.store(ticks, Ordering::Relaxed);
+    }
+
     pub fn tick(&self) {
         let mut state = self.state.lock().unwrap();
         while *state {
@@ -46,6 +67,11 @@ impl Clock {
     }
 
     pub fn tock(&self) {
+        if self.free {
+            self.step();
+            return;
+        }
+
         let mut state = self.state.lock().unwrap();
         while !*state {
             state = self.cvar.wait(state).unwrap();
@@ -57,6 +83,10 @@ impl Clock {
     }
 
     pub fn wait_tick(&self) {
+        if self.free {
+            return;
+        }
+
         let mut state = self.state.lock().unwrap();
         while !*state {
             state = self.cvar.wait(state).unwrap();
@@ -62,8 +62,9 @@ mod test {
         use crate::hardware::*;
 
         let mut system = system::System::new().pc(0x400);
-        for (i, byte) in include_bytes!("arithmetic.bin").iter().enumerate() {
-            system.set_memory(i as u16, *byte);
+        let program = crate::asm::assemble(include_str!("arithmetic.s")).unwrap();
+        for (addr, byte) in program.bytes() {
+            system.set_memory(addr.0, byte.0);
         }
 
         let (mut cpu, clk) = system.prepare();
@@ -75,7 +76,7 @@ mod test {
         });
 
         loop {
-            if !cpu.exec() {
+            if cpu.exec().unwrap() == cpu::StepOutcome::Trapped {
                 break;
             }
             instructions += 1;
@@ -106,8 +107,8 @@ mod test {
             clk.wait_tock();
         });
 
-        cpu.exec();
+        cpu.exec().unwrap();
         assert_eq!(cpu.a, Byte(0x69));
-        assert_eq!(cpu.clk.ticks(), 4);
+        assert_eq!(cpu.clk.ticks(), cpu::RESET_CYCLES + 2);
     }
 }
@@ -0,0 +1,95 @@
+; ADC and SBC: results, the N, V, Z and C flags, and every addressing mode
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+; Check A is `result` and N, V, Z and C are `flags`
+.macro check result, flags
+        php
+        cmp #result
+        beq @result
+        jmp fail
+@result:
+        pla
+        and #$C3
+        cmp #flags
+        beq @flags
+        jmp fail
+@flags:
+.endmacro
+
+; `lhs` + `rhs` + `carry`
+.macro add lhs, rhs, carry, result, flags
+        lda #carry
+        lsr a
+        lda #lhs
+        adc #rhs
+        check result, flags
+.endmacro
+
+; `lhs` - `rhs` - not `carry`
+.macro sub lhs, rhs, carry, result, flags
+        lda #carry
+        lsr a
+        lda #lhs
+        sbc #rhs
+        check result, flags
+.endmacro
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $0459
+pass:   jmp pass
+
+main:   add $01, $01, 0, $02, $00
+        add $FF, $01, 0, $00, $03
+        add $7F, $01, 0, $80, $C0
+        add $80, $FF, 0, $7F, $41
+        add $10, $20, 1, $31, $00
+        add $FF, $FF, 1, $FF, $81
+
+        sub $05, $03, 1, $02, $01
+        sub $03, $05, 1, $FE, $80
+        sub $80, $01, 1, $7F, $41
+        sub $00, $00, 0, $FF, $80
+        sub $05, $05, 1, $00, $03
+        sub $7F, $FF, 1, $80, $C0
+
+        ; $11 in every place an operand is read from
+        ldx #1
+        ldy #2
+        lda #$11
+        sta $20
+        sta $21
+        sta $0300
+        sta $0301
+        sta $0302
+        lda #<$0300
+        sta $30
+        lda #>$0300
+        sta $31
+
+        clc
+        lda #0
+        adc $20
+        adc $20,x
+        adc $0300
+        adc $0300,x
+        adc $0300,y
+        adc ($2F,x)
+        adc ($30),y
+        check $77, $00
+
+        lda #$77
+        sec
+        sbc $20
+        sbc $20,x
+        sbc $0300
+        sbc $0300,x
+        sbc $0300,y
+        sbc ($2F,x)
+        sbc ($30),y
+        check $00, $03
+
+        jmp pass
@@ -44,7 +44,6 @@ pub fn beq(arg: InstructionArgument, cpu: &mut CPU) -> bool {
         unreachable!("Illegal addressing mode: {:?}", arg);
     };
 
-    println!("Zero is {}, arg: {}", cpu.is_set(Flag::Zero), arg);
     if cpu.is_set(Flag::Zero) {
         offset_pc(cpu, offset);
 
@@ -127,8 +126,9 @@ mod test {
         use crate::hardware::*;
 
         let mut system = system::System::new().pc(0x400);
-        for (i, byte) in include_bytes!("branch.bin").iter().enumerate() {
-            system.set_memory(i as u16, *byte);
+        let program = crate::asm::assemble(include_str!("branch.s")).unwrap();
+        for (addr, byte) in program.bytes() {
+            system.set_memory(addr.0, byte.0);
         }
 
         let (mut cpu, clk) = system.prepare();
@@ -140,7 +140,7 @@ mod test {
         });
 
         loop {
-            if !cpu.exec() {
+            if cpu.exec().unwrap() == cpu::StepOutcome::Trapped {
                 break;
             }
             instructions += 1;
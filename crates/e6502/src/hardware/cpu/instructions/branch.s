@@ -0,0 +1,76 @@
+; Branches: taken or not on every flag, forward and back, and at the ends of
+; their range
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+.macro taken op
+        op @ok
+        jmp fail
+@ok:
+.endmacro
+
+.macro not_taken op
+        op @bad
+        jmp @ok
+@bad:   jmp fail
+@ok:
+.endmacro
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $0718
+pass:   jmp pass
+
+main:   sec
+        taken bcs
+        not_taken bcc
+        clc
+        taken bcc
+        not_taken bcs
+
+        lda #0
+        taken beq
+        not_taken bne
+        taken bpl
+        not_taken bmi
+        lda #$80
+        taken bne
+        not_taken beq
+        taken bmi
+        not_taken bpl
+
+        clv
+        taken bvc
+        not_taken bvs
+        lda #$40
+        sta $10
+        bit $10
+        taken bvs
+        not_taken bvc
+
+        ; Back
+        ldx #5
+        ldy #0
+@loop:  iny
+        dex
+        bne @loop
+        cpy #5
+        taken beq
+
+        ; The furthest forward
+        clc
+        bcc @far
+        .res 127, $00
+@far:
+
+        ; The furthest back
+        jmp @forth
+@back:  jmp @done
+        .res 123, $00
+@forth: bcc @back
+        jmp fail
+@done:
+
+        jmp pass
@@ -2,65 +2,37 @@ use super::InstructionArgument;
 use crate::hardware::cpu::{Flag, CPU};
 use crate::types::Bit;
 
-pub fn clc(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn clc(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.set(Flag::Carry, Bit(false));
     true
 }
 
-pub fn cld(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn cld(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.set(Flag::DecimalMode, Bit(false));
     true
 }
 
-pub fn cli(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn cli(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.set(Flag::InterruptDisable, Bit(false));
     true
 }
 
-pub fn clv(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn clv(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.set(Flag::Overflow, Bit(false));
     true
 }
 
-pub fn sec(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn sec(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.set(Flag::Carry, Bit(true));
     true
 }
 
-pub fn sed(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn sed(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.set(Flag::DecimalMode, Bit(true));
     true
 }
 
-pub fn sei(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn sei(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.set(Flag::InterruptDisable, Bit(true));
     true
 }
@@ -73,8 +45,9 @@ mod test {
         use crate::hardware::*;
 
         let mut system = system::System::new().pc(0x400);
-        for (i, byte) in include_bytes!("flag.bin").iter().enumerate() {
-            system.set_memory(i as u16, *byte);
+        let program = crate::asm::assemble(include_str!("flag.s")).unwrap();
+        for (addr, byte) in program.bytes() {
+            system.set_memory(addr.0, byte.0);
         }
 
         let (mut cpu, clk) = system.prepare();
@@ -86,7 +59,7 @@ mod test {
         });
 
         loop {
-            if !cpu.exec() {
+            if cpu.exec().unwrap() == cpu::StepOutcome::Trapped {
                 break;
             }
             instructions += 1;
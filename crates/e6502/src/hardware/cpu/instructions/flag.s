@@ -0,0 +1,52 @@
+; Setting and clearing flags, and PLP
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+; Check the flags in `mask` are `value`
+.macro flags mask, value
+        php
+        pla
+        and #mask
+        cmp #value
+        beq @ok
+        jmp fail
+@ok:
+.endmacro
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $049A
+pass:   jmp pass
+
+main:   sec
+        flags $01, $01
+        clc
+        flags $01, $00
+        sed
+        flags $08, $08
+        cld
+        flags $08, $00
+        sei
+        flags $04, $04
+        cli
+        flags $04, $00
+
+        lda #$40
+        sta $10
+        bit $10
+        flags $40, $40
+        clv
+        flags $40, $00
+
+        lda #$CF
+        pha
+        plp
+        flags $CF, $CF
+        lda #$00
+        pha
+        plp
+        flags $CF, $00
+
+        jmp pass
@@ -16,11 +16,7 @@ pub fn inc(arg: InstructionArgument, cpu: &mut CPU) -> bool {
     true
 }
 
-pub fn inx(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn inx(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.x += 1;
     cpu.set(Flag::Zero, Bit(cpu.x == 0));
     cpu.set(Flag::Negative, cpu.x & Flag::Negative);
@@ -28,11 +24,7 @@ pub fn inx(arg: InstructionArgument, cpu: &mut CPU) -> bool {
     true
 }
 
-pub fn iny(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn iny(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.y += 1;
     cpu.set(Flag::Zero, Bit(cpu.y == 0));
     cpu.set(Flag::Negative, cpu.y & Flag::Negative);
@@ -54,11 +46,7 @@ pub fn dec(arg: InstructionArgument, cpu: &mut CPU) -> bool {
     true
 }
 
-pub fn dex(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn dex(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.x -= 1;
     cpu.set(Flag::Zero, Bit(cpu.x == 0));
     cpu.set(Flag::Negative, cpu.x & Flag::Negative);
@@ -66,11 +54,7 @@ pub fn dex(arg: InstructionArgument, cpu: &mut CPU) -> bool {
     true
 }
 
-pub fn dey(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn dey(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.y -= 1;
     cpu.set(Flag::Zero, Bit(cpu.y == 0));
     cpu.set(Flag::Negative, cpu.y & Flag::Negative);
@@ -86,8 +70,9 @@ mod test {
         use crate::hardware::*;
 
         let mut system = system::System::new().pc(0x400);
-        for (i, byte) in include_bytes!("increment_decrement.bin").iter().enumerate() {
-            system.set_memory(i as u16, *byte);
+        let program = crate::asm::assemble(include_str!("increment_decrement.s")).unwrap();
+        for (addr, byte) in program.bytes() {
+            system.set_memory(addr.0, byte.0);
         }
 
         let (mut cpu, clk) = system.prepare();
@@ -99,7 +84,7 @@ mod test {
         });
 
         loop {
-            if !cpu.exec() {
+            if cpu.exec().unwrap() == cpu::StepOutcome::Trapped {
                 break;
             }
             instructions += 1;
@@ -0,0 +1,92 @@
+; INC, DEC, INX, INY, DEX and DEY: results, the N and Z flags, wrapping
+; around and every addressing mode
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+; Check N and Z are `nz`, pushed before the value is checked
+.macro nz flags
+        pla
+        and #$82
+        cmp #flags
+        beq @ok
+        jmp fail
+@ok:
+.endmacro
+
+; Check `addr` holds `value`, with N and Z `flags`
+.macro memory addr, value, flags
+        php
+        lda addr
+        cmp #value
+        beq @ok
+        jmp fail
+@ok:    nz flags
+.endmacro
+
+.macro reg cmp, value, flags
+        php
+        cmp #value
+        beq @ok
+        jmp fail
+@ok:    nz flags
+.endmacro
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $0A17
+pass:   jmp pass
+
+main:   ldx #1
+        lda #$7F
+        sta $10
+        inc $10
+        memory $10, $80, $80
+        lda #$FF
+        sta $11
+        inc $10,x
+        memory $11, $00, $02
+        lda #$FF
+        sta $0300
+        inc $0300
+        memory $0300, $00, $02
+        lda #$41
+        sta $0301
+        inc $0300,x
+        memory $0301, $42, $00
+
+        lda #$01
+        sta $12
+        dec $12
+        memory $12, $00, $02
+        dec $11,x
+        memory $12, $FF, $80
+        lda #$80
+        sta $0300
+        dec $0300
+        memory $0300, $7F, $00
+        dec $0300,x
+        memory $0301, $41, $00
+
+        ldx #$FF
+        inx
+        reg cpx, $00, $02
+        dex
+        reg cpx, $FF, $80
+        ldy #$7F
+        iny
+        reg cpy, $80, $80
+        dey
+        reg cpy, $7F, $00
+
+        ; All the way around
+        ldx #0
+@x:     inx
+        bne @x
+        ldy #0
+@y:     dey
+        bne @y
+        reg cpy, $00, $02
+
+        jmp pass
@@ -30,8 +30,7 @@ pub fn jsr(arg: InstructionArgument, cpu: &mut CPU) -> bool {
     false
 }
 
-pub fn rts(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn rts(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     let low_addr = cpu.pop_stack();
     let hi_addr = cpu.pop_stack();
     cpu.pc = Addr::new(hi_addr, low_addr);
@@ -39,11 +38,7 @@ pub fn rts(arg: InstructionArgument, cpu: &mut CPU) -> bool {
     false
 }
 
-pub fn brk(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn brk(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.push_stack(Byte::from(cpu.pc >> 8));
     cpu.push_stack(Byte::from(cpu.pc & 0xff) + 2);
     cpu.push_stack(cpu.ps | Flag::Break | Flag::Reserved);
@@ -56,11 +51,7 @@ pub fn brk(arg: InstructionArgument, cpu: &mut CPU) -> bool {
     false
 }
 
-pub fn rti(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(
-        matches!(arg, InstructionArgument::Implied),
-        "Illegal addressing mode"
-    );
+pub fn rti(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.ps = cpu.pop_stack() & !Flag::Break;
     let low_addr = cpu.pop_stack();
     let hi_addr = cpu.pop_stack();
@@ -78,8 +69,9 @@ mod test {
         use crate::hardware::*;
 
         let mut system = system::System::new().pc(0x400);
-        for (i, byte) in include_bytes!("jump_call.bin").iter().enumerate() {
-            system.set_memory(i as u16, *byte);
+        let program = crate::asm::assemble(include_str!("jump_call.s")).unwrap();
+        for (addr, byte) in program.bytes() {
+            system.set_memory(addr.0, byte.0);
         }
 
         let (mut cpu, clk) = system.prepare();
@@ -91,7 +83,7 @@ mod test {
         });
 
         loop {
-            if !cpu.exec() {
+            if cpu.exec().unwrap() == cpu::StepOutcome::Trapped {
                 break;
             }
             instructions += 1;
@@ -0,0 +1,58 @@
+; JMP, JSR and RTS: absolute and indirect jumps, nested calls and the return
+; address JSR pushes
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $0533
+pass:   jmp pass
+
+main:   ldx #$FF
+        txs
+        jmp @absolute
+        jmp fail
+
+@absolute:
+        lda #<@indirect
+        sta $10
+        lda #>@indirect
+        sta $11
+        jmp ($0010)
+        jmp fail
+
+@indirect:
+        ldy #0
+        jsr inc_y
+        cpy #1
+        bne @fail
+        jsr twice
+        cpy #3
+        bne @fail
+        jsr return
+@return:
+        tsx
+        cpx #$FF
+        bne @fail
+        jmp pass
+@fail:  jmp fail
+
+inc_y:  iny
+        rts
+
+twice:  jsr inc_y
+        jsr inc_y
+        rts
+
+; Check the return address on the stack is the one before `main@return`
+return: tsx
+        lda $0101,x
+        cmp #<(main@return - 1)
+        bne @fail
+        lda $0102,x
+        cmp #>(main@return - 1)
+        bne @fail
+        rts
+@fail:  jmp fail
@@ -0,0 +1,144 @@
+; Loads, stores and transfers between registers: every addressing mode and
+; the N and Z flags
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+.macro expect cmp, value
+        cmp #value
+        beq @ok
+        jmp fail
+@ok:
+.endmacro
+
+; Check N and Z are `flags`
+.macro nz flags
+        php
+        pla
+        and #$82
+        cmp #flags
+        beq @ok
+        jmp fail
+@ok:
+.endmacro
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $10A5
+pass:   jmp pass
+
+main:   lda #0
+        nz $02
+        lda #$80
+        nz $80
+        ldx #$00
+        nz $02
+        ldy #$FF
+        nz $80
+
+        ; Pointers to $0310 at $22 and $0320 at $24
+        lda #$10
+        sta $22
+        lda #$20
+        sta $24
+        lda #$03
+        sta $23
+        sta $25
+
+        ; A in every mode
+        ldx #2
+        ldy #3
+        lda #$A1
+        sta $10
+        lda #$A2
+        sta $10,x
+        lda #$A3
+        sta $0300
+        lda #$A4
+        sta $0300,x
+        lda #$A5
+        sta $0300,y
+        lda #$A6
+        sta ($20,x)
+        lda #$A7
+        sta ($24),y
+
+        lda $10
+        expect cmp, $A1
+        lda $10,x
+        expect cmp, $A2
+        lda $0300
+        expect cmp, $A3
+        lda $0300,x
+        expect cmp, $A4
+        lda $0300,y
+        expect cmp, $A5
+        lda ($20,x)
+        expect cmp, $A6
+        lda ($24),y
+        expect cmp, $A7
+        lda $0310
+        expect cmp, $A6
+        lda $0323
+        expect cmp, $A7
+
+        ; X and Y in every mode
+        ldx #$B1
+        stx $30
+        ldy #2
+        ldx #$B2
+        stx $30,y
+        ldx #$B3
+        stx $0330
+        ldy #$C1
+        sty $40
+        ldx #2
+        ldy #$C2
+        sty $40,x
+        ldy #$C3
+        sty $0340
+
+        ldy #2
+        ldx $30
+        expect cpx, $B1
+        ldx $30,y
+        expect cpx, $B2
+        ldx $0330
+        expect cpx, $B3
+        ldx $0330-2,y
+        expect cpx, $B3
+        ldx #2
+        ldy $40
+        expect cpy, $C1
+        ldy $40,x
+        expect cpy, $C2
+        ldy $0340
+        expect cpy, $C3
+        ldy $0340-2,x
+        expect cpy, $C3
+
+        ; Transfers
+        lda #$80
+        tax
+        nz $80
+        expect cpx, $80
+        lda #$00
+        tay
+        nz $02
+        expect cpy, $00
+        ldx #$7F
+        txa
+        expect cmp, $7F
+        ldx #$00
+        lda #$01
+        txa
+        nz $02
+        ldy #$80
+        tya
+        expect cmp, $80
+        lda #$00
+        tya
+        nz $80
+
+        jmp pass
@@ -0,0 +1,105 @@
+; AND, ORA, EOR and BIT: results, flags and every addressing mode
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+.macro expect value
+        cmp #value
+        beq @ok
+        jmp fail
+@ok:
+.endmacro
+
+; Check the flags in `mask` are `value`
+.macro flags mask, value
+        php
+        pla
+        and #mask
+        cmp #value
+        beq @ok
+        jmp fail
+@ok:
+.endmacro
+
+; `op` of $F0 with $3C in every mode
+.macro modes op, result
+        lda #$F0
+        op #$3C
+        expect result
+        lda #$F0
+        op $20
+        expect result
+        lda #$F0
+        op $20,x
+        expect result
+        lda #$F0
+        op $0300
+        expect result
+        lda #$F0
+        op $0300,x
+        expect result
+        lda #$F0
+        op $0300,y
+        expect result
+        lda #$F0
+        op ($2F,x)
+        expect result
+        lda #$F0
+        op ($30),y
+        expect result
+.endmacro
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $10E9
+pass:   jmp pass
+
+main:   ; $3C in every place an operand is read from
+        ldx #1
+        ldy #2
+        lda #$3C
+        sta $20
+        sta $21
+        sta $0300
+        sta $0301
+        sta $0302
+        lda #<$0300
+        sta $30
+        lda #>$0300
+        sta $31
+
+        modes and, $30
+        modes ora, $FC
+        modes eor, $CC
+
+        lda #$0F
+        and #$F0
+        flags $82, $02
+        lda #$00
+        ora #$80
+        flags $82, $80
+        lda #$FF
+        eor #$FF
+        flags $82, $02
+
+        ; BIT takes N and V from memory and Z from A & memory
+        lda #$C0
+        sta $10
+        sta $0310
+        lda #$3F
+        bit $10
+        flags $C2, $C2
+        lda #$40
+        bit $0310
+        flags $C2, $C0
+        lda #$3F
+        sta $11
+        lda #$01
+        bit $11
+        flags $C2, $00
+        lda #$01
+        bit $11
+        expect $01
+
+        jmp pass
@@ -10,9 +10,8 @@ mod stack_op;
 
 use std::fmt::Display;
 
-use phf::phf_map;
-
 use crate::hardware::cpu::CPU;
+use crate::hardware::error::ErrorKind;
 use crate::types::*;
 
 #[derive(Debug)]
@@ -40,54 +39,90 @@ pub enum AddressingMode {
 }
 
 impl AddressingMode {
+    pub const ALL: [AddressingMode; 12] = [
+        AddressingMode::Immediate,
+        AddressingMode::ZeroPage,
+        AddressingMode::ZeroPageX,
+        AddressingMode::ZeroPageY,
+        AddressingMode::Absolute,
+        AddressingMode::AbsoluteX,
+        AddressingMode::AbsoluteY,
+        AddressingMode::Indirect,
+        AddressingMode::IndirectX,
+        AddressingMode::IndirectY,
+        AddressingMode::Relative,
+        AddressingMode::Implied,
+    ];
+
+    /// Number of bytes an instruction with this addressing mode occupies, opcode included
+    pub const fn size(self) -> u8 {
+        match self {
+            AddressingMode::Implied => 1,
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY
+            | AddressingMode::Relative => 2,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 3,
+        }
+    }
+
+    /// The argument of the instruction at PC, for display
+    ///
+    /// Reads with `CPU::peek`, so it neither spends cycles nor disturbs devices.
     pub fn get(self, cpu: &CPU) -> InstructionArgument {
         match self {
-            AddressingMode::Immediate => InstructionArgument::Immediate(cpu.read(cpu.pc + 1)),
+            AddressingMode::Immediate => InstructionArgument::Immediate(cpu.peek(cpu.pc + 1)),
             AddressingMode::ZeroPage => {
-                InstructionArgument::Address(Addr::from(cpu.read(cpu.pc + 1)))
+                InstructionArgument::Address(Addr::from(cpu.peek(cpu.pc + 1)))
             }
             AddressingMode::ZeroPageX => {
-                InstructionArgument::Address(Addr::from(cpu.read(cpu.pc + 1) + cpu.x))
+                InstructionArgument::Address(Addr::from(cpu.peek(cpu.pc + 1) + cpu.x))
             }
             AddressingMode::ZeroPageY => {
-                InstructionArgument::Address(Addr::from(cpu.read(cpu.pc + 1) + cpu.y))
+                InstructionArgument::Address(Addr::from(cpu.peek(cpu.pc + 1) + cpu.y))
             }
             AddressingMode::Absolute => {
-                let low_addr = cpu.read(cpu.pc + 1);
-                let hi_addr = cpu.read(cpu.pc + 2);
+                let low_addr = cpu.peek(cpu.pc + 1);
+                let hi_addr = cpu.peek(cpu.pc + 2);
                 InstructionArgument::Address(Addr::new(hi_addr, low_addr))
             }
             AddressingMode::AbsoluteX => {
-                let low_addr = cpu.read(cpu.pc + 1);
-                let hi_addr = cpu.read(cpu.pc + 2);
+                let low_addr = cpu.peek(cpu.pc + 1);
+                let hi_addr = cpu.peek(cpu.pc + 2);
                 InstructionArgument::Address(Addr::new(hi_addr, low_addr) + cpu.x)
             }
             AddressingMode::AbsoluteY => {
-                let low_addr = cpu.read(cpu.pc + 1);
-                let hi_addr = cpu.read(cpu.pc + 2);
+                let low_addr = cpu.peek(cpu.pc + 1);
+                let hi_addr = cpu.peek(cpu.pc + 2);
                 InstructionArgument::Address(Addr::new(hi_addr, low_addr) + cpu.y)
             }
             AddressingMode::Indirect => {
-                let low_addr = cpu.read(cpu.pc + 1);
-                let hi_addr = cpu.read(cpu.pc + 2);
+                let low_addr = cpu.peek(cpu.pc + 1);
+                let hi_addr = cpu.peek(cpu.pc + 2);
                 let _addr = Addr::new(hi_addr, low_addr);
                 InstructionArgument::Address(
-                    (Addr::from(cpu.read(_addr + 1)) << 8) | cpu.read(_addr),
+                    (Addr::from(cpu.peek(_addr + 1)) << 8) | cpu.peek(_addr),
                 )
             }
             AddressingMode::IndirectX => {
-                let _addr = Addr::from(cpu.read(cpu.pc + 1) + cpu.x);
+                let _addr = Addr::from(cpu.peek(cpu.pc + 1) + cpu.x);
                 InstructionArgument::Address(
-                    (Addr::from(cpu.read(_addr + 1)) << 8) | cpu.read(_addr),
+                    (Addr::from(cpu.peek(_addr + 1)) << 8) | cpu.peek(_addr),
                 )
             }
             AddressingMode::IndirectY => {
-                let _addr = Addr::from(cpu.read(cpu.pc + 1));
-                let low_addr = cpu.read(_addr);
-                let hi_addr = cpu.read(_addr + 1);
+                let _addr = Addr::from(cpu.peek(cpu.pc + 1));
+                let low_addr = cpu.peek(_addr);
+                let hi_addr = cpu.peek(_addr + 1);
                 InstructionArgument::Address(Addr::new(hi_addr, low_addr) + cpu.y)
             }
-            AddressingMode::Relative => InstructionArgument::Offset(cpu.read(cpu.pc + 1)),
+            AddressingMode::Relative => InstructionArgument::Offset(cpu.peek(cpu.pc + 1)),
             AddressingMode::Implied => InstructionArgument::Implied,
         }
     }
@@ -202,518 +237,482 @@ impl From<&str> for Instruction {
 }
 
 impl Instruction {
-    pub fn exec(&self, arg: InstructionArgument, cpu: &mut CPU) -> bool {
+    /// Whether the instruction has the addressing mode `mode`, see `OPCODES`
+    pub fn valid_address_mode(&self, mode: AddressingMode) -> bool {
         match self {
-            Instruction::LDA => load_store::lda(arg, cpu),
-            Instruction::LDX => load_store::ldx(arg, cpu),
-            Instruction::LDY => load_store::ldy(arg, cpu),
-            Instruction::STA => load_store::sta(arg, cpu),
-            Instruction::STX => load_store::stx(arg, cpu),
-            Instruction::STY => load_store::sty(arg, cpu),
-
-            Instruction::TAX => stack_op::tax(arg, cpu),
-            Instruction::TAY => stack_op::tay(arg, cpu),
-            Instruction::TXA => stack_op::txa(arg, cpu),
-            Instruction::TYA => stack_op::tya(arg, cpu),
-            Instruction::TSX => stack_op::tsx(arg, cpu),
-            Instruction::TXS => stack_op::txs(arg, cpu),
-            Instruction::PHA => stack_op::pha(arg, cpu),
-            Instruction::PHP => stack_op::php(arg, cpu),
-            Instruction::PLA => stack_op::pla(arg, cpu),
-            Instruction::PLP => stack_op::plp(arg, cpu),
-
-            Instruction::AND => logical::and(arg, cpu),
-            Instruction::EOR => logical::eor(arg, cpu),
-            Instruction::ORA => logical::ora(arg, cpu),
-            Instruction::BIT => logical::bit(arg, cpu),
-            Instruction::CMP => logical::cmp(arg, cpu),
-            Instruction::CPX => logical::cpx(arg, cpu),
-            Instruction::CPY => logical::cpy(arg, cpu),
-
-            Instruction::ADC => arithmetic::adc(arg, cpu),
-            Instruction::SBC => arithmetic::sbc(arg, cpu),
-
-            Instruction::INC => increment_decrement::inc(arg, cpu),
-            Instruction::INX => increment_decrement::inx(arg, cpu),
-            Instruction::INY => increment_decrement::iny(arg, cpu),
-            Instruction::DEC => increment_decrement::dec(arg, cpu),
-            Instruction::DEX => increment_decrement::dex(arg, cpu),
-            Instruction::DEY => increment_decrement::dey(arg, cpu),
-
-            Instruction::ASL => shift::asl(arg, cpu),
-            Instruction::LSR => shift::lsr(arg, cpu),
-            Instruction::ROL => shift::rol(arg, cpu),
-            Instruction::ROR => shift::ror(arg, cpu),
-
-            Instruction::JMP => jump_call::jmp(arg, cpu),
-            Instruction::JSR => jump_call::jsr(arg, cpu),
-            Instruction::RTS => jump_call::rts(arg, cpu),
-            Instruction::BRK => jump_call::brk(arg, cpu),
-            Instruction::RTI => jump_call::rti(arg, cpu),
-
-            Instruction::BCC => branch::bcc(arg, cpu),
-            Instruction::BCS => branch::bcs(arg, cpu),
-            Instruction::BEQ => branch::beq(arg, cpu),
-            Instruction::BMI => branch::bmi(arg, cpu),
-            Instruction::BNE => branch::bne(arg, cpu),
-            Instruction::BPL => branch::bpl(arg, cpu),
-            Instruction::BVC => branch::bvc(arg, cpu),
-            Instruction::BVS => branch::bvs(arg, cpu),
-
-            Instruction::CLC => flag::clc(arg, cpu),
-            Instruction::CLD => flag::cld(arg, cpu),
-            Instruction::CLI => flag::cli(arg, cpu),
-            Instruction::CLV => flag::clv(arg, cpu),
-            Instruction::SEC => flag::sec(arg, cpu),
-            Instruction::SED => flag::sed(arg, cpu),
-            Instruction::SEI => flag::sei(arg, cpu),
-
-            Instruction::NOP => true,
-            Instruction::XXX(i) => {
-                println!("ILLEGAL: {:x}", i);
-                cpu.halt(Some("Illegal Instruction"));
-                false
-            }
+            Instruction::XXX(_) => mode == AddressingMode::Implied,
+            _ => self.op_code(mode).is_some(),
         }
     }
 
-    pub fn valid_address_mode(&self, addressing_mode: AddressingMode) -> bool {
-        match self {
-            Instruction::ADC => matches!(
-                addressing_mode,
-                AddressingMode::Immediate
-                    | AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-                    | AddressingMode::AbsoluteY
-                    | AddressingMode::IndirectX
-            ),
-
-            Instruction::AND => matches!(
-                addressing_mode,
-                AddressingMode::Immediate
-                    | AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-                    | AddressingMode::AbsoluteY
-                    | AddressingMode::IndirectX
-                    | AddressingMode::Indirect
-            ),
-
-            Instruction::ASL => matches!(
-                addressing_mode,
-                AddressingMode::Implied
-                    | AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-            ),
-
-            Instruction::TSX => matches!(addressing_mode, AddressingMode::Implied),
-
-            Instruction::CMP => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-                    | AddressingMode::AbsoluteY
-                    | AddressingMode::IndirectX
-                    | AddressingMode::IndirectY
-            ),
-
-            Instruction::RTS => matches!(addressing_mode, AddressingMode::Implied),
-
-            Instruction::DEX => matches!(addressing_mode, AddressingMode::Implied),
-
-            Instruction::DEY => matches!(addressing_mode, AddressingMode::Implied),
-
-            Instruction::STA => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-                    | AddressingMode::AbsoluteY
-                    | AddressingMode::IndirectX
-                    | AddressingMode::IndirectY
-            ),
-
-            Instruction::PHA => matches!(addressing_mode, AddressingMode::Implied),
-
-            Instruction::LDA => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-                    | AddressingMode::AbsoluteY
-                    | AddressingMode::IndirectX
-                    | AddressingMode::IndirectY
-            ),
-
-            Instruction::LSR => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-            ),
-
-            Instruction::ORA => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-                    | AddressingMode::AbsoluteY
-                    | AddressingMode::IndirectX
-                    | AddressingMode::IndirectY
-            ),
-
-            Instruction::SEC => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::SED => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::BNE => matches!(addressing_mode, AddressingMode::Relative),
-            Instruction::PLA => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::PHP => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::SEI => matches!(addressing_mode, AddressingMode::Implied),
-
-            Instruction::STX => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPageY | AddressingMode::Absolute
-            ),
-
-            Instruction::STY => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPageX | AddressingMode::Absolute
-            ),
-
-            Instruction::LDX => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageY
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteY
-            ),
+    /// The op code of the instruction in addressing mode `mode`, if it has it
+    pub fn op_code(self, mode: AddressingMode) -> Option<u8> {
+        DOCUMENTED
+            .iter()
+            .find(|&&(_, ins, m, _, _)| ins == self && m == mode)
+            .map(|&(op_code, ..)| op_code)
+    }
 
-            Instruction::LDY => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-            ),
+    /// Every addressing mode the instruction has
+    pub fn modes(self) -> impl Iterator<Item = AddressingMode> {
+        AddressingMode::ALL
+            .into_iter()
+            .filter(move |&mode| self.valid_address_mode(mode))
+    }
 
-            Instruction::ROL => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-            ),
+    /// The bytes of this instruction with `arg` in addressing mode `mode`,
+    /// op code first and operands little endian
+    pub fn encode(
+        self,
+        mode: AddressingMode,
+        arg: InstructionArgument,
+    ) -> Result<Vec<Byte>, String> {
+        let Some(op_code) = self.op_code(mode) else {
+            return Err(format!("{self} has no {mode:?} addressing mode"));
+        };
 
-            Instruction::BPL => matches!(addressing_mode, AddressingMode::Relative),
-            Instruction::PLP => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::CLC => matches!(addressing_mode, AddressingMode::Implied),
+        let mut bytes = vec![Byte(op_code)];
+        match (mode.size(), arg) {
+            (1, InstructionArgument::Implied) => (),
+            (2, InstructionArgument::Immediate(byte)) if mode == AddressingMode::Immediate => {
+                bytes.push(byte)
+            }
+            (2, InstructionArgument::Offset(byte)) if mode == AddressingMode::Relative => {
+                bytes.push(byte)
+            }
+            (2, InstructionArgument::Address(addr)) if mode != AddressingMode::Relative => {
+                if addr.0 > 0xFF {
+                    return Err(format!("${:04X} is not in the zero page", addr.0));
+                }
+                bytes.push(Byte(addr.0 as u8));
+            }
+            (3, InstructionArgument::Address(addr)) => {
+                bytes.extend(addr.0.to_le_bytes().map(Byte));
+            }
+            (_, arg) => return Err(format!("{self} {mode:?} can't take {arg:?}")),
+        }
+        Ok(bytes)
+    }
+}
 
-            Instruction::ROR => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-            ),
+/// What an instruction does, given its argument
+pub type Handler = fn(InstructionArgument, &mut CPU) -> bool;
+
+/// Everything about an op code, see `OPCODES`
+#[derive(Debug, Clone, Copy)]
+pub struct OpcodeInfo {
+    pub instruction: Instruction,
+    pub mode: AddressingMode,
+    /// Number of bytes, op code included
+    pub size: u8,
+    /// Cycles on a real 6502, before crossing a page or taking a branch
+    pub cycles: u8,
+    /// Whether the op code is one of the 151 documented ones
+    pub documented: bool,
+    pub exec: Handler,
+}
 
-            Instruction::CLD => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::BRK => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::BCC => matches!(addressing_mode, AddressingMode::Relative),
-            Instruction::CLI => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::BVC => matches!(addressing_mode, AddressingMode::Relative),
-            Instruction::TXA => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::TAX => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::NOP => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::TAY => matches!(addressing_mode, AddressingMode::Implied),
-
-            Instruction::EOR => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-                    | AddressingMode::AbsoluteY
-                    | AddressingMode::IndirectX
-                    | AddressingMode::IndirectY
-            ),
+/// What the CPU makes of each op code
+///
+/// Undocumented op codes are illegal (`Instruction::XXX`) and stop the CPU
+/// with an error. `CPU::undocumented_nops` runs the ones that don't jam a real
+/// 6502 as `NOP`s instead.
+pub static OPCODES: [OpcodeInfo; 256] = opcodes();
 
-            Instruction::CLV => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::BCS => matches!(addressing_mode, AddressingMode::Relative),
-            Instruction::JMP => matches!(
-                addressing_mode,
-                AddressingMode::Absolute | AddressingMode::Indirect
-            ),
+/// Op codes that jam a real 6502
+const JAMS: [u8; 12] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+];
 
-            Instruction::BVS => matches!(addressing_mode, AddressingMode::Relative),
-            Instruction::BEQ => matches!(addressing_mode, AddressingMode::Relative),
-            Instruction::TXS => matches!(addressing_mode, AddressingMode::Implied),
-
-            Instruction::SBC => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage
-                    | AddressingMode::ZeroPageX
-                    | AddressingMode::Absolute
-                    | AddressingMode::AbsoluteX
-                    | AddressingMode::AbsoluteY
-                    | AddressingMode::IndirectX
-                    | AddressingMode::IndirectY
-            ),
+#[rustfmt::skip]
+const DOCUMENTED: [(u8, Instruction, AddressingMode, u8, Handler); 151] = [
+    (0x69, Instruction::ADC, AddressingMode::Immediate, 2, arithmetic::adc),
+    (0x65, Instruction::ADC, AddressingMode::ZeroPage, 3, arithmetic::adc),
+    (0x75, Instruction::ADC, AddressingMode::ZeroPageX, 4, arithmetic::adc),
+    (0x6D, Instruction::ADC, AddressingMode::Absolute, 4, arithmetic::adc),
+    (0x7D, Instruction::ADC, AddressingMode::AbsoluteX, 4, arithmetic::adc),
+    (0x79, Instruction::ADC, AddressingMode::AbsoluteY, 4, arithmetic::adc),
+    (0x61, Instruction::ADC, AddressingMode::IndirectX, 6, arithmetic::adc),
+    (0x71, Instruction::ADC, AddressingMode::IndirectY, 5, arithmetic::adc),
+    (0x29, Instruction::AND, AddressingMode::Immediate, 2, logical::and),
+    (0x25, Instruction::AND, AddressingMode::ZeroPage, 3, logical::and),
+    (0x35, Instruction::AND, AddressingMode::ZeroPageX, 4, logical::and),
+    (0x2D, Instruction::AND, AddressingMode::Absolute, 4, logical::and),
+    (0x3D, Instruction::AND, AddressingMode::AbsoluteX, 4, logical::and),
+    (0x39, Instruction::AND, AddressingMode::AbsoluteY, 4, logical::and),
+    (0x21, Instruction::AND, AddressingMode::IndirectX, 6, logical::and),
+    (0x31, Instruction::AND, AddressingMode::IndirectY, 5, logical::and),
+    (0x0A, Instruction::ASL, AddressingMode::Implied, 2, shift::asl),
+    (0x06, Instruction::ASL, AddressingMode::ZeroPage, 5, shift::asl),
+    (0x16, Instruction::ASL, AddressingMode::ZeroPageX, 6, shift::asl),
+    (0x0E, Instruction::ASL, AddressingMode::Absolute, 6, shift::asl),
+    (0x1E, Instruction::ASL, AddressingMode::AbsoluteX, 7, shift::asl),
+    (0xBA, Instruction::TSX, AddressingMode::Implied, 2, stack_op::tsx),
+    (0xC9, Instruction::CMP, AddressingMode::Immediate, 2, logical::cmp),
+    (0xC5, Instruction::CMP, AddressingMode::ZeroPage, 3, logical::cmp),
+    (0xD5, Instruction::CMP, AddressingMode::ZeroPageX, 4, logical::cmp),
+    (0xCD, Instruction::CMP, AddressingMode::Absolute, 4, logical::cmp),
+    (0xDD, Instruction::CMP, AddressingMode::AbsoluteX, 4, logical::cmp),
+    (0xD9, Instruction::CMP, AddressingMode::AbsoluteY, 4, logical::cmp),
+    (0xC1, Instruction::CMP, AddressingMode::IndirectX, 6, logical::cmp),
+    (0xD1, Instruction::CMP, AddressingMode::IndirectY, 5, logical::cmp),
+    (0x60, Instruction::RTS, AddressingMode::Implied, 6, jump_call::rts),
+    (0xCA, Instruction::DEX, AddressingMode::Implied, 2, increment_decrement::dex),
+    (0x88, Instruction::DEY, AddressingMode::Implied, 2, increment_decrement::dey),
+    (0x85, Instruction::STA, AddressingMode::ZeroPage, 3, load_store::sta),
+    (0x95, Instruction::STA, AddressingMode::ZeroPageX, 4, load_store::sta),
+    (0x8D, Instruction::STA, AddressingMode::Absolute, 4, load_store::sta),
+    (0x9D, Instruction::STA, AddressingMode::AbsoluteX, 5, load_store::sta),
+    (0x99, Instruction::STA, AddressingMode::AbsoluteY, 5, load_store::sta),
+    (0x81, Instruction::STA, AddressingMode::IndirectX, 6, load_store::sta),
+    (0x91, Instruction::STA, AddressingMode::IndirectY, 6, load_store::sta),
+    (0x48, Instruction::PHA, AddressingMode::Implied, 3, stack_op::pha),
+    (0xA9, Instruction::LDA, AddressingMode::Immediate, 2, load_store::lda),
+    (0xA5, Instruction::LDA, AddressingMode::ZeroPage, 3, load_store::lda),
+    (0xB5, Instruction::LDA, AddressingMode::ZeroPageX, 4, load_store::lda),
+    (0xAD, Instruction::LDA, AddressingMode::Absolute, 4, load_store::lda),
+    (0xBD, Instruction::LDA, AddressingMode::AbsoluteX, 4, load_store::lda),
+    (0xB9, Instruction::LDA, AddressingMode::AbsoluteY, 4, load_store::lda),
+    (0xA1, Instruction::LDA, AddressingMode::IndirectX, 6, load_store::lda),
+    (0xB1, Instruction::LDA, AddressingMode::IndirectY, 5, load_store::lda),
+    (0x4A, Instruction::LSR, AddressingMode::Implied, 2, shift::lsr),
+    (0x46, Instruction::LSR, AddressingMode::ZeroPage, 5, shift::lsr),
+    (0x56, Instruction::LSR, AddressingMode::ZeroPageX, 6, shift::lsr),
+    (0x4E, Instruction::LSR, AddressingMode::Absolute, 6, shift::lsr),
+    (0x5E, Instruction::LSR, AddressingMode::AbsoluteX, 7, shift::lsr),
+    (0x09, Instruction::ORA, AddressingMode::Immediate, 2, logical::ora),
+    (0x05, Instruction::ORA, AddressingMode::ZeroPage, 3, logical::ora),
+    (0x15, Instruction::ORA, AddressingMode::ZeroPageX, 4, logical::ora),
+    (0x0D, Instruction::ORA, AddressingMode::Absolute, 4, logical::ora),
+    (0x1D, Instruction::ORA, AddressingMode::AbsoluteX, 4, logical::ora),
+    (0x19, Instruction::ORA, AddressingMode::AbsoluteY, 4, logical::ora),
+    (0x01, Instruction::ORA, AddressingMode::IndirectX, 6, logical::ora),
+    (0x11, Instruction::ORA, AddressingMode::IndirectY, 5, logical::ora),
+    (0x38, Instruction::SEC, AddressingMode::Implied, 2, flag::sec),
+    (0xF8, Instruction::SED, AddressingMode::Implied, 2, flag::sed),
+    (0xD0, Instruction::BNE, AddressingMode::Relative, 2, branch::bne),
+    (0x68, Instruction::PLA, AddressingMode::Implied, 4, stack_op::pla),
+    (0x08, Instruction::PHP, AddressingMode::Implied, 3, stack_op::php),
+    (0x78, Instruction::SEI, AddressingMode::Implied, 2, flag::sei),
+    (0x86, Instruction::STX, AddressingMode::ZeroPage, 3, load_store::stx),
+    (0x96, Instruction::STX, AddressingMode::ZeroPageY, 4, load_store::stx),
+    (0x8E, Instruction::STX, AddressingMode::Absolute, 4, load_store::stx),
+    (0x84, Instruction::STY, AddressingMode::ZeroPage, 3, load_store::sty),
+    (0x94, Instruction::STY, AddressingMode::ZeroPageX, 4, load_store::sty),
+    (0x8C, Instruction::STY, AddressingMode::Absolute, 4, load_store::sty),
+    (0xA2, Instruction::LDX, AddressingMode::Immediate, 2, load_store::ldx),
+    (0xA6, Instruction::LDX, AddressingMode::ZeroPage, 3, load_store::ldx),
+    (0xB6, Instruction::LDX, AddressingMode::ZeroPageY, 4, load_store::ldx),
+    (0xAE, Instruction::LDX, AddressingMode::Absolute, 4, load_store::ldx),
+    (0xBE, Instruction::LDX, AddressingMode::AbsoluteY, 4, load_store::ldx),
+    (0xA0, Instruction::LDY, AddressingMode::Immediate, 2, load_store::ldy),
+    (0xA4, Instruction::LDY, AddressingMode::ZeroPage, 3, load_store::ldy),
+    (0xB4, Instruction::LDY, AddressingMode::ZeroPageX, 4, load_store::ldy),
+    (0xAC, Instruction::LDY, AddressingMode::Absolute, 4, load_store::ldy),
+    (0xBC, Instruction::LDY, AddressingMode::AbsoluteX, 4, load_store::ldy),
+    (0x2A, Instruction::ROL, AddressingMode::Implied, 2, shift::rol),
+    (0x26, Instruction::ROL, AddressingMode::ZeroPage, 5, shift::rol),
+    (0x36, Instruction::ROL, AddressingMode::ZeroPageX, 6, shift::rol),
+    (0x2E, Instruction::ROL, AddressingMode::Absolute, 6, shift::rol),
+    (0x3E, Instruction::ROL, AddressingMode::AbsoluteX, 7, shift::rol),
+    (0x10, Instruction::BPL, AddressingMode::Relative, 2, branch::bpl),
+    (0x28, Instruction::PLP, AddressingMode::Implied, 4, stack_op::plp),
+    (0x18, Instruction::CLC, AddressingMode::Implied, 2, flag::clc),
+    (0x6A, Instruction::ROR, AddressingMode::Implied, 2, shift::ror),
+    (0x66, Instruction::ROR, AddressingMode::ZeroPage, 5, shift::ror),
+    (0x76, Instruction::ROR, AddressingMode::ZeroPageX, 6, shift::ror),
+    (0x6E, Instruction::ROR, AddressingMode::Absolute, 6, shift::ror),
+    (0x7E, Instruction::ROR, AddressingMode::AbsoluteX, 7, shift::ror),
+    (0xD8, Instruction::CLD, AddressingMode::Implied, 2, flag::cld),
+    (0x00, Instruction::BRK, AddressingMode::Implied, 7, jump_call::brk),
+    (0x90, Instruction::BCC, AddressingMode::Relative, 2, branch::bcc),
+    (0x58, Instruction::CLI, AddressingMode::Implied, 2, flag::cli),
+    (0x50, Instruction::BVC, AddressingMode::Relative, 2, branch::bvc),
+    (0x8A, Instruction::TXA, AddressingMode::Implied, 2, stack_op::txa),
+    (0xAA, Instruction::TAX, AddressingMode::Implied, 2, stack_op::tax),
+    (0xEA, Instruction::NOP, AddressingMode::Implied, 2, nop),
+    (0xA8, Instruction::TAY, AddressingMode::Implied, 2, stack_op::tay),
+    (0x49, Instruction::EOR, AddressingMode::Immediate, 2, logical::eor),
+    (0x45, Instruction::EOR, AddressingMode::ZeroPage, 3, logical::eor),
+    (0x55, Instruction::EOR, AddressingMode::ZeroPageX, 4, logical::eor),
+    (0x4D, Instruction::EOR, AddressingMode::Absolute, 4, logical::eor),
+    (0x5D, Instruction::EOR, AddressingMode::AbsoluteX, 4, logical::eor),
+    (0x59, Instruction::EOR, AddressingMode::AbsoluteY, 4, logical::eor),
+    (0x41, Instruction::EOR, AddressingMode::IndirectX, 6, logical::eor),
+    (0x51, Instruction::EOR, AddressingMode::IndirectY, 5, logical::eor),
+    (0xB8, Instruction::CLV, AddressingMode::Implied, 2, flag::clv),
+    (0xB0, Instruction::BCS, AddressingMode::Relative, 2, branch::bcs),
+    (0x4C, Instruction::JMP, AddressingMode::Absolute, 3, jump_call::jmp),
+    (0x6C, Instruction::JMP, AddressingMode::Indirect, 5, jump_call::jmp),
+    (0x70, Instruction::BVS, AddressingMode::Relative, 2, branch::bvs),
+    (0xF0, Instruction::BEQ, AddressingMode::Relative, 2, branch::beq),
+    (0x9A, Instruction::TXS, AddressingMode::Implied, 2, stack_op::txs),
+    (0xE9, Instruction::SBC, AddressingMode::Immediate, 2, arithmetic::sbc),
+    (0xE5, Instruction::SBC, AddressingMode::ZeroPage, 3, arithmetic::sbc),
+    (0xF5, Instruction::SBC, AddressingMode::ZeroPageX, 4, arithmetic::sbc),
+    (0xED, Instruction::SBC, AddressingMode::Absolute, 4, arithmetic::sbc),
+    (0xFD, Instruction::SBC, AddressingMode::AbsoluteX, 4, arithmetic::sbc),
+    (0xF9, Instruction::SBC, AddressingMode::AbsoluteY, 4, arithmetic::sbc),
+    (0xE1, Instruction::SBC, AddressingMode::IndirectX, 6, arithmetic::sbc),
+    (0xF1, Instruction::SBC, AddressingMode::IndirectY, 5, arithmetic::sbc),
+    (0xE0, Instruction::CPX, AddressingMode::Immediate, 2, logical::cpx),
+    (0xE4, Instruction::CPX, AddressingMode::ZeroPage, 3, logical::cpx),
+    (0xEC, Instruction::CPX, AddressingMode::Absolute, 4, logical::cpx),
+    (0xC0, Instruction::CPY, AddressingMode::Immediate, 2, logical::cpy),
+    (0xC4, Instruction::CPY, AddressingMode::ZeroPage, 3, logical::cpy),
+    (0xCC, Instruction::CPY, AddressingMode::Absolute, 4, logical::cpy),
+    (0x24, Instruction::BIT, AddressingMode::ZeroPage, 3, logical::bit),
+    (0x2C, Instruction::BIT, AddressingMode::Absolute, 4, logical::bit),
+    (0x30, Instruction::BMI, AddressingMode::Relative, 2, branch::bmi),
+    (0x20, Instruction::JSR, AddressingMode::Absolute, 6, jump_call::jsr),
+    (0xE6, Instruction::INC, AddressingMode::ZeroPage, 5, increment_decrement::inc),
+    (0xF6, Instruction::INC, AddressingMode::ZeroPageX, 6, increment_decrement::inc),
+    (0xEE, Instruction::INC, AddressingMode::Absolute, 6, increment_decrement::inc),
+    (0xFE, Instruction::INC, AddressingMode::AbsoluteX, 7, increment_decrement::inc),
+    (0xC6, Instruction::DEC, AddressingMode::ZeroPage, 5, increment_decrement::dec),
+    (0xD6, Instruction::DEC, AddressingMode::ZeroPageX, 6, increment_decrement::dec),
+    (0xCE, Instruction::DEC, AddressingMode::Absolute, 6, increment_decrement::dec),
+    (0xDE, Instruction::DEC, AddressingMode::AbsoluteX, 7, increment_decrement::dec),
+    (0xE8, Instruction::INX, AddressingMode::Implied, 2, increment_decrement::inx),
+    (0xC8, Instruction::INY, AddressingMode::Implied, 2, increment_decrement::iny),
+    (0x40, Instruction::RTI, AddressingMode::Implied, 6, jump_call::rti),
+    (0x98, Instruction::TYA, AddressingMode::Implied, 2, stack_op::tya),
+];
+
+/// The addressing mode of the undocumented `op_code`, from the column it sits
+/// in like the documented ones
+const fn undocumented_mode(op_code: u8) -> AddressingMode {
+    // $96, $97, $9E, $9F and $B6, $B7, $BE, $BF index by Y, like STX and LDX
+    let y_indexed = op_code & 0xD6 == 0x96;
+    let odd = op_code & 1 == 1;
+    match (op_code >> 2) & 7 {
+        0 if odd => AddressingMode::IndirectX,
+        0 => AddressingMode::Immediate,
+        1 => AddressingMode::ZeroPage,
+        2 if odd => AddressingMode::Immediate,
+        3 => AddressingMode::Absolute,
+        4 if odd => AddressingMode::IndirectY,
+        5 if y_indexed => AddressingMode::ZeroPageY,
+        5 => AddressingMode::ZeroPageX,
+        6 if odd => AddressingMode::AbsoluteY,
+        7 if y_indexed => AddressingMode::AbsoluteY,
+        7 => AddressingMode::AbsoluteX,
+        _ => AddressingMode::Implied,
+    }
+}
 
-            Instruction::CPX => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage | AddressingMode::Absolute
-            ),
+const fn opcodes() -> [OpcodeInfo; 256] {
+    let illegal = OpcodeInfo {
+        instruction: Instruction::XXX(0),
+        mode: AddressingMode::Implied,
+        size: 1,
+        cycles: 2,
+        documented: false,
+        exec: illegal,
+    };
+    let mut table = [illegal; 256];
+
+    let mut i = 0;
+    while i < table.len() {
+        let mode = undocumented_mode(i as u8);
+        table[i] = OpcodeInfo {
+            instruction: Instruction::XXX(i as u8),
+            mode,
+            size: mode.size(),
+            ..illegal
+        };
+        i += 1;
+    }
 
-            Instruction::CPY => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPage | AddressingMode::Absolute
-            ),
+    let mut i = 0;
+    while i < DOCUMENTED.len() {
+        let (op_code, instruction, mode, cycles, exec) = DOCUMENTED[i];
+        table[op_code as usize] = OpcodeInfo {
+            instruction,
+            mode,
+            size: mode.size(),
+            cycles,
+            documented: true,
+            exec,
+        };
+        i += 1;
+    }
 
-            Instruction::BIT => matches!(addressing_mode, AddressingMode::Absolute),
+    let mut i = 0;
+    while i < JAMS.len() {
+        table[JAMS[i] as usize] = OpcodeInfo {
+            instruction: Instruction::XXX(JAMS[i]),
+            ..illegal
+        };
+        i += 1;
+    }
+    table
+}
 
-            Instruction::BMI => matches!(addressing_mode, AddressingMode::Relative),
+fn nop(_arg: InstructionArgument, _cpu: &mut CPU) -> bool {
+    true
+}
 
-            Instruction::JSR => matches!(addressing_mode, AddressingMode::Absolute),
+fn illegal(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
+    let op_code = cpu.peek(cpu.previous_pc).0;
+    if JAMS.contains(&op_code) {
+        cpu.jam(ErrorKind::Jam(op_code));
+    } else if cpu.undocumented_nops {
+        return true;
+    } else {
+        cpu.jam(ErrorKind::IllegalOpcode(op_code));
+    }
+    false
+}
 
-            Instruction::INC => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPageX | AddressingMode::Absolute | AddressingMode::AbsoluteX
-            ),
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        for &(op_code, ins, mode, _, _) in &DOCUMENTED {
+            let arg = match mode {
+                AddressingMode::Implied => InstructionArgument::Implied,
+                AddressingMode::Immediate => InstructionArgument::Immediate(Byte(0x12)),
+                AddressingMode::Relative => InstructionArgument::Offset(Byte(0x12)),
+                _ if mode.size() == 2 => InstructionArgument::Address(Addr(0x12)),
+                _ => InstructionArgument::Address(Addr(0x1234)),
+            };
+            let bytes = ins.encode(mode, arg).unwrap();
+            assert_eq!(bytes.len(), mode.size() as usize);
+            let info = &OPCODES[bytes[0].0 as usize];
+            assert_eq!(
+                (info.instruction, info.mode, info.size),
+                (ins, mode, mode.size())
+            );
+            assert_eq!(bytes[0], Byte(op_code));
+        }
 
-            Instruction::DEC => matches!(
-                addressing_mode,
-                AddressingMode::ZeroPageX | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY
+        assert_eq!(
+            Instruction::JMP.encode(
+                AddressingMode::Indirect,
+                InstructionArgument::Address(Addr(0x1234))
             ),
-
-            Instruction::INX => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::INY => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::RTI => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::TYA => matches!(addressing_mode, AddressingMode::Implied),
-            Instruction::XXX(_) => matches!(addressing_mode, AddressingMode::Implied),
-        }
+            Ok(vec![Byte(0x6C), Byte(0x34), Byte(0x12)])
+        );
+        assert!(Instruction::LDA
+            .encode(
+                AddressingMode::ZeroPage,
+                InstructionArgument::Address(Addr(0x100))
+            )
+            .is_err());
+        assert!(Instruction::STA
+            .encode(
+                AddressingMode::Immediate,
+                InstructionArgument::Immediate(Byte(1))
+            )
+            .is_err());
+        assert!(Instruction::LDA
+            .encode(AddressingMode::Immediate, InstructionArgument::Implied)
+            .is_err());
     }
 
-    #[todop::todo]
-    pub fn new(_ins: Instruction, _arg: InstructionArgument) -> Self {}
-}
+    #[test]
+    fn test_opcodes() {
+        assert_eq!(OPCODES.iter().filter(|info| info.documented).count(), 151);
+        for (op_code, info) in OPCODES.iter().enumerate() {
+            assert_eq!(info.size, info.mode.size(), "{op_code:#04X}");
+            match info.instruction {
+                Instruction::XXX(code) => {
+                    assert_eq!(code as usize, op_code);
+                    assert!(!info.documented, "{op_code:#04X}");
+                }
+                _ => assert!(info.documented, "{op_code:#04X}"),
+            }
+        }
 
-pub fn get_instruction(op_code: Byte) -> (Instruction, AddressingMode) {
-    if OPCODES.contains_key(&op_code) {
-        OPCODES[&op_code.0]
-    } else if matches!(
-        op_code.0,
-        0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2
-    ) {
-        (Instruction::XXX(op_code.0), AddressingMode::Implied)
-    } else {
-        (Instruction::NOP, AddressingMode::Implied)
+        let cycles = |op_code: u8| OPCODES[op_code as usize].cycles;
+        assert_eq!(cycles(0x00), 7); // BRK
+        assert_eq!(cycles(0x6C), 5); // JMP (ind)
+        assert_eq!(cycles(0x91), 6); // STA (zp),Y
+        assert_eq!(cycles(0xB1), 5); // LDA (zp),Y
+        assert_eq!(cycles(0xFE), 7); // INC abs,X
+
+        let size = |op_code: u8| OPCODES[op_code as usize].size;
+        assert_eq!(size(0x02), 1); // JAM
+        assert_eq!(size(0x1A), 1); // NOP
+        assert_eq!(size(0x80), 2); // NOP #imm
+        assert_eq!(size(0xA7), 2); // LAX zp
+        assert_eq!(size(0xB7), 2); // LAX zp,Y
+        assert_eq!(size(0xB3), 2); // LAX (zp),Y
+        assert_eq!(size(0x0C), 3); // NOP abs
+        assert_eq!(size(0x9E), 3); // SHX abs,Y
+        assert_eq!(size(0xFF), 3); // ISC abs,X
     }
-}
 
-pub static OPCODES: phf::Map<u8, (Instruction, AddressingMode)> = phf_map! {
-    0x69u8 => (Instruction::ADC, AddressingMode::Immediate),
-    0x65u8 => (Instruction::ADC, AddressingMode::ZeroPage),
-    0x75u8 => (Instruction::ADC, AddressingMode::ZeroPageX),
-    0x6Du8 => (Instruction::ADC, AddressingMode::Absolute),
-    0x7Du8 => (Instruction::ADC, AddressingMode::AbsoluteX),
-    0x79u8 => (Instruction::ADC, AddressingMode::AbsoluteY),
-    0x61u8 => (Instruction::ADC, AddressingMode::IndirectX),
-    0x71u8 => (Instruction::ADC, AddressingMode::IndirectY),
-
-    0x29u8 => (Instruction::AND, AddressingMode::Immediate),
-    0x25u8 => (Instruction::AND, AddressingMode::ZeroPage),
-    0x35u8 => (Instruction::AND, AddressingMode::ZeroPageX),
-    0x2Du8 => (Instruction::AND, AddressingMode::Absolute),
-    0x3Du8 => (Instruction::AND, AddressingMode::AbsoluteX),
-    0x39u8 => (Instruction::AND, AddressingMode::AbsoluteY),
-    0x21u8 => (Instruction::AND, AddressingMode::IndirectX),
-    0x31u8 => (Instruction::AND, AddressingMode::IndirectY),
-
-    0x0Au8 => (Instruction::ASL, AddressingMode::Implied),
-    0x06u8 => (Instruction::ASL, AddressingMode::ZeroPage),
-    0x16u8 => (Instruction::ASL, AddressingMode::ZeroPageX),
-    0x0Eu8 => (Instruction::ASL, AddressingMode::Absolute),
-    0x1Eu8 => (Instruction::ASL, AddressingMode::AbsoluteX),
-
-    0xBAu8 => (Instruction::TSX, AddressingMode::Implied),
-
-    0xC9u8 => (Instruction::CMP, AddressingMode::Immediate),
-    0xC5u8 => (Instruction::CMP, AddressingMode::ZeroPage),
-    0xD5u8 => (Instruction::CMP, AddressingMode::ZeroPageX),
-    0xCDu8 => (Instruction::CMP, AddressingMode::Absolute),
-    0xDDu8 => (Instruction::CMP, AddressingMode::AbsoluteX),
-    0xD9u8 => (Instruction::CMP, AddressingMode::AbsoluteY),
-    0xC1u8 => (Instruction::CMP, AddressingMode::IndirectX),
-    0xD1u8 => (Instruction::CMP, AddressingMode::IndirectY),
-
-    0x60u8 => (Instruction::RTS, AddressingMode::Implied),
-
-    0xCAu8 => (Instruction::DEX, AddressingMode::Implied),
-
-    0x88u8 => (Instruction::DEY, AddressingMode::Implied),
-
-    0x85u8 => (Instruction::STA, AddressingMode::ZeroPage),
-    0x95u8 => (Instruction::STA, AddressingMode::ZeroPageX),
-    0x8Du8 => (Instruction::STA, AddressingMode::Absolute),
-    0x9Du8 => (Instruction::STA, AddressingMode::AbsoluteX),
-    0x99u8 => (Instruction::STA, AddressingMode::AbsoluteY),
-    0x81u8 => (Instruction::STA, AddressingMode::IndirectX),
-    0x91u8 => (Instruction::STA, AddressingMode::IndirectY),
-
-    0x48u8 => (Instruction::PHA, AddressingMode::Implied),
-
-    0xA9u8 => (Instruction::LDA, AddressingMode::Immediate),
-    0xA5u8 => (Instruction::LDA, AddressingMode::ZeroPage),
-    0xB5u8 => (Instruction::LDA, AddressingMode::ZeroPageX),
-    0xADu8 => (Instruction::LDA, AddressingMode::Absolute),
-    0xBDu8 => (Instruction::LDA, AddressingMode::AbsoluteX),
-    0xB9u8 => (Instruction::LDA, AddressingMode::AbsoluteY),
-    0xA1u8 => (Instruction::LDA, AddressingMode::IndirectX),
-    0xB1u8 => (Instruction::LDA, AddressingMode::IndirectY),
-
-    0x4Au8 => (Instruction::LSR, AddressingMode::Implied),
-    0x46u8 => (Instruction::LSR, AddressingMode::ZeroPage),
-    0x56u8 => (Instruction::LSR, AddressingMode::ZeroPageX),
-    0x4Eu8 => (Instruction::LSR, AddressingMode::Absolute),
-    0x5Eu8 => (Instruction::LSR, AddressingMode::AbsoluteX),
-
-    0x09u8 => (Instruction::ORA, AddressingMode::Immediate),
-    0x05u8 => (Instruction::ORA, AddressingMode::ZeroPage),
-    0x15u8 => (Instruction::ORA, AddressingMode::ZeroPageX),
-    0x0Du8 => (Instruction::ORA, AddressingMode::Absolute),
-    0x1Du8 => (Instruction::ORA, AddressingMode::AbsoluteX),
-    0x19u8 => (Instruction::ORA, AddressingMode::AbsoluteY),
-    0x01u8 => (Instruction::ORA, AddressingMode::IndirectX),
-    0x11u8 => (Instruction::ORA, AddressingMode::IndirectY),
-
-    0x38u8 => (Instruction::SEC, AddressingMode::Implied),
-    0xF8u8 => (Instruction::SED, AddressingMode::Implied),
-    0xD0u8 => (Instruction::BNE, AddressingMode::Relative),
-    0x68u8 => (Instruction::PLA, AddressingMode::Implied),
-    0x08u8 => (Instruction::PHP, AddressingMode::Implied),
-    0x78u8 => (Instruction::SEI, AddressingMode::Implied),
-
-    0x86u8 => (Instruction::STX, AddressingMode::ZeroPage),
-    0x96u8 => (Instruction::STX, AddressingMode::ZeroPageY),
-    0x8Eu8 => (Instruction::STX, AddressingMode::Absolute),
-
-    0x84u8 => (Instruction::STY, AddressingMode::ZeroPage),
-    0x94u8 => (Instruction::STY, AddressingMode::ZeroPageX),
-    0x8Cu8 => (Instruction::STY, AddressingMode::Absolute),
-
-    0xA2u8 => (Instruction::LDX, AddressingMode::Immediate),
-    0xA6u8 => (Instruction::LDX, AddressingMode::ZeroPage),
-    0xB6u8 => (Instruction::LDX, AddressingMode::ZeroPageY),
-    0xAEu8 => (Instruction::LDX, AddressingMode::Absolute),
-    0xBEu8 => (Instruction::LDX, AddressingMode::AbsoluteY),
-
-    0xA0u8 => (Instruction::LDY, AddressingMode::Immediate),
-    0xA4u8 => (Instruction::LDY, AddressingMode::ZeroPage),
-    0xB4u8 => (Instruction::LDY, AddressingMode::ZeroPageX),
-    0xACu8 => (Instruction::LDY, AddressingMode::Absolute),
-    0xBCu8 => (Instruction::LDY, AddressingMode::AbsoluteX),
-
-    0x2Au8 => (Instruction::ROL, AddressingMode::Implied),
-    0x26u8 => (Instruction::ROL, AddressingMode::ZeroPage),
-    0x36u8 => (Instruction::ROL, AddressingMode::ZeroPageX),
-    0x2Eu8 => (Instruction::ROL, AddressingMode::Absolute),
-    0x3Eu8 => (Instruction::ROL, AddressingMode::AbsoluteX),
-
-    0x10u8 => (Instruction::BPL, AddressingMode::Relative),
-    0x28u8 => (Instruction::PLP, AddressingMode::Implied),
-    0x18u8 => (Instruction::CLC, AddressingMode::Implied),
-
-    0x6Au8 => (Instruction::ROR, AddressingMode::Implied),
-    0x66u8 => (Instruction::ROR, AddressingMode::ZeroPage),
-    0x76u8 => (Instruction::ROR, AddressingMode::ZeroPageX),
-    0x6Eu8 => (Instruction::ROR, AddressingMode::Absolute),
-    0x7Eu8 => (Instruction::ROR, AddressingMode::AbsoluteX),
-
-    0xD8u8 => (Instruction::CLD, AddressingMode::Implied),
-    0x00u8 => (Instruction::BRK, AddressingMode::Implied),
-    0x90u8 => (Instruction::BCC, AddressingMode::Relative),
-    0x58u8 => (Instruction::CLI, AddressingMode::Implied),
-    0x50u8 => (Instruction::BVC, AddressingMode::Relative),
-    0x8Au8 => (Instruction::TXA, AddressingMode::Implied),
-    0xAAu8 => (Instruction::TAX, AddressingMode::Implied),
-    0xEAu8 => (Instruction::NOP, AddressingMode::Implied),
-    0xA8u8 => (Instruction::TAY, AddressingMode::Implied),
-
-    0x49u8 => (Instruction::EOR, AddressingMode::Immediate),
-    0x45u8 => (Instruction::EOR, AddressingMode::ZeroPage),
-    0x55u8 => (Instruction::EOR, AddressingMode::ZeroPageX),
-    0x4Du8 => (Instruction::EOR, AddressingMode::Absolute),
-    0x5Du8 => (Instruction::EOR, AddressingMode::AbsoluteX),
-    0x59u8 => (Instruction::EOR, AddressingMode::AbsoluteY),
-    0x41u8 => (Instruction::EOR, AddressingMode::IndirectX),
-    0x51u8 => (Instruction::EOR, AddressingMode::IndirectY),
-
-    0xB8u8 => (Instruction::CLV, AddressingMode::Implied),
-    0xB0u8 => (Instruction::BCS, AddressingMode::Relative),
-    0x4Cu8 => (Instruction::JMP, AddressingMode::Absolute),
-    0x6Cu8 => (Instruction::JMP, AddressingMode::Indirect),
-
-    0x70u8 => (Instruction::BVS, AddressingMode::Relative),
-    0xF0u8 => (Instruction::BEQ, AddressingMode::Relative),
-    0x9Au8 => (Instruction::TXS, AddressingMode::Implied),
-
-    0xE9u8 => (Instruction::SBC, AddressingMode::Immediate),
-    0xE5u8 => (Instruction::SBC, AddressingMode::ZeroPage),
-    0xF5u8 => (Instruction::SBC, AddressingMode::ZeroPageX),
-    0xEDu8 => (Instruction::SBC, AddressingMode::Absolute),
-    0xFDu8 => (Instruction::SBC, AddressingMode::AbsoluteX),
-    0xF9u8 => (Instruction::SBC, AddressingMode::AbsoluteY),
-    0xE1u8 => (Instruction::SBC, AddressingMode::IndirectX),
-    0xF1u8 => (Instruction::SBC, AddressingMode::IndirectY),
-
-    0xE0u8 => (Instruction::CPX, AddressingMode::Immediate),
-    0xE4u8 => (Instruction::CPX, AddressingMode::ZeroPage),
-    0xECu8 => (Instruction::CPX, AddressingMode::Absolute),
-
-    0xC0u8 => (Instruction::CPY, AddressingMode::Immediate),
-    0xC4u8 => (Instruction::CPY, AddressingMode::ZeroPage),
-    0xCCu8 => (Instruction::CPY, AddressingMode::Absolute),
-
-    0x24u8 => (Instruction::BIT, AddressingMode::ZeroPage),
-    0x2Cu8 => (Instruction::BIT, AddressingMode::Absolute),
-
-    0x30u8 => (Instruction::BMI, AddressingMode::Relative),
-
-    0x20u8 => (Instruction::JSR, AddressingMode::Absolute),
-
-    0xE6u8 => (Instruction::INC, AddressingMode::ZeroPage),
-    0xF6u8 => (Instruction::INC, AddressingMode::ZeroPageX),
-    0xEEu8 => (Instruction::INC, AddressingMode::Absolute),
-    0xFEu8 => (Instruction::INC, AddressingMode::AbsoluteX),
-
-    0xC6u8 => (Instruction::DEC, AddressingMode::ZeroPage),
-    0xD6u8 => (Instruction::DEC, AddressingMode::ZeroPageX),
-    0xCEu8 => (Instruction::DEC, AddressingMode::Absolute),
-    0xDEu8 => (Instruction::DEC, AddressingMode::AbsoluteX),
-
-    0xE8u8 => (Instruction::INX, AddressingMode::Implied),
-    0xC8u8 => (Instruction::INY, AddressingMode::Implied),
-    0x40u8 => (Instruction::RTI, AddressingMode::Implied),
-    0x98u8 => (Instruction::TYA, AddressingMode::Implied),
-    0xffu8 => (Instruction::XXX(0xff), AddressingMode::Implied),
-};
-
-#[cfg(test)]
-pub const INSTRUCTIONS: std::cell::LazyCell<
-    std::collections::HashMap<(Instruction, AddressingMode), u8>,
-> = std::cell::LazyCell::new(|| {
-    let mut map = std::collections::HashMap::new();
-    for (key, val) in OPCODES.entries() {
-        map.insert(*val, *key);
+    /// Every instruction's addressing modes, as the data sheet has them
+    const MODES: &str = "
+        ADC AND CMP EOR LDA ORA SBC: imm zp zpx abs absx absy indx indy
+        STA: zp zpx abs absx absy indx indy
+        ASL LSR ROL ROR: imp zp zpx abs absx
+        DEC INC: zp zpx abs absx
+        LDX: imm zp zpy abs absy
+        LDY: imm zp zpx abs absx
+        STX: zp zpy abs
+        STY: zp zpx abs
+        CPX CPY: imm zp abs
+        BIT: zp abs
+        JMP: abs ind
+        JSR: abs
+        BCC BCS BEQ BMI BNE BPL BVC BVS: rel
+        BRK CLC CLD CLI CLV DEX DEY INX INY NOP PHA PHP PLA PLP: imp
+        RTI RTS SEC SED SEI TAX TAY TSX TXA TXS TYA: imp
+    ";
+
+    #[test]
+    fn test_address_modes() {
+        let mut instructions = 0;
+        let mut op_codes = 0;
+        for line in MODES.trim().lines() {
+            let (names, modes) = line.split_once(':').unwrap();
+            let modes: Vec<AddressingMode> = modes
+                .split_whitespace()
+                .map(|mode| match mode {
+                    "imm" => AddressingMode::Immediate,
+                    "zp" => AddressingMode::ZeroPage,
+                    "zpx" => AddressingMode::ZeroPageX,
+                    "zpy" => AddressingMode::ZeroPageY,
+                    "abs" => AddressingMode::Absolute,
+                    "absx" => AddressingMode::AbsoluteX,
+                    "absy" => AddressingMode::AbsoluteY,
+                    "ind" => AddressingMode::Indirect,
+                    "indx" => AddressingMode::IndirectX,
+                    "indy" => AddressingMode::IndirectY,
+                    "rel" => AddressingMode::Relative,
+                    "imp" => AddressingMode::Implied,
+                    _ => unreachable!("{mode}"),
+                })
+                .collect();
+
+            for name in names.split_whitespace() {
+                let ins = Instruction::from(name);
+                assert_eq!(ins.to_string(), name);
+                instructions += 1;
+                for mode in AddressingMode::ALL {
+                    assert_eq!(
+                        ins.valid_address_mode(mode),
+                        modes.contains(&mode),
+                        "{ins} {mode:?}"
+                    );
+                    let Some(op_code) = ins.op_code(mode) else {
+                        continue;
+                    };
+                    let info = &OPCODES[op_code as usize];
+                    assert!(info.documented);
+                    assert_eq!((info.instruction, info.mode), (ins, mode));
+                    assert_eq!(info.size, mode.size());
+                    op_codes += 1;
+                }
+                assert_eq!(ins.modes().collect::<Vec<_>>().len(), modes.len());
+            }
+        }
+        assert_eq!((instructions, op_codes), (56, 151));
+        assert!(Instruction::XXX(0x02).valid_address_mode(AddressingMode::Implied));
     }
-    map
-});
+}
@@ -0,0 +1,94 @@
+; ASL, LSR, ROL and ROR on A and memory: results, carry in and out, and the
+; N and Z flags
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+; Check A is `result` and N, Z and C are `flags`
+.macro check result, flags
+        php
+        cmp #result
+        beq @result
+        jmp fail
+@result:
+        pla
+        and #$83
+        cmp #flags
+        beq @flags
+        jmp fail
+@flags:
+.endmacro
+
+; `op` on A = `value` with carry in `carry`
+.macro shift op, value, carry, result, flags
+        lda #carry
+        lsr a
+        lda #value
+        op a
+        check result, flags
+.endmacro
+
+; `op` on `value` at `addr`
+.macro memory op, addr, value, carry, result, flags
+        lda #value
+        sta addr
+        lda #carry
+        lsr a
+        op addr
+        php
+        lda addr
+        plp
+        check result, flags
+.endmacro
+
+; `op` on `value` at `base` + 1, indexed by X = 1
+.macro indexed op, base, value, carry, result, flags
+        lda #value
+        sta base + 1
+        lda #carry
+        lsr a
+        op base,x
+        php
+        lda base + 1
+        plp
+        check result, flags
+.endmacro
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $0B50
+pass:   jmp pass
+
+main:   shift asl, $81, 0, $02, $01
+        shift asl, $40, 1, $80, $80
+        shift asl, $80, 0, $00, $03
+        shift lsr, $81, 0, $40, $01
+        shift lsr, $01, 1, $00, $03
+        shift lsr, $FE, 1, $7F, $00
+        shift rol, $81, 1, $03, $01
+        shift rol, $40, 0, $80, $80
+        shift rol, $80, 0, $00, $03
+        shift ror, $81, 1, $C0, $81
+        shift ror, $02, 0, $01, $00
+        shift ror, $01, 0, $00, $03
+
+        ldx #1
+        memory asl, $10, $C1, 0, $82, $81
+        indexed asl, $10, $01, 1, $02, $00
+        memory asl, $0300, $80, 0, $00, $03
+        indexed asl, $0300, $20, 0, $40, $00
+        memory lsr, $10, $03, 0, $01, $01
+        indexed lsr, $10, $80, 1, $40, $00
+        memory lsr, $0300, $01, 0, $00, $03
+        indexed lsr, $0300, $FF, 0, $7F, $01
+        memory rol, $10, $80, 1, $01, $01
+        indexed rol, $10, $40, 0, $80, $80
+        memory rol, $0300, $80, 0, $00, $03
+        indexed rol, $0300, $7F, 1, $FF, $80
+        memory ror, $10, $01, 1, $80, $81
+        indexed ror, $10, $02, 0, $01, $00
+        memory ror, $0300, $01, 0, $00, $03
+        indexed ror, $0300, $FE, 1, $FF, $80
+
+        jmp pass
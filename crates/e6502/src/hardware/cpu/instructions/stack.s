@@ -0,0 +1,78 @@
+; PHA, PLA, PHP, PLP, TSX and TXS
+;
+; Traps at `pass` if every check holds, at `fail` otherwise.
+
+        .org $0400
+        jmp main
+fail:   jmp fail
+
+        .org $0518
+pass:   jmp pass
+
+main:   ldx #$FF
+        txs
+        tsx
+        cpx #$FF
+        bne @fail
+
+        lda #$11
+        pha
+        lda #$22
+        pha
+        tsx
+        cpx #$FD
+        bne @fail
+        lda $0100+$FF
+        cmp #$11
+        bne @fail
+        lda $0100+$FE
+        cmp #$22
+        bne @fail
+
+        ; PLA sets N and Z
+        lda #$00
+        pla
+        bmi @fail
+        beq @fail
+        cmp #$22
+        bne @fail
+        lda #$00
+        pha
+        lda #$FF
+        pla
+        bne @fail
+        pla
+        cmp #$11
+        bne @fail
+        tsx
+        cpx #$FF
+        bne @fail
+
+        ; PHP pushes B and bit 5 set, PLP restores the rest
+        sec
+        lda #$80
+        php
+        pla
+        and #$F3
+        cmp #$B1
+        bne @fail
+        lda #$C3
+        pha
+        plp
+        bpl @fail
+        bvc @fail
+        bne @fail
+        bcc @fail
+
+        ; TXS doesn't touch the flags, TSX does
+        ldx #$00
+        txs
+        bne @fail
+        ldx #$01
+        tsx
+        bne @fail
+        ldx #$FF
+        txs
+
+        jmp pass
+@fail:  jmp fail
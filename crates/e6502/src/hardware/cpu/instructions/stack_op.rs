@@ -2,72 +2,62 @@ use super::InstructionArgument;
 use crate::hardware::cpu::{Flag, Register, CPU};
 use crate::types::Bit;
 
-pub fn tax(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn tax(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.x = cpu.a;
     cpu.set(Flag::Negative, cpu.x & Flag::Negative);
     cpu.set(Flag::Zero, Bit(cpu.x == 0));
     true
 }
-pub fn tay(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn tay(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.y = cpu.a;
     cpu.set(Flag::Negative, cpu.y & Flag::Negative);
     cpu.set(Flag::Zero, Bit(cpu.y == 0));
     true
 }
-pub fn txa(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn txa(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.a = cpu.x;
     cpu.set(Flag::Negative, cpu.a & Flag::Negative);
     cpu.set(Flag::Zero, Bit(cpu.a == 0));
     true
 }
 
-pub fn tya(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn tya(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.a = cpu.y;
     cpu.set(Flag::Negative, cpu.a & Flag::Negative);
     cpu.set(Flag::Zero, Bit(cpu.a == 0));
     true
 }
 
-pub fn tsx(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn tsx(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.x = cpu.sp;
     cpu.set(Flag::Negative, cpu.x & Flag::Negative);
     cpu.set(Flag::Zero, Bit(cpu.x == 0));
     true
 }
 
-pub fn txs(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn txs(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.sp = cpu.x;
     true
 }
 
-pub fn pha(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn pha(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.push_stack(cpu.a);
     true
 }
 
-pub fn php(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn php(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.push_stack(cpu.get_reg(Register::PS).unwrap_left() | Flag::Break);
     true
 }
 
-pub fn pla(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn pla(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.a = cpu.pop_stack();
     cpu.set(Flag::Negative, cpu.a & Flag::Negative);
     cpu.set(Flag::Zero, Bit(cpu.a == 0));
     true
 }
 
-pub fn plp(arg: InstructionArgument, cpu: &mut CPU) -> bool {
-    assert!(matches!(arg, InstructionArgument::Implied));
+pub fn plp(_arg: InstructionArgument, cpu: &mut CPU) -> bool {
     cpu.ps = cpu.pop_stack();
     cpu.ps &= !Flag::Break;
     true
@@ -81,8 +71,9 @@ mod test {
         use crate::hardware::*;
 
         let mut system = system::System::new().pc(0x400);
-        for (i, byte) in include_bytes!("stack.bin").iter().enumerate() {
-            system.set_memory(i as u16, *byte);
+        let program = crate::asm::assemble(include_str!("stack.s")).unwrap();
+        for (addr, byte) in program.bytes() {
+            system.set_memory(addr.0, byte.0);
         }
 
         let (mut cpu, clk) = system.prepare();
@@ -94,7 +85,7 @@ mod test {
         });
 
         loop {
-            if !cpu.exec() {
+            if cpu.exec().unwrap() == cpu::StepOutcome::Trapped {
                 break;
             }
             instructions += 1;
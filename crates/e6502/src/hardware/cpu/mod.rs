@@ -1,17 +1,30 @@
 use super::cpu::instructions::*;
 use crate::hardware::bus::Bus;
+use crate::hardware::error::{EmuError, ErrorKind};
+use crate::hardware::memory::Pattern;
 use crate::types::*;
+use std::cell::Cell;
 use std::collections::HashSet;
 use std::fmt::Display;
 pub mod instructions;
 use crate::hardware::clock::Clock;
 use either::Either;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+/// What the CPU makes of `op_code`, see `OPCODES`
+pub fn decode(op_code: Byte) -> (Instruction, AddressingMode) {
+    let info = &OPCODES[op_code.0 as usize];
+    (info.instruction, info.mode)
+}
+
 pub const STACK_START: Addr = Addr(0x100);
 pub const STACK_END: Addr = Addr(0x01ff);
 pub const STACK_SIZE: usize = 0xff;
 
+/// Cycles the reset sequence takes
+pub const RESET_CYCLES: u64 = 7;
+
 /// brief: The processor status flags
 ///
 /// Carry: Set if last operation overflow bit 7 or underflowed bit 0
@@ -35,7 +48,39 @@ pub enum Flag {
     Negative,
 }
 
-#[derive(Debug, Copy, Clone)]
+/// What the stack guard does about a stack overflow or underflow, or a write
+/// below the stack pointer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StackGuard {
+    /// Report it on stderr and carry on
+    Warn,
+    /// Fail the instruction with an error, which stops the debugger
+    Break,
+}
+
+impl TryFrom<&str> for StackGuard {
+    type Error = String;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "warn" => Ok(StackGuard::Warn),
+            "break" => Ok(StackGuard::Break),
+            _ => Err(format!("Invalid stack guard {value:?}, not warn or break")),
+        }
+    }
+}
+
+/// How an instruction run by `CPU::exec` went
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction ran and the CPU can go on
+    Continue,
+    /// The CPU is stuck in a trap, e.g. `jmp *`, and won't go anywhere
+    Trapped,
+    /// `debug_exec` stopped at a breakpoint or after a watchpoint
+    Break,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Register {
     A,
     X,
@@ -45,6 +90,22 @@ pub enum Register {
     PC,
 }
 
+/// Snapshot of the registers and internal flags of a `CPU`
+#[derive(Debug, Clone, Copy)]
+pub struct CpuState {
+    pub pc: Addr,
+    pub sp: Byte,
+    pub a: Byte,
+    pub x: Byte,
+    pub y: Byte,
+    pub ps: Byte,
+    pub previous_pc: Addr,
+    pub advance: bool,
+    pub trap: bool,
+    pub irq_pending: bool,
+    pub nmi_pending: bool,
+}
+
 ///Contains the CPU state
 ///
 ///pc: Program Counter
@@ -66,8 +127,9 @@ pub struct CPU {
 
     previous_pc: Addr,
     advance: bool,
-    reset: bool,
+    reset_line: Arc<AtomicBool>,
     trap: bool,
+    pattern: Pattern,
 
     clk: Arc<Clock>,
     irq_pending: bool,
@@ -77,14 +139,25 @@ pub struct CPU {
     debug: bool,
     breakpoints: Option<HashSet<Addr>>,
     breaked: bool,
+    watchpoints: Option<HashSet<Addr>>,
+    watched: bool,
+    read_watchpoints: Option<HashSet<Addr>>,
+    read_hit: Cell<Option<Addr>>,
+    writes: Vec<(Addr, Byte)>,
+    stack_guard: Option<StackGuard>,
+    undocumented_nops: bool,
+
+    /// The first error of the running instruction, and the tick it happened at
+    fault: Cell<Option<(ErrorKind, u64)>>,
 }
 
 impl CPU {
-    /// Create a CPU instance connected on `bus`
+    /// Create a CPU instance connected on `bus`, just powered on with the
+    /// RESET line held
     pub fn new(bus: Bus, clk: Arc<Clock>) -> Self {
         Self {
             pc: Addr(0xfffc),
-            sp: Byte(0xfd),
+            sp: Byte(0x00),
             a: Byte(0x00),
             x: Byte(0x00),
             y: Byte(0x00),
@@ -92,11 +165,20 @@ impl CPU {
             previous_pc: Addr(0x0000),
             bus,
             advance: false,
-            reset: true,
+            reset_line: Arc::new(AtomicBool::new(true)),
             trap: false,
+            pattern: Pattern::default(),
             debug: false,
             breakpoints: None,
             breaked: false,
+            watchpoints: None,
+            watched: false,
+            read_watchpoints: None,
+            read_hit: Cell::new(None),
+            writes: vec![],
+            stack_guard: None,
+            undocumented_nops: false,
+            fault: Cell::new(None),
             clk,
             irq_pending: false,
             nmi_pending: false,
@@ -107,6 +189,18 @@ impl CPU {
         self.debug = true;
     }
 
+    /// Watch the stack for overflows, underflows and writes below the stack
+    /// pointer, handling them by `guard`
+    pub fn stack_guard(&mut self, guard: StackGuard) {
+        self.stack_guard = Some(guard);
+    }
+
+    /// Run the undocumented op codes that don't jam a real 6502 as `NOP`s
+    /// of their size, instead of failing with `ErrorKind::IllegalOpcode`
+    pub fn undocumented_nops(&mut self) {
+        self.undocumented_nops = true;
+    }
+
     pub fn breakpoint(&mut self, bp: Addr) {
         if self.breakpoints.is_none() {
             self.breakpoints = Some(HashSet::new());
@@ -115,11 +209,127 @@ impl CPU {
         self.breakpoints.as_mut().unwrap().insert(bp);
     }
 
+    pub fn remove_breakpoint(&mut self, bp: Addr) {
+        if let Some(bps) = self.breakpoints.as_mut() {
+            bps.remove(&bp);
+        }
+    }
+
+    pub fn is_breakpoint(&self, addr: Addr) -> bool {
+        self.breakpoints
+            .as_ref()
+            .is_some_and(|bps| bps.contains(&addr))
+    }
+
+    /// Let the next `debug_exec` run the instruction at PC even if it has a breakpoint
+    pub fn skip_breakpoint(&mut self) {
+        self.breaked = true;
+    }
+
+    /// Stop `debug_exec` after any instruction that writes to `addr`
+    pub fn watchpoint(&mut self, addr: Addr) {
+        self.watchpoints
+            .get_or_insert_with(HashSet::new)
+            .insert(addr);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: Addr) {
+        if let Some(wps) = self.watchpoints.as_mut() {
+            wps.remove(&addr);
+        }
+    }
+
+    pub fn is_watchpoint(&self, addr: Addr) -> bool {
+        self.watchpoints
+            .as_ref()
+            .is_some_and(|wps| wps.contains(&addr))
+    }
+
+    /// Stop `debug_exec` after any instruction that reads from `addr`
+    pub fn read_watchpoint(&mut self, addr: Addr) {
+        self.read_watchpoints
+            .get_or_insert_with(HashSet::new)
+            .insert(addr);
+    }
+
+    pub fn remove_read_watchpoint(&mut self, addr: Addr) {
+        if let Some(wps) = self.read_watchpoints.as_mut() {
+            wps.remove(&addr);
+        }
+    }
+
+    pub fn is_read_watchpoint(&self, addr: Addr) -> bool {
+        self.read_watchpoints
+            .as_ref()
+            .is_some_and(|wps| wps.contains(&addr))
+    }
+
+    /// The read watchpoint hit by the last `debug_exec`, if any
+    pub fn read_hit(&self) -> Option<Addr> {
+        self.read_hit.get()
+    }
+
+    /// Take the bus writes made since the last call, only recorded in debug mode
+    pub fn take_writes(&mut self) -> Vec<(Addr, Byte)> {
+        std::mem::take(&mut self.writes)
+    }
+
+    /// The clock driving this CPU
+    pub fn clock(&self) -> &Clock {
+        &self.clk
+    }
+
+    /// The bus this CPU is connected to
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    /// Capture the registers and internal flags
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            sp: self.sp,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            ps: self.ps,
+            previous_pc: self.previous_pc,
+            advance: self.advance,
+            trap: self.trap,
+            irq_pending: self.irq_pending,
+            nmi_pending: self.nmi_pending,
+        }
+    }
+
+    /// Restore registers and internal flags captured with `state`
+    pub fn restore(&mut self, state: CpuState) {
+        self.pc = state.pc;
+        self.sp = state.sp;
+        self.a = state.a;
+        self.x = state.x;
+        self.y = state.y;
+        self.ps = state.ps;
+        self.previous_pc = state.previous_pc;
+        self.advance = state.advance;
+        self.trap = state.trap;
+        self.irq_pending = state.irq_pending;
+        self.nmi_pending = state.nmi_pending;
+        self.reset_line.store(false, Ordering::Relaxed);
+        self.breaked = false;
+    }
+
+    /// Whether the CPU is stuck in a trap, e.g. `jmp *`
+    pub fn is_trapped(&self) -> bool {
+        self.trap
+    }
+
+    /// In debug mode the CPU runs free: cycles are counted but nobody waits on the clock
     fn with_tick<F, U>(&self, f: F) -> U
     where
         F: Fn(&Self) -> U,
     {
         if self.debug {
+            self.clk.step();
             return f(self);
         }
 
@@ -135,6 +345,7 @@ impl CPU {
         F: Fn(&mut Self) -> U,
     {
         if self.debug {
+            self.clk.step();
             return f(self);
         }
         self.clk.wait_tick();
@@ -163,7 +374,12 @@ impl CPU {
     ///
     /// Note: This decrements the stack pointer
     pub fn push_stack(&mut self, data: Byte) {
-        self.with_tick_mut(|cpu| cpu.bus.write(STACK_START + cpu.sp, data));
+        self.with_tick_mut(|cpu| {
+            if cpu.sp == 0 {
+                cpu.guard(ErrorKind::StackOverflow);
+            }
+            cpu.bus_write(STACK_START + cpu.sp, data)
+        });
 
         if self.sp == 0 {
             self.sp = STACK_END.low()
@@ -176,18 +392,98 @@ impl CPU {
     ///
     /// Note: This increments the stack pointer
     pub fn pop_stack(&mut self) -> Byte {
+        let underflow = self.sp == STACK_END.low();
         if self.sp == STACK_END & 0xFF {
             self.sp = Byte(0);
         } else {
             self.sp += 1;
         }
 
-        self.with_tick(|cpu| cpu.bus.read(STACK_START + cpu.sp))
+        self.with_tick(|cpu| {
+            if underflow {
+                cpu.guard(ErrorKind::StackUnderflow);
+            }
+            cpu.bus_read(STACK_START + cpu.sp)
+        })
     }
 
     /// Read from the `bus` at `addr`
     pub fn read(&self, addr: impl Into<Addr> + Copy) -> Byte {
-        self.with_tick(move |cpu| cpu.bus.read(addr.into()))
+        let addr = addr.into();
+        if self.debug && self.is_read_watchpoint(addr) {
+            self.read_hit.set(Some(addr));
+        }
+        self.with_tick(move |cpu| cpu.bus_read(addr))
+    }
+
+    /// Read from the `bus` at `addr` without spending a clock cycle or any
+    /// side effects on the device, unmapped addresses read as 0
+    ///
+    /// For debuggers and other tools inspecting memory.
+    pub fn peek(&self, addr: impl Into<Addr>) -> Byte {
+        self.bus.peek(addr.into()).unwrap_or(Byte(0))
+    }
+
+    /// Write to the `bus` at `addr` without spending a clock cycle or any
+    /// side effects on the device
+    ///
+    /// For debuggers and other tools changing memory.
+    pub fn poke(&mut self, addr: impl Into<Addr>, data: impl Into<Byte>) {
+        self.bus.poke(addr.into(), data.into());
+    }
+
+    /// Record `kind` as the error of the running instruction, unless it
+    /// already has one
+    fn fault(&self, kind: ErrorKind) {
+        if self.fault.get().is_none() {
+            self.fault.set(Some((kind, self.clk.ticks())));
+        }
+    }
+
+    /// The instruction's error, if any, as the result of running it
+    fn take_fault(&self) -> Result<(), EmuError> {
+        match self.fault.take() {
+            Some((kind, cycle)) => Err(EmuError {
+                kind,
+                pc: self.previous_pc,
+                cycle,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Let the stack guard, if any, handle `kind`
+    fn guard(&self, kind: ErrorKind) {
+        match self.stack_guard {
+            Some(StackGuard::Warn) => {
+                let e = EmuError {
+                    kind,
+                    pc: self.previous_pc,
+                    cycle: self.clk.ticks(),
+                };
+                eprintln!("WARNING: {e}");
+            }
+            Some(StackGuard::Break) => self.fault(kind),
+            None => (),
+        }
+    }
+
+    fn bus_read(&self, addr: Addr) -> Byte {
+        self.bus.read(addr).unwrap_or_else(|kind| {
+            self.fault(kind);
+            Byte(0)
+        })
+    }
+
+    fn bus_write(&mut self, addr: Addr, data: Byte) {
+        if let Err(kind) = self.bus.write(addr, data) {
+            self.fault(kind);
+            return;
+        }
+        if self.debug {
+            self.writes.push((addr, data));
+            self.watched |= self.is_watchpoint(addr);
+        }
     }
 
     fn next_pc(&self) -> Addr {
@@ -208,28 +504,77 @@ impl CPU {
 
     /// Write `data` to the `bus` at `addr`
     pub fn write(&mut self, addr: Addr, data: Byte) {
-        assert!(
-            addr.0 < 0x0100 || addr.0 > 0x01ff,
-            "tried to write to stack at {:#06X}",
-            addr.0
-        );
-        self.with_tick_mut(move |cpu| cpu.bus.write(addr, data))
+        self.with_tick_mut(move |cpu| {
+            if (STACK_START..=STACK_END).contains(&addr) && addr.low() <= cpu.sp {
+                cpu.guard(ErrorKind::StackWrite(addr));
+            }
+            cpu.bus_write(addr, data)
+        })
+    }
+
+    /// Fill RAM with `pattern` on `power_on`
+    ///
+    /// A random pattern randomises the registers too.
+    pub fn ram_pattern(&mut self, pattern: Pattern) {
+        self.pattern = pattern;
+    }
+
+    /// Emulate a cold start: RAM holds the pattern set with `ram_pattern`, the
+    /// registers hold whatever they come up with and the RESET line is held
+    /// until the next `reset` or `exec`
+    pub fn power_on(&mut self) {
+        let random = |n: u64| match self.pattern {
+            Pattern::Random(_) => self.pattern.byte(0x10000 + n),
+            _ => Byte(0),
+        };
+        self.a = random(0);
+        self.x = random(1);
+        self.y = random(2);
+        // Without the break and reserved bits, which aren't in the register
+        self.ps = Byte(random(3).0 & 0b1100_1111);
+        // Down to $FD by the reset sequence
+        self.sp = Byte(0x00);
+        self.trap = false;
+        self.irq_pending = false;
+        self.nmi_pending = false;
+        self.bus.power_on(self.pattern);
+        self.pend_reset();
+    }
+
+    /// The RESET line, for devices to reset the CPU with: setting it runs the
+    /// reset sequence before the next instruction
+    pub fn reset_line(&self) -> Arc<AtomicBool> {
+        self.reset_line.clone()
+    }
+
+    /// Assert the RESET line, running the reset sequence before the next
+    /// instruction
+    pub fn pend_reset(&mut self) {
+        self.reset_line.store(true, Ordering::Relaxed);
     }
 
-    /// Emulate a hard reset
+    /// Run the reset sequence, as when the RESET line is released
+    ///
+    /// Like a real 6502 it takes `RESET_CYCLES`: two reads at the program
+    /// counter, three on the stack that take the stack pointer down as pushes
+    /// would and two for the vector. Only the interrupt disable flag changes,
+    /// A, X and Y keep what they held.
     pub fn reset(&mut self) {
-        self.x = Byte(0x00);
-        self.y = Byte(0x00);
-        self.ps = Byte(0x00);
-        self.a = Byte(0x00);
-        self.sp = Byte(0xfd);
+        self.reset_line.store(false, Ordering::Relaxed);
+        self.read(self.next_pc());
+        self.read(self.next_pc());
+        for _ in 0..3 {
+            self.read(STACK_START + self.sp);
+            self.sp = Byte(self.sp.0.wrapping_sub(1));
+        }
 
         self.set(Flag::InterruptDisable, Bit(true));
 
         let low_addr = self.read(0xfffc);
         let hi_addr = self.read(0xfffd);
         self.pc = Addr::new(hi_addr, low_addr);
-        self.reset = false;
+        self.advance = false;
+        self.trap = false;
     }
 
     /// Handle an interrupt request
@@ -266,25 +611,30 @@ impl CPU {
         self.irq_pending = true;
     }
 
-    pub fn halt(&self, msg: Option<&'static str>) {
-        eprintln!("CPU halted!: {}", msg.unwrap_or_default());
-        println!("State of CPU: ");
-        println!("{self}");
-        std::process::exit(1);
+    /// Stop on the op code just fetched with `kind`, so every `exec` from
+    /// now on fails with it again
+    pub(crate) fn jam(&mut self, kind: ErrorKind) {
+        self.pc = self.previous_pc;
+        self.advance = false;
+        self.fault(kind);
     }
+
     pub fn trap(&mut self) {
         self.trap = true;
     }
 
     /// Execute next instruction
     ///
-    /// If an interrupt is pending then handle that first
+    /// If the RESET line is held or an interrupt is pending then handle that
+    /// first
     ///
     /// Note: This increments the program counter
-    pub fn exec(&mut self) -> bool {
-        assert!(!self.reset);
+    pub fn exec(&mut self) -> Result<StepOutcome, EmuError> {
+        if self.reset_line.load(Ordering::Relaxed) {
+            self.reset();
+        }
         if self.trap {
-            return false;
+            return Ok(StepOutcome::Trapped);
         }
 
         if self.nmi_pending {
@@ -297,23 +647,27 @@ impl CPU {
 
         let npc = self.next_pc();
 
-        let (instruction, addressing_mode) = self.fetch_decode();
-        let arg = self.fetch_argument(addressing_mode);
-        instruction.exec(arg, self);
+        let info = self.fetch_decode();
+        let arg = self.fetch_argument(info.mode);
+        (info.exec)(arg, self);
+        self.take_fault()?;
 
         if self.next_pc() == npc {
-            println!("CPU TRAPPED\n{self}");
             self.trap();
-            return false;
+            return Ok(StepOutcome::Trapped);
         }
 
-        true
+        Ok(StepOutcome::Continue)
     }
 
-    pub fn debug_exec(&mut self) -> bool {
+    /// Like `exec`, but stop at breakpoints and after watchpoints
+    pub fn debug_exec(&mut self) -> Result<StepOutcome, EmuError> {
+        self.read_hit.set(None);
+        if self.reset_line.load(Ordering::Relaxed) {
+            self.reset();
+        }
         if self.trap {
-            println!("CPU TRAPPED\n{self}");
-            return false;
+            return Ok(StepOutcome::Trapped);
         }
 
         let npc = self.next_pc();
@@ -321,41 +675,36 @@ impl CPU {
             if let Some(ref breakpoints) = self.breakpoints {
                 if breakpoints.get(&npc).is_some() {
                     self.breaked = true;
-                    return false;
+                    return Ok(StepOutcome::Break);
                 }
             }
         }
 
         self.breaked = false;
 
-        let (instruction, addressing_mode) = self.fetch_decode();
-        let arg = self.fetch_argument(addressing_mode);
-        // println!("{}: {instruction}, {arg}", self.previous_pc);
-        instruction.exec(arg, self);
+        let info = self.fetch_decode();
+        let arg = self.fetch_argument(info.mode);
+        (info.exec)(arg, self);
+        let watched = std::mem::take(&mut self.watched) || self.read_hit.get().is_some();
+        self.take_fault()?;
 
         if self.next_pc() == npc {
-            println!("CPU TRAPPED\n{self}");
             self.trap();
-            return false;
+            return Ok(StepOutcome::Trapped);
         }
 
-        true
+        if watched {
+            return Ok(StepOutcome::Break);
+        }
+
+        Ok(StepOutcome::Continue)
     }
 
-    fn fetch_decode(&mut self) -> (Instruction, AddressingMode) {
+    fn fetch_decode(&mut self) -> &'static OpcodeInfo {
         self.previous_pc = self.next_pc();
 
         let op_code = self.read_pc();
-        if OPCODES.contains_key(&op_code) {
-            OPCODES[&op_code.0]
-        } else if matches!(
-            op_code.0,
-            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2
-        ) {
-            (Instruction::XXX(op_code.0), AddressingMode::Implied)
-        } else {
-            (Instruction::NOP, AddressingMode::Implied)
-        }
+        &OPCODES[op_code.0 as usize]
     }
 
     fn fetch_argument(&mut self, mode: AddressingMode) -> InstructionArgument {
@@ -449,7 +798,10 @@ impl CPU {
             Register::Y => self.y = val.unwrap_left(),
             Register::PS => self.ps = val.unwrap_left(),
             Register::SP => self.sp = val.unwrap_left(),
-            Register::PC => self.pc = val.unwrap_right(),
+            Register::PC => {
+                self.pc = val.unwrap_right();
+                self.advance = false;
+            }
         };
     }
 }
@@ -542,14 +894,14 @@ pub mod system {
             let memory = memory::Memory::new(Addr(0x0000), Addr(0xffff));
             bus.register(memory).unwrap();
             for (addr, val) in self.memory {
-                bus.write(addr, val);
+                bus.write(addr, val).unwrap();
             }
 
             let clk = std::sync::Arc::new(clock::Clock::new());
             let mut cpu = cpu::CPU::new(bus, clk.clone());
             let _clk = clk.clone();
             std::thread::spawn(move || {
-                for _ in 0..2 {
+                for _ in 0..cpu::RESET_CYCLES {
                     _clk.tick();
                     _clk.wait_tock();
                 }
@@ -579,3 +931,174 @@ pub mod system {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm6502;
+    use crate::hardware::bus::Unmapped;
+    use crate::hardware::{memory::Memory, rom::Rom};
+
+    /// `kind` at the instruction at `pc`, in the cycle the CPU is at now
+    fn error(cpu: &CPU, kind: ErrorKind, pc: u16) -> Result<StepOutcome, EmuError> {
+        Err(EmuError {
+            kind,
+            pc: Addr(pc),
+            cycle: cpu.clock().ticks(),
+        })
+    }
+
+    #[test]
+    fn test_errors() {
+        // A jammed CPU stays jammed
+        let mut run = asm6502!("nop\n.byte $02").steps(1).run();
+        let cpu = run.cpu();
+        let result = cpu.exec();
+        assert_eq!(result, error(cpu, ErrorKind::Jam(0x02), 0x401));
+        let result = cpu.exec();
+        assert_eq!(result, error(cpu, ErrorKind::Jam(0x02), 0x401));
+        assert_eq!(cpu.get_reg(Register::PC).unwrap_right(), Addr(0x401));
+
+        let mut bus = Bus::new();
+        bus.set_unmapped(Unmapped::Break);
+        bus.register(Memory::new(Addr(0x100), Addr(0x2ff))).unwrap();
+        bus.register(Rom::new(None).unwrap()).unwrap();
+        let program = [
+            0x8D, 0x10, 0x02, // sta $0210
+            0x8D, 0x00, 0x80, // sta $8000
+            0xAD, 0x00, 0x40, // lda $4000
+            0x8D, 0x00, 0x40, // sta $4000
+        ];
+        for (i, &byte) in program.iter().enumerate() {
+            bus.poke(Addr(0x8000 + i as u16), Byte(byte));
+        }
+        bus.poke(Addr(0xfffd), Byte(0x80));
+
+        let mut cpu = CPU::new(bus, Arc::new(Clock::new()));
+        cpu.debug();
+        cpu.reset();
+        cpu.a = Byte(0x42);
+        assert_eq!(cpu.exec(), Ok(StepOutcome::Continue));
+        assert_eq!(cpu.peek(0x210), Byte(0x42));
+        let result = cpu.exec();
+        assert_eq!(
+            result,
+            error(&cpu, ErrorKind::RomWrite(Addr(0x8000)), 0x8003)
+        );
+        assert_eq!(cpu.peek(0x8000), Byte(0x8D));
+        let result = cpu.exec();
+        assert_eq!(
+            result,
+            error(&cpu, ErrorKind::UnmappedRead(Addr(0x4000)), 0x8006)
+        );
+        let result = cpu.exec();
+        assert_eq!(
+            result,
+            error(&cpu, ErrorKind::UnmappedWrite(Addr(0x4000)), 0x8009)
+        );
+    }
+
+    #[test]
+    fn test_undocumented() {
+        // LAX $10 is illegal, unless undocumented op codes run as NOPs, which
+        // then skip its operand
+        let mut run = asm6502!("nop\n.byte $A7, $10\nldx #1\n.byte $02")
+            .steps(1)
+            .run();
+        let cpu = run.cpu();
+        let result = cpu.exec();
+        assert_eq!(result, error(cpu, ErrorKind::IllegalOpcode(0xA7), 0x401));
+        assert_eq!(cpu.get_reg(Register::PC).unwrap_right(), Addr(0x401));
+
+        let mut run = asm6502!("nop\n.byte $A7, $10\nldx #1\n.byte $02")
+            .steps(1)
+            .run();
+        let cpu = run.cpu();
+        cpu.undocumented_nops();
+        assert_eq!(cpu.exec(), Ok(StepOutcome::Continue));
+        assert_eq!(cpu.get_reg(Register::PC).unwrap_right(), Addr(0x403));
+        assert_eq!(cpu.exec(), Ok(StepOutcome::Continue));
+        assert_eq!(cpu.get_reg(Register::X).unwrap_left(), Byte(1));
+        let result = cpu.exec();
+        assert_eq!(result, error(cpu, ErrorKind::Jam(0x02), 0x405));
+    }
+
+    #[test]
+    fn test_stack_guard() {
+        asm6502! { ldx #$F0; txs; lda #$42; sta $0150; sta $0103,x }
+            .run()
+            .memory(0x150, 0x42)
+            .memory(0x1F3, 0x42);
+
+        let mut run = asm6502! { lda #$42; sta $01F8; sta $0150; pla }
+            .sp(0xF0)
+            .steps(1)
+            .run();
+        let cpu = run.cpu();
+        cpu.stack_guard(StackGuard::Break);
+        assert_eq!(cpu.exec(), Ok(StepOutcome::Continue));
+        let result = cpu.exec();
+        assert_eq!(
+            result,
+            error(cpu, ErrorKind::StackWrite(Addr(0x150)), 0x405)
+        );
+        // Only a diagnostic, the write goes through
+        assert_eq!(cpu.peek(0x150), Byte(0x42));
+
+        let mut run = asm6502! { pla }.sp(0xFF).steps(0).run();
+        let cpu = run.cpu();
+        cpu.stack_guard(StackGuard::Break);
+        let result = cpu.exec();
+        assert_eq!(result, error(cpu, ErrorKind::StackUnderflow, 0x400));
+        assert_eq!(cpu.get_sp(), Byte(0x00));
+
+        let mut run = asm6502! { pha }.sp(0x00).steps(0).run();
+        let cpu = run.cpu();
+        cpu.stack_guard(StackGuard::Warn);
+        assert_eq!(cpu.exec(), Ok(StepOutcome::Continue));
+        assert_eq!(cpu.get_sp(), Byte(0xFF));
+        cpu.set_reg(Register::PC, Either::Right(Addr(0x400)));
+        cpu.set_reg(Register::SP, Either::Left(Byte(0x00)));
+        cpu.stack_guard(StackGuard::Break);
+        let result = cpu.exec();
+        assert_eq!(result, error(cpu, ErrorKind::StackOverflow, 0x400));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut run = asm6502!("start: lda #$42\nldy #$99\n.org $FFFC\n.word start")
+            .x(0x17)
+            .ps(0x00)
+            .run()
+            .pc(0x0404)
+            .sp(0xFD);
+        let cpu = run.cpu();
+        cpu.pend_reset();
+        let start = cpu.clock().ticks();
+        assert_eq!(cpu.exec(), Ok(StepOutcome::Continue));
+        assert_eq!(cpu.clock().ticks() - start, RESET_CYCLES + 2);
+        assert_eq!(cpu.get_reg(Register::PC).unwrap_right(), Addr(0x0402));
+        assert_eq!(cpu.get_reg(Register::X).unwrap_left(), Byte(0x17));
+        assert_eq!(cpu.get_reg(Register::Y).unwrap_left(), Byte(0x99));
+        assert_eq!(cpu.get_sp(), Byte(0xFA));
+        assert_eq!(cpu.get_reg(Register::PS).unwrap_left(), Byte(0x24));
+
+        cpu.ram_pattern(Pattern::Fill(Byte(0xEA)));
+        cpu.power_on();
+        assert_eq!(cpu.peek(0x0400), Byte(0xEA));
+        assert_eq!(cpu.get_reg(Register::X).unwrap_left(), Byte(0x00));
+        cpu.reset();
+        assert_eq!(cpu.get_reg(Register::PC).unwrap_right(), Addr(0xEAEA));
+        assert_eq!(cpu.get_sp(), Byte(0xFD));
+        assert!(cpu.is_set(Flag::InterruptDisable));
+
+        let power_on = |cpu: &mut CPU, seed| {
+            cpu.ram_pattern(Pattern::Random(seed));
+            cpu.power_on();
+            (cpu.peek(0x1234), cpu.get_reg(Register::A).unwrap_left())
+        };
+        let first = power_on(cpu, 6502);
+        assert_eq!(power_on(cpu, 6502), first);
+        assert_ne!(power_on(cpu, 6510), first);
+    }
+}
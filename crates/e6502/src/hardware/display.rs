@@ -11,7 +11,12 @@ pub const READY: Byte = Byte(0x08);
 const ADDR_START: Addr = Addr(0x5002);
 const ADDR_END: Addr = Addr(0x5003);
 
-pub struct Display;
+/// Prints the characters the CPU writes to KEY_DATA
+///
+/// By default they go to stdout, `with_output` sends them elsewhere.
+pub struct Display {
+    output: Option<Box<dyn Fn(u8) + Send>>,
+}
 
 impl Default for Display {
     fn default() -> Self {
@@ -21,15 +26,27 @@ impl Default for Display {
 
 impl Display {
     pub fn new() -> Self {
-        Self
+        Self { output: None }
+    }
+
+    /// Hand every character to `output` instead of printing it
+    pub fn with_output(output: impl Fn(u8) + Send + 'static) -> Self {
+        Self {
+            output: Some(Box::new(output)),
+        }
     }
 }
 
 impl Device for Display {
     fn rx(&self, addr: Addr, data: Byte) {
         if addr == KEY_DATA {
-            print!("{}", data.0 as char);
-            std::io::stdout().flush().unwrap();
+            match self.output {
+                Some(ref output) => output(data.0),
+                None => {
+                    print!("{}", data.0 as char);
+                    std::io::stdout().flush().unwrap();
+                }
+            }
         }
     }
 
@@ -43,4 +60,7 @@ impl Device for Display {
     fn range(&self) -> (Addr, Addr) {
         (ADDR_START, ADDR_END)
     }
+
+    /// There is nothing to change, only output to print
+    fn poke(&self, _addr: Addr, _data: Byte) {}
 }
@@ -0,0 +1,63 @@
+use crate::types::Addr;
+use std::fmt::Display;
+
+/// What went wrong while the CPU ran an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An op code the CPU doesn't run, e.g. $FF from erased ROM
+    IllegalOpcode(u8),
+    /// An op code that jams a real 6502 until it's reset
+    Jam(u8),
+    /// Read from an address no device is registered at, which read as 0
+    ///
+    /// Only with `Unmapped::Break`, as are the next two.
+    UnmappedRead(Addr),
+    /// Write to an address no device is registered at, which went nowhere
+    UnmappedWrite(Addr),
+    /// Write to ROM, which the ROM ignored
+    RomWrite(Addr),
+    /// Push with the stack full, which wraps around to $01FF
+    ///
+    /// Only with `StackGuard::Break`, as are the next two.
+    StackOverflow,
+    /// Pull with the stack empty, which wraps around to $0100
+    StackUnderflow,
+    /// Write below the stack pointer, where the next push goes
+    StackWrite(Addr),
+}
+
+/// An error from `CPU::exec`, at the instruction starting at `pc`
+///
+/// The instruction has run as far as it could. The host decides whether to
+/// stop, log it or carry on with the next instruction: a jammed CPU stays on
+/// the op code and fails again, anything else continues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmuError {
+    pub kind: ErrorKind,
+    pub pc: Addr,
+    /// The clock tick the error happened at
+    pub cycle: u64,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::IllegalOpcode(op_code) => write!(f, "Illegal op code {op_code:#04X}"),
+            ErrorKind::Jam(op_code) => write!(f, "Jammed by op code {op_code:#04X}"),
+            ErrorKind::UnmappedRead(addr) => write!(f, "Read from unmapped {addr}"),
+            ErrorKind::UnmappedWrite(addr) => write!(f, "Write to unmapped {addr}"),
+            ErrorKind::RomWrite(addr) => write!(f, "Write to ROM at {addr}"),
+            ErrorKind::StackOverflow => write!(f, "Stack overflow"),
+            ErrorKind::StackUnderflow => write!(f, "Stack underflow"),
+            ErrorKind::StackWrite(addr) => write!(f, "Write below the stack pointer at {addr}"),
+        }
+    }
+}
+
+impl Display for EmuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}, cycle {}", self.kind, self.pc, self.cycle)
+    }
+}
+
+impl std::error::Error for EmuError {}
@@ -1,7 +1,10 @@
+use crate::hardware::clock::Clock;
 use crate::types::{Addr, Byte};
-use console::Term;
+use console::{Key, Term};
 use std::cell::UnsafeCell;
 use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
 
 use super::Device;
 
@@ -13,8 +16,20 @@ pub const NOT_READY: Byte = Byte(0x00);
 const ADDR_START: Addr = Addr(0x5000);
 const ADDR_END: Addr = Addr(0x5001);
 
+/// Where typed characters come from
+pub enum Input {
+    /// Characters as they are typed, e.g. sent by `Keyboard::poll`
+    Live(Receiver<u8>),
+    /// Characters recorded earlier, each with the cycle it has to arrive at
+    Replay(VecDeque<(u64, u8)>),
+}
+
+/// Characters only reach the keyboard when the CPU reads it, so the cycle
+/// they arrive at depends on the program alone and can be replayed.
 pub struct Keyboard {
     data: UnsafeCell<VecDeque<u8>>,
+    input: UnsafeCell<Option<(Input, Arc<Clock>)>>,
+    record: Option<Box<dyn Fn(u64, u8) + Send>>,
 }
 
 impl Default for Keyboard {
@@ -24,27 +39,88 @@ impl Default for Keyboard {
 }
 
 impl Keyboard {
+    /// A keyboard nobody types on
     pub fn new() -> Keyboard {
         Self {
             data: UnsafeCell::new(VecDeque::new()),
+            input: UnsafeCell::new(None),
+            record: None,
+        }
+    }
+
+    /// A keyboard typed on through `input`, arriving by the cycles of `clock`
+    pub fn with_input(input: Input, clock: Arc<Clock>) -> Self {
+        Self {
+            input: UnsafeCell::new(Some((input, clock))),
+            ..Self::new()
         }
     }
 
-    pub fn poll(keyboard: &Keyboard) {
+    /// Hand every character to `record` together with the cycle it arrived at
+    pub fn record(mut self, record: impl Fn(u64, u8) + Send + 'static) -> Self {
+        self.record = Some(Box::new(record));
+        self
+    }
+
+    /// Send the characters typed on the terminal to `input`
+    ///
+    /// Returns once Ctrl-C is pressed or nobody listens anymore, and with an
+    /// error if the terminal can't be read.
+    pub fn poll(input: Sender<u8>) -> std::io::Result<()> {
         let term = Term::stdout();
+        if !term.is_term() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Not a terminal",
+            ));
+        }
+        loop {
+            let c = match term.read_key_raw()? {
+                Key::CtrlC => return Ok(()),
+                Key::Char(c) => c,
+                Key::Enter => '\n',
+                _ => continue,
+            };
+            if input.send(c as u8).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Queue the characters that have arrived by now
+    fn deliver(&self) {
+        let data = unsafe { &mut *self.data.get() };
+        let input = unsafe { &mut *self.input.get() };
+        let Some((input, clock)) = input else {
+            return;
+        };
+
+        let cycle = clock.ticks();
         loop {
-            let c = match term.read_char() {
-                Err(_) => continue,
-                Ok(c) => c,
+            let c = match input {
+                Input::Live(rx) => match rx.try_recv() {
+                    Ok(c) => c,
+                    Err(_) => break,
+                },
+                Input::Replay(keys) => match keys.front() {
+                    Some(&(at, c)) if at <= cycle => {
+                        keys.pop_front();
+                        c
+                    }
+                    _ => break,
+                },
             };
-            let data = unsafe { &mut *keyboard.data.get() };
-            data.push_back(c as u8);
+            if let Some(ref record) = self.record {
+                record(cycle, c);
+            }
+            data.push_back(c);
         }
     }
 }
 
 impl Device for Keyboard {
     fn tx(&self, addr: Addr) -> Byte {
+        self.deliver();
         let data = unsafe { &mut *self.data.get() };
         match addr {
             KEY_READY => {
@@ -68,4 +144,90 @@ impl Device for Keyboard {
     fn range(&self) -> (Addr, Addr) {
         (ADDR_START, ADDR_END)
     }
+
+    /// Like `tx`, but leaves the character in the queue and input that
+    /// hasn't been delivered yet where it is
+    fn peek(&self, addr: Addr) -> Byte {
+        let data = unsafe { &*self.data.get() };
+        match addr {
+            KEY_READY if !data.is_empty() => READY,
+            KEY_READY => NOT_READY,
+            KEY_DATA => Byte(data.front().copied().unwrap_or(0x00)),
+            _ => unreachable!("Unsupported read :{addr:?}"),
+        }
+    }
+
+    /// Input only comes from the terminal
+    fn poke(&self, _addr: Addr, _data: Byte) {}
+
+    /// The characters typed but not read yet
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        let data = unsafe { &*self.data.get() };
+        Some(data.iter().copied().collect())
+    }
+
+    fn restore(&self, data: &[u8]) {
+        let queue = unsafe { &mut *self.data.get() };
+        queue.clear();
+        queue.extend(data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_peek() {
+        let keyboard = Keyboard::new();
+        unsafe { &mut *keyboard.data.get() }.extend(b"hi");
+
+        assert_eq!(keyboard.peek(KEY_READY), READY);
+        assert_eq!(keyboard.peek(KEY_DATA), Byte(b'h'));
+        assert_eq!(keyboard.peek(KEY_DATA), Byte(b'h'));
+        keyboard.poke(KEY_DATA, Byte(b'x'));
+
+        assert_eq!(keyboard.tx(KEY_DATA), Byte(b'h'));
+        assert_eq!(keyboard.tx(KEY_DATA), Byte(b'i'));
+        assert_eq!(keyboard.peek(KEY_READY), NOT_READY);
+        assert_eq!(keyboard.peek(KEY_DATA), Byte(0x00));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let keyboard = Keyboard::new();
+        unsafe { &mut *keyboard.data.get() }.extend(b"hi");
+        let snapshot = keyboard.snapshot().unwrap();
+
+        keyboard.tx(KEY_DATA);
+        unsafe { &mut *keyboard.data.get() }.extend(b"!");
+        keyboard.restore(&snapshot);
+        assert_eq!(keyboard.tx(KEY_DATA), Byte(b'h'));
+        assert_eq!(keyboard.tx(KEY_DATA), Byte(b'i'));
+        assert_eq!(keyboard.peek(KEY_READY), NOT_READY);
+    }
+
+    #[test]
+    fn test_replay() {
+        let clock = Arc::new(Clock::free_running());
+        let keys = VecDeque::from([(2, b'h'), (2, b'i'), (5, b'!')]);
+        let recorded = Arc::new(std::sync::Mutex::new(vec![]));
+        let keyboard = Keyboard::with_input(Input::Replay(keys), clock.clone()).record({
+            let recorded = recorded.clone();
+            move |cycle, c| recorded.lock().unwrap().push((cycle, c))
+        });
+
+        clock.set_ticks(1);
+        assert_eq!(keyboard.tx(KEY_READY), NOT_READY);
+        clock.set_ticks(3);
+        assert_eq!(keyboard.peek(KEY_READY), NOT_READY);
+        assert_eq!(keyboard.tx(KEY_DATA), Byte(b'h'));
+        clock.set_ticks(5);
+        assert_eq!(keyboard.tx(KEY_DATA), Byte(b'i'));
+        assert_eq!(keyboard.tx(KEY_DATA), Byte(b'!'));
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            vec![(3, b'h'), (3, b'i'), (5, b'!')]
+        );
+    }
 }
@@ -7,6 +7,58 @@ const ADDR_START: Addr = Addr(0x0000);
 const ADDR_END: Addr = Addr(0x3fff);
 pub const MEMORY_SIZE: usize = 0x4000;
 
+/// What RAM holds at power-on
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Pattern {
+    #[default]
+    Zero,
+    /// Every byte the same
+    Fill(Byte),
+    /// Pseudo-random bytes from a seed, the same for the same seed
+    Random(u64),
+}
+
+impl Pattern {
+    /// The `n`th byte of the pattern
+    pub fn byte(self, n: u64) -> Byte {
+        match self {
+            Pattern::Zero => Byte(0),
+            Pattern::Fill(byte) => byte,
+            Pattern::Random(seed) => Byte(splitmix64(seed.wrapping_add(n)) as u8),
+        }
+    }
+}
+
+impl TryFrom<&str> for Pattern {
+    type Error = String;
+    /// `zero`, a byte to fill with, e.g. `0xFF`, or `random`, optionally with
+    /// a seed, `random:1234`
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.split_once(':') {
+            _ if value == "zero" => Ok(Pattern::Zero),
+            _ if value == "random" => {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+                Ok(Pattern::Random(now.map_or(0, |d| d.as_nanos() as u64)))
+            }
+            Some(("random", seed)) => Ok(Pattern::Random(
+                seed.parse()
+                    .map_err(|e| format!("Invalid seed {seed:?}: {e}"))?,
+            )),
+            _ => Ok(Pattern::Fill(Byte::try_from(value).map_err(|e| {
+                format!("Invalid pattern {value:?}, not zero, random or a byte: {e}")
+            })?)),
+        }
+    }
+}
+
+/// Scramble `x`, for random numbers without depending on a crate
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
 pub struct Memory {
     data: UnsafeCell<Vec<Byte>>,
     start: Addr,
@@ -29,6 +81,14 @@ impl Memory {
         }
     }
 
+    /// Index of `addr` in `data`, `None` outside the range
+    ///
+    /// The bus only hands a device the addresses in its range, anything else
+    /// reads as 0 and is not written.
+    fn index(&self, addr: Addr) -> Option<usize> {
+        (self.start <= addr && addr <= self.end).then(|| (addr.0 - self.start.0) as usize)
+    }
+
     unsafe fn write(&self, addr: usize, byte: Byte) {
         let data = unsafe { &mut *self.data.get() };
         data[addr] = byte;
@@ -37,14 +97,50 @@ impl Memory {
 
 impl Device for Memory {
     fn rx(&self, addr: Addr, data: Byte) {
-        assert!(addr <= self.end, "Outside memory region: {:#06X}", addr.0);
-        unsafe { self.write(addr.0 as usize, data) }
+        if let Some(index) = self.index(addr) {
+            unsafe { self.write(index, data) }
+        }
     }
     fn tx(&self, addr: Addr) -> Byte {
-        assert!(addr <= self.end, "Outside memory region: {:#06X}", addr.0);
-        unsafe { (*self.data.get())[addr.0 as usize] }
+        let data = unsafe { &*self.data.get() };
+        self.index(addr).map_or(Byte(0), |index| data[index])
     }
     fn range(&self) -> (Addr, Addr) {
         (self.start, self.end)
     }
+
+    fn power_on(&self, pattern: Pattern) {
+        let data = unsafe { &mut *self.data.get() };
+        for (n, byte) in data.iter_mut().enumerate() {
+            *byte = pattern.byte(n as u64);
+        }
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        let data = unsafe { &*self.data.get() };
+        Some(data.iter().map(|b| b.0).collect())
+    }
+
+    fn restore(&self, data: &[u8]) {
+        let mem = unsafe { &mut *self.data.get() };
+        for (byte, val) in mem.iter_mut().zip(data) {
+            *byte = Byte(*val);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_outside_range() {
+        let memory = Memory::new(Addr(0x100), Addr(0x1FF));
+        memory.rx(Addr(0x1FF), Byte(0x42));
+        memory.rx(Addr(0xFF), Byte(0x42));
+        memory.rx(Addr(0x200), Byte(0x42));
+        assert_eq!(memory.tx(Addr(0x1FF)), Byte(0x42));
+        assert_eq!(memory.tx(Addr(0xFF)), Byte(0));
+        assert_eq!(memory.tx(Addr(0x200)), Byte(0));
+    }
 }
@@ -2,25 +2,87 @@ pub mod bus;
 pub mod clock;
 pub mod cpu;
 pub mod display;
+pub mod error;
 pub mod keyboard;
 pub mod memory;
 pub mod rom;
+pub mod semihost;
 pub mod serial;
 
 use crate::types::{Addr, Byte};
+use bus::Bus;
+use display::Display;
+use keyboard::Keyboard;
+use memory::{Memory, Pattern};
+use rom::Rom;
+
+/// Connect memory, keyboard, display and ROM to a bus, the machine `e6502`
+/// emulates
+pub fn standard_bus(
+    rom: Option<String>,
+    keyboard: Keyboard,
+    display: Display,
+) -> Result<Bus, String> {
+    let mut bus = Bus::new(); // Everyone talks over this
+
+    bus.register(Memory::default())?;
+    bus.register(keyboard)?;
+    bus.register(display)?;
+    bus.register(Rom::new(rom)?)?;
+
+    Ok(bus)
+}
 
 /// Trait for devices on `Bus`
 pub trait Device: Send {
     /// Someone writes `data` to `addr` belonging to this device
     ///
-    /// Note: The device does not necessarily need to support this.
+    /// Note: The device does not necessarily need to support this, writes
+    /// are ignored by default.
     #[allow(unused_variables)]
-    fn rx(&self, addr: Addr, data: Byte) {
-        unimplemented!("Not supported for device");
+    fn rx(&self, addr: Addr, data: Byte) {}
+
+    /// Whether the CPU can't write to this device, e.g. ROM
+    ///
+    /// The bus reports writes to read-only devices instead of passing them on.
+    fn read_only(&self) -> bool {
+        false
     }
 
     /// Someone reads from `addr` belonging to this device
     fn tx(&self, addr: Addr) -> Byte;
 
     fn range(&self) -> (Addr, Addr);
+
+    /// Inspect `addr` the way `tx` reads it, without any side effects
+    ///
+    /// Devices whose reads change their state, e.g. by consuming input, must
+    /// override this.
+    fn peek(&self, addr: Addr) -> Byte {
+        self.tx(addr)
+    }
+
+    /// Change the state behind `addr` without any side effects
+    ///
+    /// Devices whose writes do more than store `data` must override this.
+    fn poke(&self, addr: Addr, data: Byte) {
+        self.rx(addr, data)
+    }
+
+    /// Power on with RAM holding `pattern`
+    ///
+    /// Only RAM has anything to do, other devices keep their state.
+    #[allow(unused_variables)]
+    fn power_on(&self, pattern: Pattern) {}
+
+    /// Capture the internal state of the device, e.g. the contents of RAM
+    ///
+    /// Devices without state worth restoring return `None`.
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restore state captured with `snapshot`
+    #[allow(unused_variables)]
+    fn restore(&self, data: &[u8]) {}
 }
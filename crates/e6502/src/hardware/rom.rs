@@ -1,4 +1,5 @@
 use crate::types::{Addr, Byte};
+use std::cell::UnsafeCell;
 use std::path::Path;
 
 use super::Device;
@@ -8,38 +9,93 @@ const ADDR_START: Addr = Addr(0x7f00);
 const ADDR_END: Addr = Addr(0xffff);
 
 pub struct Rom {
-    data: [Byte; (ROM_SIZE.0 + 1) as usize],
+    data: UnsafeCell<[Byte; (ROM_SIZE.0 + 1) as usize]>,
 }
 
 impl Rom {
-    pub fn new(file: Option<String>) -> Rom {
+    /// ROM holding the image in `file` from its start, zeros without one
+    ///
+    /// Fails if the file can't be read or doesn't fit.
+    pub fn new(file: Option<String>) -> Result<Rom, String> {
         let mut data = [Byte(0); (ROM_SIZE.0 + 1) as usize];
         if let Some(file) = file {
-            let _data = std::fs::read(Path::new(&file)).unwrap();
-            for (i, byte) in _data[..((ROM_SIZE.0 - 1) as usize)].iter().enumerate() {
+            let image = std::fs::read(Path::new(&file)).map_err(|e| format!("{file}: {e}"))?;
+            if image.len() > data.len() {
+                return Err(format!(
+                    "{file}: {} bytes don't fit in {} bytes of ROM",
+                    image.len(),
+                    data.len()
+                ));
+            }
+            for (i, byte) in image.iter().enumerate() {
                 data[i] = Byte(*byte);
             }
         }
 
-        Self { data }
+        Ok(Self {
+            data: UnsafeCell::new(data),
+        })
     }
 }
 
 impl Device for Rom {
-    fn rx(&self, _addr: Addr, _data: Byte) {
-        panic!("Not allowed to write to ROM");
+    fn read_only(&self) -> bool {
+        true
     }
 
-    #[allow(clippy::absurd_extreme_comparisons)]
+    /// Outside the ROM reads as 0, the bus doesn't send those addresses here
     fn tx(&self, addr: Addr) -> Byte {
-        assert!(
-            addr.0 <= ADDR_START.0 + ROM_SIZE.0,
-            "ROM: Outside memory region {:#06X}",
-            addr.0
-        );
-        self.data[(addr.0 - ADDR_START.0) as usize]
+        let data = unsafe { &*self.data.get() };
+        let index = addr.0.checked_sub(ADDR_START.0);
+        index.map_or(Byte(0), |index| data[index as usize])
+    }
+
+    /// Patch the ROM, which the CPU itself cannot do
+    fn poke(&self, addr: Addr, byte: Byte) {
+        let data = unsafe { &mut *self.data.get() };
+        if let Some(index) = addr.0.checked_sub(ADDR_START.0) {
+            data[index as usize] = byte;
+        }
     }
     fn range(&self) -> (Addr, Addr) {
         (ADDR_START, ADDR_END)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_images() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            let path = dir.join(format!("e6502-rom-{}-{name}", std::process::id()));
+            path.to_string_lossy().to_string()
+        };
+
+        let short = path("short.bin");
+        std::fs::write(&short, [0xEA, 0x60]).unwrap();
+        let rom = Rom::new(Some(short.clone())).unwrap();
+        assert_eq!(rom.tx(ADDR_START), Byte(0xEA));
+        assert_eq!(rom.tx(ADDR_START + 1), Byte(0x60));
+        assert_eq!(rom.tx(ADDR_END), Byte(0));
+        assert_eq!(rom.tx(ADDR_START - 1u8), Byte(0));
+
+        let full = path("full.bin");
+        std::fs::write(&full, vec![0xFF; ROM_SIZE.0 as usize + 1]).unwrap();
+        assert_eq!(
+            Rom::new(Some(full.clone())).unwrap().tx(ADDR_END),
+            Byte(0xFF)
+        );
+
+        let large = path("large.bin");
+        std::fs::write(&large, vec![0xFF; ROM_SIZE.0 as usize + 2]).unwrap();
+        assert!(Rom::new(Some(large.clone())).is_err());
+
+        for file in [short, full, large] {
+            std::fs::remove_file(&file).unwrap();
+            assert!(Rom::new(Some(file)).is_err());
+        }
+    }
+}
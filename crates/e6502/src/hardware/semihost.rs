@@ -0,0 +1,322 @@
+use crate::hardware::clock::Clock;
+use crate::types::{Addr, Byte};
+use std::cell::UnsafeCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use super::Device;
+
+/// Write: run a command. Read: `OK` or `ERROR` for the last one
+pub const COMMAND: u16 = 0;
+/// Write: append a byte to the request. Read: take the next byte of the reply
+pub const DATA: u16 = 1;
+/// The argument of a command, and its result
+pub const ARG: u16 = 2;
+/// Read: how many bytes are left in the reply, at most 255
+pub const AVAIL: u16 = 3;
+/// The file `READ`, `WRITE` and `CLOSE` work on, set by `OPEN`
+pub const HANDLE: u16 = 4;
+
+const SIZE: u16 = 5;
+
+pub const OK: u8 = 0x00;
+pub const ERROR: u8 = 0xFF;
+
+/// Exit with code `ARG`
+pub const EXIT: u8 = 0x01;
+/// Print the character `ARG`
+pub const PUTCHAR: u8 = 0x02;
+/// Print the request and a newline
+pub const PUTS: u8 = 0x03;
+/// Reply with the cycle count, 8 bytes little endian
+pub const CYCLES: u8 = 0x04;
+/// Report the request as a failed assertion and exit with code 1
+pub const ASSERT: u8 = 0x05;
+/// Open the host file named by the request, `ARG` is 0 to read, 1 to write
+/// and 2 to append; sets `HANDLE`
+pub const OPEN: u8 = 0x10;
+pub const CLOSE: u8 = 0x11;
+/// Reply with up to `ARG` bytes read; sets `ARG` to the number read, 0 at
+/// the end of the file
+pub const READ: u8 = 0x12;
+/// Write the request
+pub const WRITE: u8 = 0x13;
+
+#[derive(Default)]
+struct State {
+    status: u8,
+    request: Vec<u8>,
+    reply: VecDeque<u8>,
+    arg: u8,
+    handle: u8,
+    files: HashMap<u8, File>,
+}
+
+/// Lets programs under test talk to the host: exit with a code, print,
+/// read the cycle counter, fail assertions and use files
+///
+/// Files are only found below the root directory given, so a program can't
+/// touch anything else on the host.
+pub struct Semihost {
+    start: Addr,
+    root: PathBuf,
+    clock: Arc<Clock>,
+    state: UnsafeCell<State>,
+    output: Box<dyn Fn(u8) + Send>,
+    exit: Box<dyn Fn(u8) + Send>,
+}
+
+impl Semihost {
+    /// Print to stdout and hand exit codes to `exit`, with the registers at
+    /// `start`
+    ///
+    /// `exit` decides what exiting means: the process, a headless run or a
+    /// test may each want something else.
+    pub fn new(
+        start: Addr,
+        root: impl Into<PathBuf>,
+        clock: Arc<Clock>,
+        exit: impl Fn(u8) + Send + 'static,
+    ) -> Self {
+        Self {
+            start,
+            root: root.into(),
+            clock,
+            state: UnsafeCell::new(State::default()),
+            output: Box::new(|c| {
+                print!("{}", c as char);
+                std::io::stdout().flush().unwrap();
+            }),
+            exit: Box::new(exit),
+        }
+    }
+
+    /// Hand printed characters to `output` instead
+    pub fn with_output(mut self, output: impl Fn(u8) + Send + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// The path of the file `name` below the root, which has to be relative
+    /// and stay below it
+    fn path(&self, name: &[u8]) -> Result<PathBuf, String> {
+        let name = String::from_utf8_lossy(name);
+        let path = Path::new(name.as_ref());
+        let inside = path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !inside {
+            return Err(format!("{name:?} is not below the root"));
+        }
+        Ok(self.root.join(path))
+    }
+
+    fn run(&self, command: u8) -> Result<(), String> {
+        let state = unsafe { &mut *self.state.get() };
+        let request = std::mem::take(&mut state.request);
+        match command {
+            EXIT => (self.exit)(state.arg),
+            PUTCHAR => (self.output)(state.arg),
+            PUTS => request.iter().chain(b"\n").for_each(|&c| (self.output)(c)),
+            CYCLES => state.reply = self.clock.ticks().to_le_bytes().into(),
+            ASSERT => {
+                std::io::stdout().flush().unwrap();
+                eprintln!("Assertion failed: {}", String::from_utf8_lossy(&request));
+                (self.exit)(1);
+            }
+            OPEN => {
+                let path = self.path(&request)?;
+                let mut options = OpenOptions::new();
+                match state.arg {
+                    0 => options.read(true),
+                    1 => options.write(true).create(true).truncate(true),
+                    2 => options.append(true).create(true),
+                    mode => return Err(format!("Invalid mode {mode}")),
+                };
+                let file = options
+                    .open(&path)
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+                let handle = (1..=u8::MAX)
+                    .find(|h| !state.files.contains_key(h))
+                    .ok_or("Too many open files")?;
+                state.files.insert(handle, file);
+                state.handle = handle;
+            }
+            CLOSE => {
+                state
+                    .files
+                    .remove(&state.handle)
+                    .ok_or(format!("Invalid handle {}", state.handle))?;
+            }
+            READ | WRITE => {
+                let file = state
+                    .files
+                    .get_mut(&state.handle)
+                    .ok_or(format!("Invalid handle {}", state.handle))?;
+                if command == READ {
+                    let mut data = vec![0; state.arg as usize];
+                    let n = file.read(&mut data).map_err(|e| e.to_string())?;
+                    state.reply = data[..n].iter().copied().collect();
+                    state.arg = n as u8;
+                } else {
+                    file.write_all(&request).map_err(|e| e.to_string())?;
+                }
+            }
+            _ => return Err(format!("Invalid command {command:#04X}")),
+        }
+        Ok(())
+    }
+}
+
+impl Device for Semihost {
+    fn rx(&self, addr: Addr, data: Byte) {
+        if (addr - self.start).0 == COMMAND {
+            let status = match self.run(data.0) {
+                Ok(()) => OK,
+                Err(e) => {
+                    eprintln!("Semihosting: {e}");
+                    ERROR
+                }
+            };
+            unsafe { (*self.state.get()).status = status };
+            return;
+        }
+
+        let state = unsafe { &mut *self.state.get() };
+        match (addr - self.start).0 {
+            DATA => state.request.push(data.0),
+            ARG => state.arg = data.0,
+            HANDLE => state.handle = data.0,
+            _ => (),
+        }
+    }
+
+    fn tx(&self, addr: Addr) -> Byte {
+        let state = unsafe { &mut *self.state.get() };
+        match (addr - self.start).0 {
+            DATA => Byte(state.reply.pop_front().unwrap_or(0)),
+            _ => self.peek(addr),
+        }
+    }
+
+    fn range(&self) -> (Addr, Addr) {
+        (self.start, self.start + (SIZE - 1) as usize)
+    }
+
+    /// Like `tx`, but leaves the reply alone
+    fn peek(&self, addr: Addr) -> Byte {
+        let state = unsafe { &*self.state.get() };
+        match (addr - self.start).0 {
+            COMMAND => Byte(state.status),
+            DATA => Byte(state.reply.front().copied().unwrap_or(0)),
+            ARG => Byte(state.arg),
+            AVAIL => Byte(state.reply.len().min(255) as u8),
+            HANDLE => Byte(state.handle),
+            _ => Byte(0),
+        }
+    }
+
+    /// Commands only run when the CPU writes them
+    fn poke(&self, addr: Addr, data: Byte) {
+        let state = unsafe { &mut *self.state.get() };
+        match (addr - self.start).0 {
+            ARG => state.arg = data.0,
+            HANDLE => state.handle = data.0,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    const BASE: Addr = Addr(0x4000);
+
+    fn write(semihost: &Semihost, reg: u16, data: u8) {
+        semihost.rx(BASE + reg as usize, Byte(data));
+    }
+
+    fn read(semihost: &Semihost, reg: u16) -> u8 {
+        semihost.tx(BASE + reg as usize).0
+    }
+
+    fn send(semihost: &Semihost, text: &[u8]) {
+        for &c in text {
+            write(semihost, DATA, c);
+        }
+    }
+
+    #[test]
+    fn test_semihost() {
+        let root = std::env::temp_dir().join(format!("e6502-semihost-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let clock = Arc::new(Clock::free_running());
+        let output = Arc::new(Mutex::new(vec![]));
+        let exit = Arc::new(Mutex::new(None));
+        let semihost = Semihost::new(BASE, &root, clock.clone(), {
+            let exit = exit.clone();
+            move |code| *exit.lock().unwrap() = Some(code)
+        })
+        .with_output({
+            let output = output.clone();
+            move |c| output.lock().unwrap().push(c)
+        });
+
+        write(&semihost, ARG, b'>');
+        write(&semihost, COMMAND, PUTCHAR);
+        send(&semihost, b"hi");
+        write(&semihost, COMMAND, PUTS);
+        assert_eq!(*output.lock().unwrap(), b">hi\n");
+
+        clock.set_ticks(0x1234);
+        write(&semihost, COMMAND, CYCLES);
+        assert_eq!(read(&semihost, AVAIL), 8);
+        assert_eq!(semihost.peek(BASE + DATA as usize), Byte(0x34));
+        assert_eq!(read(&semihost, DATA), 0x34);
+        assert_eq!(read(&semihost, DATA), 0x12);
+
+        send(&semihost, b"out.txt");
+        write(&semihost, ARG, 1);
+        write(&semihost, COMMAND, OPEN);
+        assert_eq!(read(&semihost, COMMAND), OK);
+        send(&semihost, b"data");
+        write(&semihost, COMMAND, WRITE);
+        write(&semihost, COMMAND, CLOSE);
+        assert_eq!(std::fs::read(root.join("out.txt")).unwrap(), b"data");
+
+        send(&semihost, b"./out.txt");
+        write(&semihost, ARG, 0);
+        write(&semihost, COMMAND, OPEN);
+        write(&semihost, ARG, 3);
+        write(&semihost, COMMAND, READ);
+        assert_eq!((read(&semihost, ARG), read(&semihost, AVAIL)), (3, 3));
+        assert_eq!(read(&semihost, DATA), b'd');
+        write(&semihost, ARG, 3);
+        write(&semihost, COMMAND, READ);
+        assert_eq!(read(&semihost, ARG), 1);
+        write(&semihost, COMMAND, READ);
+        assert_eq!(read(&semihost, ARG), 0);
+        write(&semihost, COMMAND, CLOSE);
+        write(&semihost, COMMAND, CLOSE);
+        assert_eq!(read(&semihost, COMMAND), ERROR);
+
+        for name in [&b"../out.txt"[..], b"/etc/passwd", b""] {
+            send(&semihost, name);
+            write(&semihost, COMMAND, OPEN);
+            assert_eq!(read(&semihost, COMMAND), ERROR);
+        }
+        std::fs::remove_dir_all(root).unwrap();
+
+        write(&semihost, ARG, 3);
+        write(&semihost, COMMAND, EXIT);
+        assert_eq!(*exit.lock().unwrap(), Some(3));
+        send(&semihost, b"x == 1");
+        write(&semihost, COMMAND, ASSERT);
+        assert_eq!(*exit.lock().unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,349 @@
+//! Expect-style automation of the console, for end-to-end tests of ROMs
+//!
+//! A `Console` runs a machine as fast as it can, typing on its keyboard and
+//! capturing what it writes to the display. `expect` runs until some text
+//! shows up in the output, within a budget of cycles:
+//!
+//! ```no_run
+//! # use e6502::harness::Console;
+//! let mut console = Console::boot("wozmon.rom").unwrap();
+//! console.expect("\\", 100_000).unwrap();
+//! console.send("FF00.FF0F\n");
+//! console.expect("FF08:", 1_000_000).unwrap();
+//! ```
+//!
+//! The same can be written as a `Script` and run with `e6502 --script`.
+
+use regex::Regex;
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::hardware::bus::Bus;
+use crate::hardware::clock::Clock;
+use crate::hardware::cpu::{StepOutcome, CPU};
+use crate::hardware::display::Display;
+use crate::hardware::keyboard::{Input, Keyboard};
+use crate::hardware::standard_bus;
+
+/// Cycles an `expect` in a script may take, unless the script says otherwise
+pub const DEFAULT_BUDGET: u64 = 1_000_000;
+
+pub struct Console {
+    cpu: CPU,
+    keys: Sender<u8>,
+    output: Arc<Mutex<Vec<u8>>>,
+    /// Output up to here has been matched already
+    matched: usize,
+}
+
+impl Console {
+    /// Reset the machine `machine` connects the console's keyboard and
+    /// display to
+    pub fn new(
+        machine: impl FnOnce(Keyboard, Display) -> Result<Bus, String>,
+    ) -> Result<Self, String> {
+        let clk = Arc::new(Clock::free_running());
+        let (keys, input) = mpsc::channel();
+        let keyboard = Keyboard::with_input(Input::Live(input), clk.clone());
+        let output = Arc::new(Mutex::new(vec![]));
+        let display = Display::with_output({
+            let output = output.clone();
+            move |c| output.lock().unwrap().push(c)
+        });
+
+        let mut cpu = CPU::new(machine(keyboard, display)?, clk);
+        cpu.reset();
+        Ok(Self {
+            cpu,
+            keys,
+            output,
+            matched: 0,
+        })
+    }
+
+    /// Reset the standard machine with `rom` loaded, as `e6502 -l <rom>` does
+    pub fn boot(rom: impl AsRef<Path>) -> Result<Self, String> {
+        let rom = rom.as_ref().to_string_lossy().to_string();
+        Self::new(|keyboard, display| standard_bus(Some(rom), keyboard, display))
+    }
+
+    pub fn cpu(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    /// Everything written to the display so far
+    pub fn output(&self) -> String {
+        text(&self.output.lock().unwrap())
+    }
+
+    /// Type `text` on the keyboard
+    ///
+    /// The program gets to read it as soon as it looks at the keyboard.
+    pub fn send(&mut self, text: &str) {
+        for c in text.bytes() {
+            // The keyboard, and with it the receiver, lives as long as `self`
+            self.keys.send(c).unwrap();
+        }
+    }
+
+    /// Run until `text` is written after the last match, for at most
+    /// `budget` cycles
+    ///
+    /// Returns the output from the last match up to the end of this one.
+    pub fn expect(&mut self, text: &str, budget: u64) -> Result<String, String> {
+        self.run_until(budget, &format!("{text:?}"), |output| {
+            output.find(text).map(|start| start + text.len())
+        })
+    }
+
+    /// Like `expect`, but waits for a match of `regex`
+    pub fn expect_regex(&mut self, regex: &Regex, budget: u64) -> Result<String, String> {
+        self.run_until(budget, &format!("/{regex}/"), |output| {
+            regex.find(output).map(|m| m.end())
+        })
+    }
+
+    /// Run until `find` finds the end of a match in the new output
+    fn run_until(
+        &mut self,
+        budget: u64,
+        what: &str,
+        find: impl Fn(&str) -> Option<usize>,
+    ) -> Result<String, String> {
+        let start = self.cpu.clock().ticks();
+        let mut seen = None;
+        loop {
+            let output = self.output.lock().unwrap();
+            if seen != Some(output.len()) {
+                seen = Some(output.len());
+                let new = text(&output[self.matched..]);
+                if let Some(end) = find(&new) {
+                    self.matched += new[..end].chars().count();
+                    return Ok(new[..end].to_string());
+                }
+            }
+            drop(output);
+
+            let cycles = self.cpu.clock().ticks() - start;
+            if cycles >= budget {
+                return Err(format!(
+                    "No {what} within {budget} cycles, got {:?}",
+                    self.unmatched()
+                ));
+            }
+            match self.cpu.exec() {
+                Ok(StepOutcome::Continue) => (),
+                Ok(_) => {
+                    return Err(format!(
+                        "CPU trapped waiting for {what}, got {:?}",
+                        self.unmatched()
+                    ))
+                }
+                Err(e) => return Err(format!("{e} waiting for {what}")),
+            }
+        }
+    }
+
+    /// The output after the last match
+    fn unmatched(&self) -> String {
+        text(&self.output.lock().unwrap()[self.matched..])
+    }
+}
+
+/// `output` as the display shows it, a character per byte
+fn text(output: &[u8]) -> String {
+    output.iter().map(|&b| b as char).collect()
+}
+
+#[derive(Debug)]
+enum Step {
+    Send(String),
+    Expect(String),
+    ExpectRegex(Regex),
+    Timeout(u64),
+}
+
+/// A list of things to type and to wait for, one per line:
+///
+/// ```text
+/// # Dump the reset code
+/// timeout 200000
+/// expect "\\"
+/// send "FF00.FF0F\n"
+/// expect-regex "FF00: ([0-9A-F]{2} ?){8}"
+/// ```
+///
+/// `timeout` sets the cycles each `expect` after it may take. Strings are
+/// quoted, with the escapes `\n`, `\r`, `\t`, `\\`, `\"` and `\xNN`.
+#[derive(Debug)]
+pub struct Script {
+    steps: Vec<(usize, Step)>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut steps = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |e: String| format!("Line {}: {e}", n + 1);
+            let (cmd, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let step = match cmd {
+                "send" => Step::Send(unquote(arg.trim()).map_err(error)?),
+                "expect" => Step::Expect(unquote(arg.trim()).map_err(error)?),
+                "expect-regex" => {
+                    let regex = unquote(arg.trim()).map_err(error)?;
+                    Step::ExpectRegex(Regex::new(&regex).map_err(|e| error(e.to_string()))?)
+                }
+                "timeout" => {
+                    let cycles = arg.split_whitespace().next().unwrap_or("");
+                    Step::Timeout(
+                        cycles
+                            .parse()
+                            .map_err(|_| error(format!("Invalid cycles {cycles:?}")))?,
+                    )
+                }
+                _ => return Err(error(format!("Unknown command {cmd:?}"))),
+            };
+            steps.push((n + 1, step));
+        }
+        Ok(Self { steps })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Run the script on `console`, copying the output matched to `out`
+    pub fn run(&self, console: &mut Console, out: &mut impl Write) -> Result<(), String> {
+        let mut budget = DEFAULT_BUDGET;
+        for (line, step) in &self.steps {
+            let matched = match step {
+                Step::Send(text) => {
+                    console.send(text);
+                    continue;
+                }
+                Step::Timeout(cycles) => {
+                    budget = *cycles;
+                    continue;
+                }
+                Step::Expect(text) => console.expect(text, budget),
+                Step::ExpectRegex(regex) => console.expect_regex(regex, budget),
+            };
+            let matched = matched.map_err(|e| format!("Line {line}: {e}"))?;
+            out.write_all(matched.as_bytes())
+                .and_then(|_| out.flush())
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// The contents of the quoted string `arg`, with escapes resolved
+fn unquote(arg: &str) -> Result<String, String> {
+    let Some(inner) = arg.strip_prefix('"').and_then(|a| a.strip_suffix('"')) else {
+        return Err(format!("Expected a quoted string, got {arg:?}"));
+    };
+
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            Some('t') => text.push('\t'),
+            Some('\\') => text.push('\\'),
+            Some('"') => text.push('"'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte =
+                    u8::from_str_radix(&hex, 16).map_err(|_| format!("Invalid escape \\x{hex}"))?;
+                text.push(byte as char);
+            }
+            Some(c) => return Err(format!("Invalid escape \\{c}")),
+            None => return Err("Unterminated escape".to_string()),
+        }
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Prints `>` then echoes every key typed, in upper case
+    fn echo_rom(name: &str) -> std::path::PathBuf {
+        let mut rom = vec![0; 0x8100];
+        let code = [
+            0xA9, 0x3E, // LDA #'>'
+            0x8D, 0x02, 0x50, // STA $5002
+            0xAD, 0x01, 0x50, // loop: LDA $5001
+            0xF0, 0xFB, // BEQ loop
+            0xAD, 0x00, 0x50, // LDA $5000
+            0xC9, 0x61, // CMP #'a'
+            0x90, 0x02, // BCC print
+            0x29, 0xDF, // AND #$DF
+            0x8D, 0x02, 0x50, // print: STA $5002
+            0x4C, 0x05, 0xFF, // JMP loop
+        ];
+        rom[0x8000..0x8000 + code.len()].copy_from_slice(&code);
+        rom[0x80FC] = 0x00;
+        rom[0x80FD] = 0xFF;
+
+        let path = std::env::temp_dir().join(format!("e6502-{name}-{}.rom", std::process::id()));
+        std::fs::write(&path, rom).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_console() {
+        let rom = echo_rom("console");
+        let mut console = Console::boot(&rom).unwrap();
+        std::fs::remove_file(rom).unwrap();
+
+        assert_eq!(console.expect(">", 100), Ok(">".to_string()));
+        console.send("ff00.ff0f\n");
+        assert_eq!(console.expect(".", 1000), Ok("FF00.".to_string()));
+        let regex = Regex::new("F+\n").unwrap();
+        assert_eq!(console.expect_regex(&regex, 1000), Ok("FF0F\n".to_string()));
+        assert!(console.expect("FF", 1000).is_err());
+        assert_eq!(console.output(), ">FF00.FF0F\n");
+    }
+
+    #[test]
+    fn test_script() {
+        let script = Script::parse(
+            "# Comment\n\
+             timeout 1000\n\
+             expect \">\"\n\
+             send \"hi\\x21\\n\"\n\
+             expect-regex \"H.!\"\n",
+        )
+        .unwrap();
+        let rom = echo_rom("script");
+        let mut console = Console::boot(&rom).unwrap();
+        std::fs::remove_file(rom).unwrap();
+
+        let mut out = vec![];
+        assert!(script.run(&mut console, &mut out).is_ok());
+        assert_eq!(out, b">HI!");
+
+        assert!(Script::parse("expect >").is_err());
+        assert!(Script::parse("send \"\\q\"").is_err());
+        assert_eq!(
+            Script::parse("\n\nwait 10").unwrap_err(),
+            "Line 3: Unknown command \"wait\""
+        );
+    }
+}
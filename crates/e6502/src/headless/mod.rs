@@ -0,0 +1,313 @@
+//! Running a ROM without anyone watching, e.g. a test suite in CI
+//!
+//! `Headless` runs until one of its stop conditions and reports the outcome:
+//!
+//! * a trap, `jmp *` or `bne *`, or a `BRK` when asked to stop there: a pass
+//!   at the `pass_at` address, a failure anywhere else, a pass if there is no
+//!   such address
+//! * a write to the exit port: a pass if the value written is 0, a failure
+//!   otherwise
+//! * an exit through semihosting: a pass if the exit code is 0, a failure
+//!   otherwise
+//! * the cycle limit or the wall-clock timeout: a timeout
+//! * an illegal instruction or a jam, which stops the CPU
+//! * any other error from the CPU, e.g. a write to ROM: a failure
+
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::hardware::cpu::instructions::Instruction;
+use crate::hardware::cpu::{decode, Register, StepOutcome, CPU};
+use crate::hardware::error::{EmuError, ErrorKind};
+use crate::types::{Addr, Byte};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    Timeout,
+    Illegal,
+}
+
+impl Outcome {
+    /// The exit code of `e6502 run`: 0, 1, 124 like timeout(1) and 132 like
+    /// a process killed by SIGILL
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Pass => 0,
+            Outcome::Fail => 1,
+            Outcome::Timeout => 124,
+            Outcome::Illegal => 132,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "fail",
+            Outcome::Timeout => "timeout",
+            Outcome::Illegal => "illegal",
+        }
+    }
+}
+
+/// Why a headless run stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Trap,
+    Brk,
+    ExitPort(Byte),
+    Exit(u8),
+    CycleLimit,
+    Timeout,
+    Illegal(u8),
+    Error(EmuError),
+}
+
+impl Stop {
+    fn name(self) -> &'static str {
+        match self {
+            Stop::Trap => "trap",
+            Stop::Brk => "brk",
+            Stop::ExitPort(_) => "exit-port",
+            Stop::Exit(_) => "exit",
+            Stop::CycleLimit => "cycle-limit",
+            Stop::Timeout => "timeout",
+            Stop::Illegal(_) => "illegal",
+            Stop::Error(_) => "error",
+        }
+    }
+}
+
+/// How a headless run ended
+#[derive(Debug)]
+pub struct Report {
+    pub outcome: Outcome,
+    pub stop: Stop,
+    /// Where the CPU stopped: the trap, the `BRK`, the illegal instruction, or
+    /// the instruction after the last one run
+    pub pc: Addr,
+    pub cycles: u64,
+    pub instructions: u64,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// The report and the final state of `cpu` as JSON
+    pub fn summary(&self, cpu: &CPU) -> Value {
+        let reg = |reg| cpu.get_reg(reg).unwrap_left().0;
+        let mut summary = json!({
+            "outcome": self.outcome.name(),
+            "stop": self.stop.name(),
+            "pc": self.pc.0,
+            "a": reg(Register::A),
+            "x": reg(Register::X),
+            "y": reg(Register::Y),
+            "sp": reg(Register::SP),
+            "ps": reg(Register::PS),
+            "cycles": self.cycles,
+            "instructions": self.instructions,
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+        });
+        match self.stop {
+            Stop::ExitPort(value) => summary["exit_value"] = json!(value.0),
+            Stop::Exit(code) => summary["exit_code"] = json!(code),
+            Stop::Illegal(op_code) => summary["op_code"] = json!(op_code),
+            Stop::Error(e) => summary["error"] = json!(e.to_string()),
+            _ => (),
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Headless {
+    pass_at: Option<Addr>,
+    brk: bool,
+    exit_port: Option<Addr>,
+    exit: Option<Arc<Mutex<Option<u8>>>>,
+    max_cycles: Option<u64>,
+    timeout: Option<Duration>,
+}
+
+impl Headless {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Traps and `BRK`s pass at `addr` and fail anywhere else
+    pub fn pass_at(mut self, addr: Addr) -> Self {
+        self.pass_at = Some(addr);
+        self
+    }
+
+    /// Stop at a `BRK` instead of running it
+    pub fn brk(mut self) -> Self {
+        self.brk = true;
+        self
+    }
+
+    /// Stop once anything is written to `addr`
+    pub fn exit_port(mut self, addr: Addr) -> Self {
+        self.exit_port = Some(addr);
+        self
+    }
+
+    /// Stop once the program exits through semihosting, which hands the exit
+    /// code to `exit`
+    pub fn semihost(mut self, exit: Arc<Mutex<Option<u8>>>) -> Self {
+        self.exit = Some(exit);
+        self
+    }
+
+    /// Give up after `cycles` cycles
+    pub fn max_cycles(mut self, cycles: u64) -> Self {
+        self.max_cycles = Some(cycles);
+        self
+    }
+
+    /// Give up after `timeout` has passed on the wall clock
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run `cpu` from where it is until a stop condition is met
+    ///
+    /// The CPU is put in debug mode, which runs as fast as it can and lets
+    /// the writes to the exit port be seen.
+    pub fn run(&self, cpu: &mut CPU) -> Report {
+        cpu.debug();
+        cpu.take_writes();
+        let start = Instant::now();
+        let first = cpu.clock().ticks();
+        let mut instructions = 0;
+
+        let stop = loop {
+            let pc = cpu.get_reg(Register::PC).unwrap_right();
+            if self
+                .max_cycles
+                .is_some_and(|max| cpu.clock().ticks() - first >= max)
+            {
+                break Stop::CycleLimit;
+            }
+            if self
+                .timeout
+                .is_some_and(|timeout| start.elapsed() >= timeout)
+            {
+                break Stop::Timeout;
+            }
+            if self.brk && decode(cpu.peek(pc)).0 == Instruction::BRK {
+                break Stop::Brk;
+            }
+
+            let step = cpu.exec();
+            instructions += 1;
+            let writes = cpu.take_writes();
+            if let Some(port) = self.exit_port {
+                if let Some(&(_, value)) = writes.iter().rev().find(|(addr, _)| *addr == port) {
+                    break Stop::ExitPort(value);
+                }
+            }
+            if let Some(code) = self.exit.as_ref().and_then(|e| e.lock().unwrap().take()) {
+                break Stop::Exit(code);
+            }
+            match step {
+                Ok(StepOutcome::Trapped) => break Stop::Trap,
+                Err(EmuError {
+                    kind: ErrorKind::Jam(op_code) | ErrorKind::IllegalOpcode(op_code),
+                    ..
+                }) => break Stop::Illegal(op_code),
+                Err(e) => break Stop::Error(e),
+                Ok(_) => (),
+            }
+        };
+
+        let pc = cpu.get_reg(Register::PC).unwrap_right();
+        let outcome = match stop {
+            Stop::Trap | Stop::Brk if self.pass_at.is_none_or(|addr| addr == pc) => Outcome::Pass,
+            Stop::Trap | Stop::Brk => Outcome::Fail,
+            Stop::ExitPort(value) if value.0 == 0 => Outcome::Pass,
+            Stop::ExitPort(_) => Outcome::Fail,
+            Stop::Exit(0) => Outcome::Pass,
+            Stop::Exit(_) => Outcome::Fail,
+            Stop::CycleLimit | Stop::Timeout => Outcome::Timeout,
+            Stop::Illegal(_) => Outcome::Illegal,
+            Stop::Error(_) => Outcome::Fail,
+        };
+        Report {
+            outcome,
+            stop,
+            pc,
+            cycles: cpu.clock().ticks() - first,
+            instructions,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::Test;
+
+    /// A count down from 3, then `last` at $405 and `jmp *` at $406
+    fn program(last: u8) -> CPU {
+        let source = format!("ldx #3\n@loop: dex\n bne @loop\n .byte {last}\n jmp *");
+        let (cpu, _) = Test::new(&source).prepare();
+        cpu
+    }
+
+    #[test]
+    fn test_headless() {
+        const NOP: u8 = 0xEA;
+
+        let mut cpu = program(NOP);
+        let report = Headless::new().run(&mut cpu);
+        assert_eq!(report.outcome, Outcome::Pass);
+        assert_eq!(report.stop, Stop::Trap);
+        assert_eq!(report.pc, Addr(0x406));
+        assert_eq!(report.instructions, 9);
+
+        let report = Headless::new().pass_at(Addr(0x500)).run(&mut program(NOP));
+        assert_eq!((report.outcome, report.stop), (Outcome::Fail, Stop::Trap));
+
+        let report = Headless::new()
+            .brk()
+            .pass_at(Addr(0x405))
+            .run(&mut program(0x00));
+        assert_eq!((report.outcome, report.pc), (Outcome::Pass, Addr(0x405)));
+
+        let report = Headless::new().run(&mut program(0x02));
+        assert_eq!(report.outcome, Outcome::Illegal);
+        assert_eq!(report.stop, Stop::Illegal(0x02));
+
+        let report = Headless::new().max_cycles(10).run(&mut program(NOP));
+        assert_eq!(report.outcome, Outcome::Timeout);
+        assert!(report.cycles >= 10 && report.cycles < 20);
+
+        // The loop writes X to $10 on the way out
+        let mut cpu = program(0x86);
+        cpu.poke(Addr(0x406), 0x10);
+        cpu.poke(Addr(0x407), 0x4C);
+        cpu.poke(Addr(0x408), 0x07);
+        cpu.poke(Addr(0x409), 0x04);
+        let report = Headless::new().exit_port(Addr(0x10)).run(&mut cpu);
+        assert_eq!(
+            (report.outcome, report.stop),
+            (Outcome::Pass, Stop::ExitPort(Byte(0)))
+        );
+        let summary = report.summary(&cpu);
+        assert_eq!(summary["outcome"], "pass");
+        assert_eq!(summary["exit_value"], 0);
+        assert_eq!(summary["x"], 0);
+
+        let exit = Arc::new(Mutex::new(Some(3)));
+        let report = Headless::new().semihost(exit).run(&mut program(NOP));
+        assert_eq!(
+            (report.outcome, report.stop, report.instructions),
+            (Outcome::Fail, Stop::Exit(3), 1)
+        );
+    }
+}
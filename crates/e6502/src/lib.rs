@@ -1,5 +1,15 @@
+pub mod asm;
+pub mod coverage;
 pub mod debugger;
+pub mod disasm;
 pub mod hardware;
+pub mod harness;
+pub mod headless;
+pub mod profile;
+pub mod replay;
+pub mod savestate;
+pub mod testing;
+pub mod trace;
 pub mod types;
 
 #[macro_export]
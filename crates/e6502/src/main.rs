@@ -1,56 +1,792 @@
 #![allow(arithmetic_overflow)]
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use e6502::asm::assemble;
+use e6502::coverage::Coverage;
+use e6502::debugger::dap::Dap;
+use e6502::debugger::gdb::GdbStub;
+use e6502::debugger::source::Sources;
+use e6502::debugger::symbols::Symbols;
+use e6502::debugger::vice::BinaryMonitor;
 use e6502::debugger::Debugger;
+use e6502::disasm::analysis::analyze;
+use e6502::hardware::bus::{Bus, Unmapped};
 use e6502::hardware::clock::Clock;
+use e6502::hardware::cpu::{Register, StackGuard, StepOutcome, CPU};
 use e6502::hardware::display::Display;
-use e6502::hardware::keyboard::Keyboard;
-use e6502::hardware::rom::Rom;
-use e6502::{hardware::bus::Bus, hardware::cpu::CPU, hardware::memory::Memory};
-use std::sync::Arc;
+use e6502::hardware::keyboard::{Input, Keyboard};
+use e6502::hardware::memory::Pattern;
+use e6502::hardware::semihost::Semihost;
+use e6502::hardware::standard_bus;
+use e6502::harness::{Console, Script};
+use e6502::headless::Headless;
+use e6502::profile::Profiler;
+use e6502::replay::{End, Recorder, Recording};
+use e6502::savestate::SaveState;
+use e6502::trace::{Tracer, Trigger};
+use e6502::types::Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 
 // mod visualize;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, global = true)]
     load: Option<String>,
 
+    /// Map the semihosting device at this address, for test programs to exit,
+    /// print and use host files through
+    #[arg(long, global = true)]
+    semihost: Option<String>,
+
+    /// Directory the semihosting device may open files in
+    #[arg(long, global = true, default_value = ".", requires = "semihost")]
+    semihost_root: String,
+
+    /// What reads from unmapped addresses, and writes to them or to ROM, do:
+    /// `open-bus` reads the last byte on the data bus, a byte like `0xFF`
+    /// reads that, `log` reports them and `break` stops with an error
+    #[arg(long, global = true, default_value = "open-bus")]
+    unmapped: String,
+
+    /// Watch for stack overflows, underflows and writes below the stack
+    /// pointer, and `warn` about them or `break` with an error
+    #[arg(long, global = true, value_name = "warn|break")]
+    stack_guard: Option<String>,
+
+    /// What RAM holds at power-on: `zero`, a byte like `0xFF` or `random`,
+    /// optionally seeded with `random:<seed>`, which randomises the registers too
+    #[arg(long, global = true, default_value = "zero")]
+    ram_pattern: String,
+
     #[arg(long)]
     visualize: bool,
 
     #[arg(long)]
     debug: bool,
+
+    /// ld65 debug info file (`ld65 --dbgfile`) to load symbols from
+    #[arg(long)]
+    dbgfile: Option<String>,
+
+    /// VICE label file (`ld65 -Ln`) to load symbols from
+    #[arg(long)]
+    labels: Option<String>,
+
+    /// Memory in MiB the debugger may use to record history for reverse execution
+    #[arg(long, default_value_t = 64)]
+    history_mb: usize,
+
+    /// Log every executed instruction to this file, in nestest.log format
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// Start tracing at an address, or after a number of cycles with `cycle:<n>`
+    #[arg(long, requires = "trace")]
+    trace_start: Option<String>,
+
+    /// Stop tracing at an address, or after a number of cycles with `cycle:<n>`
+    #[arg(long, requires = "trace")]
+    trace_stop: Option<String>,
+
+    /// Include the effective address and value of memory operands in the trace
+    #[arg(long, requires = "trace")]
+    trace_effective: bool,
+
+    /// Serve the Debug Adapter Protocol, on `stdio` or on a local TCP port
+    #[arg(long, value_name = "stdio|PORT")]
+    dap: Option<String>,
+
+    /// Serve the GDB remote serial protocol on a local TCP port
+    #[arg(long, value_name = "PORT")]
+    gdb: Option<u16>,
+
+    /// Serve the VICE binary monitor protocol on a local TCP port
+    #[arg(long, value_name = "PORT")]
+    binary_monitor: Option<u16>,
+
+    /// Run as fast as possible, profiling, and write the report to this file
+    /// once the CPU traps
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Also write the cycles per call stack to this file, for flamegraph tools
+    #[arg(long, requires = "profile")]
+    profile_collapsed: Option<String>,
+
+    /// Run as fast as possible, recording code coverage, and write the
+    /// coverage map to this file once the CPU traps
+    #[arg(long, conflicts_with = "profile")]
+    coverage: Option<String>,
+
+    /// Also write the coverage as an lcov tracefile, by the lines in `--dbgfile`
+    #[arg(long, requires_all = ["coverage", "dbgfile"])]
+    lcov: Option<String>,
+
+    /// Run as fast as possible and save the machine to this file once the CPU
+    /// traps or `--stop` is hit
+    #[arg(long, conflicts_with_all = ["profile", "coverage"])]
+    save_state: Option<String>,
+
+    /// Start from the machine saved in this file instead of a reset
+    #[arg(long)]
+    load_state: Option<String>,
+
+    /// Record every key typed, with the cycle it arrived at, to this file;
+    /// Ctrl-C ends the session
+    #[arg(long, conflicts_with_all = ["debug", "gdb", "binary_monitor", "profile", "coverage", "save_state"])]
+    record: Option<String>,
+
+    /// Run as fast as possible, typing the keys recorded in this file with
+    /// `--record`, and check the machine ends up in the recorded state
+    #[arg(long, conflicts_with_all = ["record", "debug", "gdb", "binary_monitor", "profile", "coverage", "save_state"])]
+    replay: Option<String>,
+
+    /// Run this expect-style script against the ROM, typing on the keyboard
+    /// and waiting for display output, and fail if it doesn't go as expected
+    #[arg(long, requires = "load")]
+    script: Option<String>,
+
+    /// Stop profiling, recording coverage or running for `--save-state` at an
+    /// address, or after a number of cycles with `cycle:<n>`
+    #[arg(long)]
+    stop: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the ROM headless until a stop condition, print a JSON summary of
+    /// the final CPU state and exit with 0 on a pass, 1 on a failure, 124 on
+    /// a timeout or 132 on an illegal instruction
+    ///
+    /// A trap (`jmp *`) always stops the run. Display output goes to stderr.
+    Run(RunArgs),
+
+    /// Assemble a 6502 source file to a binary, from its lowest address to
+    /// its highest
+    Asm(AsmArgs),
+
+    /// Disassemble a ROM image to ca65 source that assembles back to it,
+    /// telling code from data by following the code from the reset, NMI and
+    /// IRQ vectors
+    Disasm(DisasmArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct DisasmArgs {
+    rom: String,
+
+    /// Where the image is loaded, by default so that it ends at $FFFF
+    #[arg(long)]
+    base: Option<String>,
+
+    /// Follow the code from here too, e.g. for routines only called through
+    /// a table
+    #[arg(long)]
+    entry: Vec<String>,
+
+    /// Where to write the source, stdout if not given
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct AsmArgs {
+    source: String,
+
+    /// Where to write the binary, the source with `.bin` for an extension if
+    /// not given
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Also write a listing of the source with addresses and bytes
+    #[arg(long)]
+    listing: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// Traps, and BRKs with `--brk`, pass at this address and fail anywhere
+    /// else; without it they always pass
+    #[arg(long)]
+    pass_at: Option<String>,
+
+    /// Stop at a BRK instead of running it
+    #[arg(long)]
+    brk: bool,
+
+    /// Stop once this address is written to, passing if the value is 0
+    #[arg(long)]
+    exit_port: Option<String>,
+
+    /// Time out after this many cycles
+    #[arg(long)]
+    max_cycles: Option<u64>,
+
+    /// Time out after this many seconds on the wall clock
+    #[arg(long)]
+    timeout: Option<f64>,
+}
+
+/// Map the semihosting device at `addr` on `bus`, if there is one, handing
+/// exit codes to `exit`
+fn semihost(
+    bus: &mut Bus,
+    addr: Option<&str>,
+    root: &str,
+    clk: Arc<Clock>,
+    exit: impl Fn(u8) + Send + 'static,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(addr) = addr else {
+        return Ok(());
+    };
+    bus.register(Semihost::new(Addr::try_from(addr)?, root, clk, exit))?;
+    Ok(())
+}
+
+/// Run `rom` headless, exiting with the outcome
+fn run(
+    rom: &str,
+    args: RunArgs,
+    semihost_at: Option<&str>,
+    semihost_root: &str,
+    unmapped: Unmapped,
+    stack_guard: Option<StackGuard>,
+    ram_pattern: Pattern,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut headless = Headless::new();
+    if let Some(addr) = args.pass_at {
+        headless = headless.pass_at(Addr::try_from(addr.as_str())?);
+    }
+    if args.brk {
+        headless = headless.brk();
+    }
+    if let Some(addr) = args.exit_port {
+        headless = headless.exit_port(Addr::try_from(addr.as_str())?);
+    }
+    if let Some(cycles) = args.max_cycles {
+        headless = headless.max_cycles(cycles);
+    }
+    if let Some(secs) = args.timeout {
+        headless = headless.timeout(std::time::Duration::try_from_secs_f64(secs)?);
+    }
+
+    let display = Display::with_output(|c| eprint!("{}", c as char));
+    let mut bus = standard_bus(Some(rom.to_string()), Keyboard::new(), display)?;
+    bus.set_unmapped(unmapped);
+    let clk = Arc::new(Clock::free_running());
+    let exit = Arc::new(Mutex::new(None));
+    if semihost_at.is_some() {
+        headless = headless.semihost(exit.clone());
+    }
+    semihost(
+        &mut bus,
+        semihost_at,
+        semihost_root,
+        clk.clone(),
+        move |code| *exit.lock().unwrap() = Some(code),
+    )?;
+    let mut cpu = CPU::new(bus, clk);
+    if let Some(guard) = stack_guard {
+        cpu.stack_guard(guard);
+    }
+    cpu.ram_pattern(ram_pattern);
+    cpu.power_on();
+    cpu.reset();
+
+    let report = headless.run(&mut cpu);
+    println!("{}", report.summary(&cpu));
+    std::process::exit(report.outcome.exit_code());
+}
+
+/// Assemble `args.source` to a binary and maybe a listing
+fn asm(args: AsmArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let source =
+        std::fs::read_to_string(&args.source).map_err(|e| format!("{}: {e}", args.source))?;
+    let program = assemble(&source).map_err(|e| format!("{}: {e}", args.source))?;
+
+    let output = args.output.unwrap_or_else(|| {
+        std::path::Path::new(&args.source)
+            .with_extension("bin")
+            .to_string_lossy()
+            .to_string()
+    });
+    let (start, image) = program.image();
+    std::fs::write(&output, &image).map_err(|e| format!("{output}: {e}"))?;
+    eprintln!(
+        "Wrote {} bytes from ${:04X} to {output}",
+        image.len(),
+        start.0
+    );
+    if let Some(path) = args.listing {
+        std::fs::write(&path, program.listing()).map_err(|e| format!("{path}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Disassemble `args.rom` to source
+fn disasm(args: DisasmArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let image = std::fs::read(&args.rom).map_err(|e| format!("{}: {e}", args.rom))?;
+    let base = match args.base {
+        Some(base) => Addr::try_from(base.as_str())?,
+        None => Addr(0x10000usize.saturating_sub(image.len()) as u16),
+    };
+    let entries = args
+        .entry
+        .iter()
+        .map(|entry| Addr::try_from(entry.as_str()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let source = analyze(&image, base, &entries)?.source();
+    match args.output {
+        Some(path) => std::fs::write(&path, source).map_err(|e| format!("{path}: {e}"))?,
+        None => print!("{source}"),
+    }
+    Ok(())
+}
+
+/// Serve one DAP session, the machine is built when the client launches a ROM
+fn serve_dap(
+    transport: &str,
+    unmapped: Unmapped,
+    stack_guard: Option<StackGuard>,
+    ram_pattern: Pattern,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let machine = Box::new(move |rom: &str, display| {
+        let mut bus = standard_bus(Some(rom.to_string()), Keyboard::new(), display)?;
+        bus.set_unmapped(unmapped);
+        let mut cpu = CPU::new(bus, Arc::new(Clock::new()));
+        if let Some(guard) = stack_guard {
+            cpu.stack_guard(guard);
+        }
+        cpu.ram_pattern(ram_pattern);
+        cpu.power_on();
+        Ok(cpu)
+    });
+
+    if transport == "stdio" {
+        let stdin = std::io::BufReader::new(std::io::stdin());
+        Dap::new(machine, std::io::stdout()).serve(stdin)?;
+    } else {
+        let port: u16 = transport.parse()?;
+        let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+        eprintln!("Waiting for a DAP client on port {port}");
+        let (stream, _) = listener.accept()?;
+        let reader = std::io::BufReader::new(stream.try_clone()?);
+        Dap::new(machine, stream).serve(reader)?;
+    }
+    Ok(())
+}
+
+/// Run `script` on the machine with `rom` loaded, printing the output it matches
+fn run_script(
+    rom: &str,
+    script: &str,
+    unmapped: Unmapped,
+    stack_guard: Option<StackGuard>,
+    ram_pattern: Pattern,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let script = Script::load(script)?;
+    let mut console = Console::new(|keyboard, display| {
+        let mut bus = standard_bus(Some(rom.to_string()), keyboard, display)?;
+        bus.set_unmapped(unmapped);
+        Ok(bus)
+    })?;
+    if let Some(guard) = stack_guard {
+        console.cpu().stack_guard(guard);
+    }
+    console.cpu().ram_pattern(ram_pattern);
+    console.cpu().power_on();
+    script.run(&mut console, &mut std::io::stdout())?;
+    Ok(())
+}
+
+/// Serve one GDB session over `debugger`
+fn serve_gdb(debugger: Debugger, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Waiting for GDB on port {port}");
+    let (stream, _) = listener.accept()?;
+    GdbStub::new(debugger, stream.try_clone()?).serve(stream)?;
+    Ok(())
+}
+
+/// Serve one VICE binary monitor session over `debugger`
+fn serve_binary_monitor(debugger: Debugger, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Waiting for a binary monitor client on port {port}");
+    let (stream, _) = listener.accept()?;
+    BinaryMonitor::new(debugger, stream.try_clone()?).serve(stream)?;
+    Ok(())
+}
+
+/// Trace the instruction `cpu` is about to execute, dropping the tracer on errors
+fn trace(tracer: &mut Option<Tracer>, cpu: &CPU) {
+    if let Some(ref mut t) = tracer {
+        if let Err(e) = t.trace(cpu) {
+            println!("Tracing stopped: {e}");
+            *tracer = None;
+        }
+    }
+}
+
+/// Run `cpu` until it traps or `profiler` stops, then write the reports
+fn profile(
+    mut cpu: CPU,
+    mut profiler: Profiler,
+    mut tracer: Option<Tracer>,
+    symbols: &Symbols,
+    report: &str,
+    collapsed: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        trace(&mut tracer, &cpu);
+        match profiler.exec(&mut cpu) {
+            Ok(StepOutcome::Continue) => (),
+            Ok(_) => break,
+            Err(e) => {
+                eprintln!("{e}");
+                break;
+            }
+        }
+    }
+
+    let mut out = std::io::BufWriter::new(std::fs::File::create(report)?);
+    profiler.report(&mut out, &cpu, symbols)?;
+    eprintln!("Profile written to {report}");
+    if let Some(path) = collapsed {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        profiler.collapsed(&mut out, symbols)?;
+        eprintln!("Call stacks written to {path}");
+    }
+    Ok(())
+}
+
+/// Run `cpu` until it traps or `coverage` stops, then write the coverage
+fn cover(
+    mut cpu: CPU,
+    mut coverage: Coverage,
+    mut tracer: Option<Tracer>,
+    map: &str,
+    lcov: Option<(String, Sources)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        trace(&mut tracer, &cpu);
+        match coverage.exec(&mut cpu) {
+            Ok(StepOutcome::Continue) => (),
+            Ok(_) => break,
+            Err(e) => {
+                eprintln!("{e}");
+                break;
+            }
+        }
+    }
+
+    let mut out = std::io::BufWriter::new(std::fs::File::create(map)?);
+    coverage.write_map(&mut out)?;
+    eprintln!("Coverage map written to {map}");
+    if let Some((path, sources)) = lcov {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        coverage.write_lcov(&mut out, &sources, |addr| cpu.peek(addr))?;
+        eprintln!("Coverage written to {path}");
+    }
+    Ok(())
+}
+
+/// Run `cpu` until it traps or `stop` is hit, then save the machine to `path`
+fn save_state(
+    mut cpu: CPU,
+    mut tracer: Option<Tracer>,
+    stop: Option<Trigger>,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let pc = cpu.get_reg(Register::PC).unwrap_right();
+        if stop.is_some_and(|t| t.hit(pc, cpu.clock().ticks())) {
+            break;
+        }
+        trace(&mut tracer, &cpu);
+        if cpu.exec()? != StepOutcome::Continue {
+            break;
+        }
+    }
+
+    SaveState::capture(&cpu).save(path)?;
+    eprintln!(
+        "Saved the machine at cycle {} to {path}",
+        cpu.clock().ticks()
+    );
+    Ok(())
+}
+
+/// Run `cpu` to the `end` of a recording and check it is in the recorded state
+fn replay(
+    mut cpu: CPU,
+    mut tracer: Option<Tracer>,
+    end: End,
+) -> Result<(), Box<dyn std::error::Error>> {
+    while cpu.clock().ticks() < end.cycle {
+        trace(&mut tracer, &cpu);
+        // The recording carried on past errors, and so does the replay
+        let _ = cpu.exec();
+    }
+
+    let cycle = cpu.clock().ticks();
+    let hash = SaveState::capture(&cpu).hash();
+    if cycle != end.cycle || hash != end.hash {
+        return Err(format!(
+            "Replay diverged: state {hash:016x} at cycle {cycle}, recorded {:016x} at cycle {}",
+            end.hash, end.cycle
+        )
+        .into());
+    }
+    eprintln!("Replay matches the recording at cycle {cycle}");
+    Ok(())
 }
 
 #[allow(arithmetic_overflow)]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    let unmapped = Unmapped::try_from(args.unmapped.as_str())?;
+    let stack_guard = match args.stack_guard {
+        Some(ref guard) => Some(StackGuard::try_from(guard.as_str())?),
+        None => None,
+    };
+    let ram_pattern = Pattern::try_from(args.ram_pattern.as_str())?;
 
-    let mut bus = Bus::new(); // Everyone talks over this
+    if let Some(transport) = args.dap {
+        return serve_dap(&transport, unmapped, stack_guard, ram_pattern);
+    }
+
+    if let Some(Command::Asm(asm_args)) = args.command {
+        return asm(asm_args);
+    }
 
-    let memory = Memory::default();
-    let rom = Rom::new(args.load);
+    if let Some(Command::Disasm(disasm_args)) = args.command {
+        return disasm(disasm_args);
+    }
 
-    let keyboard = Keyboard::new();
-    let display = Display::new();
-    let clk = Arc::new(Clock::new());
+    if let Some(Command::Run(run_args)) = args.command {
+        let Some(rom) = args.load else {
+            return Err("run needs a ROM, --load <FILE>".into());
+        };
+        return run(
+            &rom,
+            run_args,
+            args.semihost.as_deref(),
+            &args.semihost_root,
+            unmapped,
+            stack_guard,
+            ram_pattern,
+        );
+    }
 
-    bus.register(memory)?;
-    bus.register(keyboard)?;
-    bus.register(display)?;
-    bus.register(rom)?;
+    if let (Some(script), Some(rom)) = (&args.script, &args.load) {
+        return run_script(rom, script, unmapped, stack_guard, ram_pattern);
+    }
+
+    let free = args.profile.is_some()
+        || args.coverage.is_some()
+        || args.save_state.is_some()
+        || args.replay.is_some();
+    let clk = Arc::new(match free {
+        true => Clock::free_running(),
+        false => Clock::new(),
+    });
+
+    let recording = args.replay.map(Recording::load).transpose()?;
+    let recorder = match args.record {
+        Some(path) => Some(Arc::new(Mutex::new(Recorder::create(path)?))),
+        None => None,
+    };
+    let (keys, input) = mpsc::channel();
+    let keyboard = match recording {
+        Some(ref recording) => {
+            Keyboard::with_input(Input::Replay(recording.keys.clone()), clk.clone())
+        }
+        None => Keyboard::with_input(Input::Live(input), clk.clone()),
+    };
+    let keyboard = match recorder {
+        Some(ref recorder) => {
+            let recorder = recorder.clone();
+            keyboard.record(move |cycle, key| {
+                if let Err(e) = recorder.lock().unwrap().key(cycle, key) {
+                    eprintln!("Recording: {e}");
+                }
+            })
+        }
+        None => keyboard,
+    };
+    let mut bus = standard_bus(args.load, keyboard, Display::new())?;
+    bus.set_unmapped(unmapped);
+    semihost(
+        &mut bus,
+        args.semihost.as_deref(),
+        &args.semihost_root,
+        clk.clone(),
+        |code| std::process::exit(code as i32),
+    )?;
+    let stop = match args.stop {
+        Some(stop) => Some(Trigger::try_from(stop.as_str())?),
+        None => None,
+    };
+
+    let mut tracer = match args.trace {
+        Some(path) => {
+            let mut tracer = Tracer::create(path)?.effective(args.trace_effective);
+            if let Some(start) = args.trace_start {
+                tracer = tracer.start(Trigger::try_from(start.as_str())?);
+            }
+            if let Some(stop) = args.trace_stop {
+                tracer = tracer.stop(Trigger::try_from(stop.as_str())?);
+            }
+            Some(tracer)
+        }
+        None => None,
+    };
 
     let mut cpu = CPU::new(bus, clk.clone());
-    if args.debug {
-        Debugger::new(cpu, true).start();
+    if let Some(guard) = stack_guard {
+        cpu.stack_guard(guard);
+    }
+    cpu.ram_pattern(ram_pattern);
+    let reset = match args.load_state {
+        Some(path) => {
+            SaveState::load(path)?.apply(&mut cpu)?;
+            false
+        }
+        None => {
+            cpu.power_on();
+            true
+        }
+    };
+    // Anything else resets on the thread that drives the clock
+    if free && reset {
+        cpu.reset();
+    }
+
+    if let Some(path) = args.save_state {
+        return save_state(cpu, tracer, stop, &path);
+    }
+
+    if let Some(recording) = recording {
+        return replay(cpu, tracer, recording.end);
     }
 
+    if let Some(report) = args.profile {
+        let mut profiler = Profiler::new();
+        if let Some(stop) = stop {
+            profiler = profiler.stop(stop);
+        }
+        let mut symbols = Symbols::new();
+        if let Some(dbgfile) = args.dbgfile {
+            symbols.load_dbgfile(dbgfile)?;
+        }
+        if let Some(labels) = args.labels {
+            symbols.load_labels(labels)?;
+        }
+        return profile(
+            cpu,
+            profiler,
+            tracer,
+            &symbols,
+            &report,
+            args.profile_collapsed,
+        );
+    }
+
+    if let Some(map) = args.coverage {
+        let mut coverage = Coverage::new();
+        if let Some(stop) = stop {
+            coverage = coverage.stop(stop);
+        }
+        let lcov = match (args.lcov, args.dbgfile) {
+            (Some(lcov), Some(dbgfile)) => {
+                let mut sources = Sources::new();
+                sources.load_dbgfile(dbgfile)?;
+                Some((lcov, sources))
+            }
+            _ => None,
+        };
+        return cover(cpu, coverage, tracer, &map, lcov);
+    }
+
+    if args.debug || args.gdb.is_some() || args.binary_monitor.is_some() {
+        let mut debugger = Debugger::new(cpu, reset);
+        debugger.set_history_budget(args.history_mb << 20);
+        if let Some(tracer) = tracer {
+            debugger.set_tracer(tracer);
+        }
+        if let Some(dbgfile) = args.dbgfile {
+            let (n, m) = debugger.load_dbgfile(dbgfile)?;
+            println!("Loaded {n} symbols and {m} line spans");
+        }
+        if let Some(labels) = args.labels {
+            let n = debugger.load_labels(labels)?;
+            println!("Loaded {n} symbols");
+        }
+        if let Some(port) = args.gdb {
+            return serve_gdb(debugger, port);
+        }
+        if let Some(port) = args.binary_monitor {
+            return serve_binary_monitor(debugger, port);
+        }
+        debugger.start();
+    }
+
+    // The session ends with Ctrl-C on the keyboard
+    let done = Arc::new(AtomicBool::new(false));
+    std::thread::spawn({
+        let done = done.clone();
+        move || match Keyboard::poll(keys) {
+            Ok(()) => done.store(true, Ordering::Release),
+            Err(e) => eprintln!("No keyboard: {e}"),
+        }
+    });
+
     std::thread::spawn(move || {
-        cpu.reset();
+        if reset {
+            cpu.reset();
+        }
         println!("Ready, set, go!");
+        let mut last = None;
+        let mut trapped = false;
         loop {
-            cpu.exec();
+            trace(&mut tracer, &cpu);
+            // Report errors and traps and carry on, but only once for a
+            // jammed or trapped CPU
+            match cpu.exec() {
+                Ok(outcome) => {
+                    let now = outcome == StepOutcome::Trapped;
+                    if now && !trapped {
+                        eprintln!("CPU TRAPPED\n{cpu}");
+                    }
+                    trapped = now;
+                }
+                Err(e) => {
+                    if last != Some((e.kind, e.pc)) {
+                        eprintln!("{e}");
+                        last = Some((e.kind, e.pc));
+                    }
+                }
+            }
+
+            if done.load(Ordering::Acquire) {
+                if let Some(recorder) = recorder {
+                    let end = End {
+                        cycle: cpu.clock().ticks(),
+                        hash: SaveState::capture(&cpu).hash(),
+                    };
+                    match recorder.lock().unwrap().end(end) {
+                        Ok(()) => eprintln!("Recorded up to cycle {}", end.cycle),
+                        Err(e) => eprintln!("Recording: {e}"),
+                    }
+                }
+                std::process::exit(0);
+            }
         }
     });
 
@@ -0,0 +1,375 @@
+//! Profiler showing where the cycles go
+//!
+//! Counts executed instructions and cycles per address, and follows `JSR` and
+//! `RTS` to charge cycles to subroutines, both inclusive and exclusive of the
+//! subroutines they call. Taken backward jumps and branches mark loops.
+//!
+//! The report lists the hotspots, the loops and the subroutines, followed by
+//! the annotated disassembly of everything that ran. The call stacks can also
+//! be written in the collapsed format of flamegraph tools:
+//!
+//! ```text
+//! top;MAIN;PRINT 1234
+//! ```
+//!
+//! Stacks are tracked by `JSR`/`RTS` alone, code that manipulates return
+//! addresses on the stack confuses them.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::debugger::symbols::Symbols;
+use crate::disasm;
+use crate::hardware::cpu::{Register, StepOutcome, CPU};
+use crate::hardware::error::EmuError;
+use crate::trace::Trigger;
+use crate::types::Addr;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+const BRK: u8 = 0x00;
+
+/// Entries in the hotspot, loop and subroutine tables
+const TOP: usize = 20;
+
+/// Name of the outermost stack frame in collapsed stacks
+const TOP_FRAME: &str = "top";
+
+#[derive(Default)]
+struct Subroutine {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+/// A subroutine being executed
+struct Frame {
+    target: Addr,
+    /// Cycle count after the `JSR`
+    entered: u64,
+    /// Inclusive cycles of the subroutines called from this one
+    children: u64,
+    stack: usize,
+}
+
+pub struct Profiler {
+    counts: Vec<u64>,
+    cycles: Vec<u64>,
+    instructions: u64,
+    total: u64,
+    /// Taken backward jumps and branches, by the address of the jump and its target
+    loops: HashMap<(Addr, Addr), u64>,
+    subroutines: HashMap<Addr, Subroutine>,
+    frames: Vec<Frame>,
+    /// Call stacks, each the parent stack and the subroutine called, the
+    /// outermost stack is 0
+    stacks: Vec<(usize, Addr)>,
+    stack_ids: HashMap<(usize, Addr), usize>,
+    stack_cycles: Vec<u64>,
+    stop: Option<Trigger>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; 0x10000],
+            cycles: vec![0; 0x10000],
+            instructions: 0,
+            total: 0,
+            loops: HashMap::new(),
+            subroutines: HashMap::new(),
+            frames: vec![],
+            stacks: vec![(0, Addr(0))],
+            stack_ids: HashMap::new(),
+            stack_cycles: vec![0],
+            stop: None,
+        }
+    }
+
+    /// Stop profiling once `trigger` is hit
+    pub fn stop(mut self, trigger: Trigger) -> Self {
+        self.stop = Some(trigger);
+        self
+    }
+
+    /// Execute the next instruction with `CPU::exec` and account for it
+    ///
+    /// Returns `StepOutcome::Break` once the stop trigger is hit.
+    pub fn exec(&mut self, cpu: &mut CPU) -> Result<StepOutcome, EmuError> {
+        let pc = cpu.get_reg(Register::PC).unwrap_right();
+        let start = cpu.clock().ticks();
+        if self.stop.is_some_and(|t| t.hit(pc, start)) {
+            return Ok(StepOutcome::Break);
+        }
+
+        let op_code = cpu.peek(pc).0;
+        let step = cpu.exec();
+        let end = cpu.clock().ticks();
+        if end > start {
+            let next = cpu.get_reg(Register::PC).unwrap_right();
+            self.record(pc, op_code, next, start, end);
+        }
+        step
+    }
+
+    /// Account for the instruction `op_code` at `pc` that ran from cycle
+    /// `start` to `end` and continued at `next`
+    fn record(&mut self, pc: Addr, op_code: u8, next: Addr, start: u64, end: u64) {
+        let cycles = end - start;
+        self.counts[pc.0 as usize] += 1;
+        self.cycles[pc.0 as usize] += cycles;
+        self.instructions += 1;
+        self.total += cycles;
+        // The JSR is the caller's, the RTS the callee's
+        let stack = self.frames.last().map_or(0, |f| f.stack);
+        self.stack_cycles[stack] += cycles;
+
+        match op_code {
+            JSR => {
+                let stack = self.stack_id(stack, next);
+                self.subroutines.entry(next).or_default().calls += 1;
+                self.frames.push(Frame {
+                    target: next,
+                    entered: end,
+                    children: 0,
+                    stack,
+                });
+            }
+            RTS => {
+                if let Some(frame) = self.frames.pop() {
+                    let inclusive = end - frame.entered;
+                    let sub = self.subroutines.entry(frame.target).or_default();
+                    sub.inclusive += inclusive;
+                    sub.exclusive += inclusive - frame.children;
+                    if let Some(parent) = self.frames.last_mut() {
+                        parent.children += inclusive;
+                    }
+                }
+            }
+            RTI | BRK => (),
+            _ if next.0 < pc.0 => *self.loops.entry((pc, next)).or_default() += 1,
+            _ => (),
+        }
+    }
+
+    fn stack_id(&mut self, parent: usize, target: Addr) -> usize {
+        if let Some(&id) = self.stack_ids.get(&(parent, target)) {
+            return id;
+        }
+        let id = self.stacks.len();
+        self.stacks.push((parent, target));
+        self.stack_cycles.push(0);
+        self.stack_ids.insert((parent, target), id);
+        id
+    }
+
+    /// Write the report, disassembling the code in `cpu`'s memory
+    pub fn report(
+        &self,
+        out: &mut impl Write,
+        cpu: &CPU,
+        symbols: &Symbols,
+    ) -> std::io::Result<()> {
+        let name = |addr: Addr| match symbols.at(addr) {
+            Some(name) => name.to_string(),
+            None => format!("${:04X}", addr.0),
+        };
+        let percent = |cycles: u64| 100.0 * cycles as f64 / self.total.max(1) as f64;
+
+        writeln!(
+            out,
+            "{} instructions in {} cycles\n",
+            self.instructions, self.total
+        )?;
+
+        writeln!(out, "Hotspots")?;
+        writeln!(
+            out,
+            "{:>12} {:>6} {:>10}  instruction",
+            "cycles", "%", "count"
+        )?;
+        let mut hotspots: Vec<usize> = (0..0x10000).filter(|&a| self.counts[a] > 0).collect();
+        hotspots.sort_by_key(|&a| std::cmp::Reverse(self.cycles[a]));
+        for &addr in hotspots.iter().take(TOP) {
+            let ins = disasm::disassemble(Addr(addr as u16), |a| cpu.peek(a));
+            writeln!(
+                out,
+                "{:>12} {:>6.2} {:>10}  {:04X}  {}",
+                self.cycles[addr],
+                percent(self.cycles[addr]),
+                self.counts[addr],
+                addr,
+                ins.format(|a| symbols.resolve(a))
+            )?;
+        }
+
+        writeln!(out, "\nLoops")?;
+        writeln!(
+            out,
+            "{:>12} {:>6} {:>10}  range",
+            "cycles", "%", "iterations"
+        )?;
+        let mut loops: Vec<(Addr, Addr, u64, u64)> = self
+            .loops
+            .iter()
+            .map(|(&(from, to), &n)| {
+                let cycles = (to.0..=from.0).map(|a| self.cycles[a as usize]).sum();
+                (from, to, n, cycles)
+            })
+            .collect();
+        loops.sort_by_key(|&(from, _, _, cycles)| (std::cmp::Reverse(cycles), from.0));
+        for (from, to, n, cycles) in loops.into_iter().take(TOP) {
+            writeln!(
+                out,
+                "{cycles:>12} {:>6.2} {n:>10}  {:04X}..{:04X}  {}",
+                percent(cycles),
+                to.0,
+                from.0,
+                name(to)
+            )?;
+        }
+
+        writeln!(out, "\nSubroutines")?;
+        writeln!(
+            out,
+            "{:>12} {:>6} {:>12} {:>6} {:>10}  subroutine",
+            "inclusive", "%", "exclusive", "%", "calls"
+        )?;
+        let mut subroutines: Vec<(&Addr, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(addr, sub)| (std::cmp::Reverse(sub.inclusive), addr.0));
+        for (&addr, sub) in subroutines.into_iter().take(TOP) {
+            writeln!(
+                out,
+                "{:>12} {:>6.2} {:>12} {:>6.2} {:>10}  {}",
+                sub.inclusive,
+                percent(sub.inclusive),
+                sub.exclusive,
+                percent(sub.exclusive),
+                sub.calls,
+                name(addr)
+            )?;
+        }
+
+        writeln!(out, "\nDisassembly")?;
+        writeln!(out, "{:>12} {:>10}", "cycles", "count")?;
+        let mut next = None;
+        let mut addr = 0;
+        while addr < 0x10000 {
+            if self.counts[addr] == 0 {
+                addr += 1;
+                continue;
+            }
+
+            let at = Addr(addr as u16);
+            if next.is_some() && next != Some(at) {
+                writeln!(out)?;
+            }
+            if let Some(label) = symbols.at(at) {
+                writeln!(out, "{label}:")?;
+            }
+            let ins = disasm::disassemble(at, |a| cpu.peek(a));
+            writeln!(
+                out,
+                "{:>12} {:>10}  {:04X}  {:<9} {}",
+                self.cycles[addr],
+                self.counts[addr],
+                addr,
+                ins.hex(),
+                ins.format(|a| symbols.resolve(a))
+            )?;
+            next = Some(ins.next());
+            addr += ins.size() as usize;
+        }
+        Ok(())
+    }
+
+    /// Write the cycles spent in each call stack, one `top;outer;inner cycles`
+    /// line per stack
+    pub fn collapsed(&self, out: &mut impl Write, symbols: &Symbols) -> std::io::Result<()> {
+        for (id, &cycles) in self.stack_cycles.iter().enumerate() {
+            if cycles == 0 {
+                continue;
+            }
+
+            let mut names = vec![];
+            let mut stack = id;
+            while stack != 0 {
+                let (parent, target) = self.stacks[stack];
+                names.push(match symbols.at(target) {
+                    Some(name) => name.to_string(),
+                    None => format!("${:04X}", target.0),
+                });
+                stack = parent;
+            }
+            names.push(TOP_FRAME.to_string());
+            names.reverse();
+            writeln!(out, "{} {cycles}", names.join(";"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm6502;
+    use crate::debugger::symbols::SymbolKind;
+
+    #[test]
+    fn test_profile() {
+        let (mut cpu, clk) = asm6502!(
+            "
+        ldx #3
+loop:   jsr sub
+        dex
+        bne loop
+        jmp *
+        .org $0410
+sub:    nop
+        rts"
+        )
+        .prepare();
+        std::thread::spawn(move || loop {
+            clk.tick();
+            clk.wait_tock();
+        });
+
+        let mut profiler = Profiler::new();
+        while profiler.exec(&mut cpu) == Ok(StepOutcome::Continue) {}
+
+        assert_eq!(profiler.counts[0x402], 3);
+        assert_eq!(profiler.counts[0x410], 3);
+        assert_eq!(profiler.counts[0x408], 1);
+        assert_eq!(profiler.loops[&(Addr(0x406), Addr(0x402))], 2);
+
+        let sub = &profiler.subroutines[&Addr(0x410)];
+        assert_eq!(sub.calls, 3);
+        let body = profiler.cycles[0x410] + profiler.cycles[0x411];
+        assert_eq!((sub.inclusive, sub.exclusive), (body, body));
+
+        let mut symbols = Symbols::new();
+        symbols.insert("DELAY", Addr(0x410), SymbolKind::Label);
+
+        let mut collapsed = vec![];
+        profiler.collapsed(&mut collapsed, &symbols).unwrap();
+        let collapsed = String::from_utf8(collapsed).unwrap();
+        let lines: Vec<&str> = collapsed.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("top "));
+        assert_eq!(lines[1], format!("top;DELAY {body}"));
+
+        let mut report = vec![];
+        profiler.report(&mut report, &cpu, &symbols).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("0402..0406"));
+        assert!(report.contains("DELAY:\n"));
+        assert!(report.contains("JSR DELAY"));
+    }
+}
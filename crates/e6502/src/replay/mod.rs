@@ -0,0 +1,143 @@
+//! Recording external input to replay a session exactly
+//!
+//! A recording is a text file that starts with the line `e6502 recording 1`,
+//! followed by a line per character typed, `key <cycle> <byte>`, with the
+//! cycle at which the keyboard handed it to the CPU. It ends with
+//! `end <cycle> <hash>`: the instruction boundary where the session stopped
+//! and the `SaveState::hash` of the machine there, which a replay has to
+//! reach too.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+
+const HEADER: &str = "e6502 recording";
+
+/// Version of the format written, the only one read
+pub const VERSION: u32 = 1;
+
+/// Where a recorded session stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct End {
+    pub cycle: u64,
+    pub hash: u64,
+}
+
+/// Writes a recording as the session goes, so little is lost if it is killed
+pub struct Recorder {
+    out: Box<dyn Write + Send>,
+}
+
+impl Recorder {
+    pub fn new(mut out: impl Write + Send + 'static) -> std::io::Result<Self> {
+        writeln!(out, "{HEADER} {VERSION}")?;
+        out.flush()?;
+        Ok(Self { out: Box::new(out) })
+    }
+
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::new(file).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// `key` reached the keyboard at `cycle`
+    pub fn key(&mut self, cycle: u64, key: u8) -> std::io::Result<()> {
+        writeln!(self.out, "key {cycle} {key}")?;
+        self.out.flush()
+    }
+
+    pub fn end(&mut self, end: End) -> std::io::Result<()> {
+        writeln!(self.out, "end {} {:016x}", end.cycle, end.hash)?;
+        self.out.flush()
+    }
+}
+
+/// A recorded session
+#[derive(Debug)]
+pub struct Recording {
+    /// The characters typed and the cycles at which they reached the keyboard
+    pub keys: VecDeque<(u64, u8)>,
+    pub end: End,
+}
+
+impl Recording {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) if line.starts_with(HEADER) => {
+                let version = line[HEADER.len()..].trim();
+                if version != VERSION.to_string() {
+                    return Err(format!(
+                        "Unsupported recording version {version}, expected {VERSION}"
+                    ));
+                }
+            }
+            _ => return Err("Not a recording".to_string()),
+        }
+
+        let mut keys = VecDeque::new();
+        let mut end = None;
+        for (n, line) in lines {
+            let invalid = || format!("Line {}: invalid {line:?}", n + 1);
+            if end.is_some() {
+                return Err(format!("Line {}: after the end", n + 1));
+            }
+
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["key", cycle, key] => {
+                    let cycle: u64 = cycle.parse().map_err(|_| invalid())?;
+                    if keys.back().is_some_and(|&(last, _)| cycle < last) {
+                        return Err(format!("Line {}: cycles out of order", n + 1));
+                    }
+                    keys.push_back((cycle, key.parse().map_err(|_| invalid())?));
+                }
+                ["end", cycle, hash] => {
+                    end = Some(End {
+                        cycle: cycle.parse().map_err(|_| invalid())?,
+                        hash: u64::from_str_radix(hash, 16).map_err(|_| invalid())?,
+                    });
+                }
+                [] => (),
+                _ => return Err(invalid()),
+            }
+        }
+
+        let Some(end) = end else {
+            return Err("The recording has no end, was the session killed?".to_string());
+        };
+        Ok(Self { keys, end })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let recording = Recording::parse(
+            "e6502 recording 1\nkey 10 104\nkey 10 105\n\nend 42 00000000deadbeef\n",
+        )
+        .unwrap();
+        assert_eq!(recording.keys, VecDeque::from([(10, b'h'), (10, b'i')]));
+        assert_eq!(
+            recording.end,
+            End {
+                cycle: 42,
+                hash: 0xdeadbeef
+            }
+        );
+
+        assert!(Recording::parse("e6502 recording 2\nend 42 0\n").is_err());
+        assert!(Recording::parse("e6502 recording 1\nkey 10 104\n").is_err());
+        assert!(Recording::parse("e6502 recording 1\nkey 10 104\nkey 9 105\nend 42 0\n").is_err());
+        assert!(Recording::parse("e6502 recording 1\nend 42 0\nkey 50 104\n").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! Save states: the whole machine in a file
+//!
+//! A save state holds the registers and internal flags of the CPU, the clock
+//! cycle count and the state of every device on the bus. All numbers are
+//! little endian:
+//!
+//! ```text
+//! "E6502SAV"  magic
+//! u16         format version
+//! u64         clock cycles
+//! u16 u8 u8 u8 u8 u8 u16 u8
+//!             PC, SP, A, X, Y, PS, previous PC and the flags advance (bit 0),
+//!             trap (1), IRQ pending (2) and NMI pending (3)
+//! u8          last byte on the data bus, which unmapped reads see
+//! u16         number of devices, then for each in registration order:
+//!   u16 u16   first and last address
+//!   u8        1 if the device has state, 0 if not
+//!   u32 [u8]  length and the state, only if it has any
+//! ```
+//!
+//! A state can only be loaded into a machine with the same devices at the
+//! same addresses.
+
+use std::path::Path;
+
+use crate::hardware::cpu::{CpuState, CPU};
+use crate::types::{Addr, Byte};
+
+const MAGIC: &[u8; 8] = b"E6502SAV";
+
+/// Version of the format written, the only one read
+pub const VERSION: u16 = 2;
+
+const ADVANCE: u8 = 0x01;
+const TRAP: u8 = 0x02;
+const IRQ_PENDING: u8 = 0x04;
+const NMI_PENDING: u8 = 0x08;
+
+/// A device on the bus: its address range and state
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceState {
+    range: (Addr, Addr),
+    data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SaveState {
+    pub cycle: u64,
+    pub cpu: CpuState,
+    /// The last byte on the data bus
+    pub latch: Byte,
+    devices: Vec<DeviceState>,
+}
+
+impl SaveState {
+    /// Capture `cpu`, its clock and everything on its bus
+    pub fn capture(cpu: &CPU) -> Self {
+        let bus = cpu.bus();
+        let devices = bus
+            .ranges()
+            .into_iter()
+            .zip(bus.snapshot())
+            .map(|(range, data)| DeviceState { range, data })
+            .collect();
+        Self {
+            cycle: cpu.clock().ticks(),
+            cpu: cpu.state(),
+            latch: bus.latch(),
+            devices,
+        }
+    }
+
+    /// Put `cpu`, its clock and everything on its bus back in the saved state
+    ///
+    /// Fails, leaving `cpu` alone, if the devices don't match the ones saved.
+    pub fn apply(&self, cpu: &mut CPU) -> Result<(), String> {
+        let ranges = cpu.bus().ranges();
+        if ranges.len() != self.devices.len()
+            || ranges.iter().zip(&self.devices).any(|(r, d)| *r != d.range)
+        {
+            return Err("Save state is for different devices".to_string());
+        }
+
+        let devices: Vec<_> = self.devices.iter().map(|d| d.data.clone()).collect();
+        cpu.restore(self.cpu);
+        cpu.bus().restore(&devices);
+        cpu.bus().set_latch(self.latch);
+        cpu.clock().set_ticks(self.cycle);
+        cpu.take_writes();
+        Ok(())
+    }
+
+    /// 64-bit FNV-1a hash of the state as saved, to tell cheaply whether two
+    /// machines are in the same state
+    pub fn hash(&self) -> u64 {
+        self.to_bytes()
+            .iter()
+            .fold(0xcbf29ce484222325, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let cpu = &self.cpu;
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        out.extend(self.cycle.to_le_bytes());
+        out.extend(cpu.pc.0.to_le_bytes());
+        out.extend([cpu.sp.0, cpu.a.0, cpu.x.0, cpu.y.0, cpu.ps.0]);
+        out.extend(cpu.previous_pc.0.to_le_bytes());
+        let mut flags = 0;
+        for (set, flag) in [
+            (cpu.advance, ADVANCE),
+            (cpu.trap, TRAP),
+            (cpu.irq_pending, IRQ_PENDING),
+            (cpu.nmi_pending, NMI_PENDING),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+        out.push(flags);
+        out.push(self.latch.0);
+
+        out.extend((self.devices.len() as u16).to_le_bytes());
+        for dev in &self.devices {
+            out.extend(dev.range.0 .0.to_le_bytes());
+            out.extend(dev.range.1 .0.to_le_bytes());
+            match dev.data {
+                Some(ref data) => {
+                    out.push(1);
+                    out.extend((data.len() as u32).to_le_bytes());
+                    out.extend(data);
+                }
+                None => out.push(0),
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let mut input = Reader(data);
+        if input.bytes(MAGIC.len())? != MAGIC {
+            return Err("Not a save state".to_string());
+        }
+        let version = input.u16()?;
+        if version != VERSION {
+            return Err(format!(
+                "Unsupported save state version {version}, expected {VERSION}"
+            ));
+        }
+
+        let cycle = input.u64()?;
+        let pc = Addr(input.u16()?);
+        let [sp, a, x, y, ps] = [(); 5].map(|_| input.u8().map(Byte));
+        let previous_pc = Addr(input.u16()?);
+        let flags = input.u8()?;
+        let latch = Byte(input.u8()?);
+        let cpu = CpuState {
+            pc,
+            sp: sp?,
+            a: a?,
+            x: x?,
+            y: y?,
+            ps: ps?,
+            previous_pc,
+            advance: flags & ADVANCE != 0,
+            trap: flags & TRAP != 0,
+            irq_pending: flags & IRQ_PENDING != 0,
+            nmi_pending: flags & NMI_PENDING != 0,
+        };
+
+        let count = input.u16()?;
+        let mut devices = vec![];
+        for _ in 0..count {
+            let range = (Addr(input.u16()?), Addr(input.u16()?));
+            let data = match input.u8()? {
+                0 => None,
+                1 => {
+                    let len = input.u32()? as usize;
+                    Some(input.bytes(len)?.to_vec())
+                }
+                tag => return Err(format!("Invalid device state tag {tag}")),
+            };
+            devices.push(DeviceState { range, data });
+        }
+
+        if !input.0.is_empty() {
+            return Err("Trailing data after save state".to_string());
+        }
+        Ok(Self {
+            cycle,
+            cpu,
+            latch,
+            devices,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes()).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Self::from_bytes(&data).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+/// Takes little endian numbers off the front of a slice
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err("Save state is truncated".to_string());
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm6502;
+    use crate::hardware::cpu::Register;
+
+    #[test]
+    fn test_save_state() {
+        let (mut cpu, clk) = asm6502!("loop: inx\n stx $10\n jmp loop").prepare();
+        std::thread::spawn(move || loop {
+            clk.tick();
+            clk.wait_tock();
+        });
+
+        for _ in 0..10 {
+            cpu.exec().unwrap();
+        }
+        let state = SaveState::from_bytes(&SaveState::capture(&cpu).to_bytes()).unwrap();
+        let now = |cpu: &CPU| {
+            (
+                cpu.clock().ticks(),
+                cpu.get_reg(Register::PC).unwrap_right(),
+                cpu.get_reg(Register::X).unwrap_left(),
+                cpu.peek(Addr(0x10)),
+                cpu.bus().latch(),
+            )
+        };
+        let saved = now(&cpu);
+
+        for _ in 0..10 {
+            cpu.exec().unwrap();
+        }
+        assert_ne!(now(&cpu), saved);
+        assert!(state.apply(&mut cpu).is_ok());
+        assert_eq!(now(&cpu), saved);
+
+        let mut data = state.to_bytes();
+        assert!(SaveState::from_bytes(&data[..data.len() - 1]).is_err());
+        data[8] = 1;
+        assert_eq!(
+            SaveState::from_bytes(&data).unwrap_err(),
+            "Unsupported save state version 1, expected 2"
+        );
+
+        let mut other = state.clone();
+        other.devices[0].range.1 = Addr(0x3FFF);
+        assert!(other.apply(&mut cpu).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! Instruction-level unit tests written in assembly
+//!
+//! `asm6502!` assembles a few statements, separated by `;`, to a `Test`.
+//! The test sets up the CPU, runs until a `BRK`, a trap or a number of
+//! instructions, and checks the outcome:
+//!
+//! ```
+//! # use e6502::asm6502;
+//! # use e6502::hardware::cpu::Flag;
+//! asm6502! { lda #$34; adc #$35 }
+//!     .run()
+//!     .a(0x69)
+//!     .flag(Flag::Carry, false)
+//!     .cycles(4);
+//! ```
+//!
+//! Code goes to $0400 unless it says otherwise with `.org`, and runs from its
+//! lowest address. Numbers Rust can't take as tokens, like `$1E`, need the
+//! source as a string instead: `asm6502!("lda #$1E")`.
+
+use std::sync::Arc;
+
+use crate::asm::assemble;
+use crate::hardware::clock::Clock;
+use crate::hardware::cpu::instructions::Instruction;
+use crate::hardware::cpu::system::System;
+use crate::hardware::cpu::{decode, Flag, Register, StepOutcome, CPU};
+use crate::types::Addr;
+
+/// Instructions a test may run before it's taken to be stuck
+pub const MAX_INSTRUCTIONS: u64 = 1_000_000;
+
+/// Assemble the statements to a `Test`, see the module documentation
+#[macro_export]
+macro_rules! asm6502 {
+    ($source:literal) => {
+        $crate::testing::Test::new($source)
+    };
+    ($($statement:tt)*) => {
+        $crate::testing::Test::inline(stringify!($($statement)*))
+    };
+}
+
+pub struct Test {
+    system: System,
+    steps: Option<u64>,
+}
+
+impl Test {
+    /// Assemble `source`, panicking on errors
+    pub fn new(source: &str) -> Self {
+        let program = match assemble(&format!(".org $0400\n{source}")) {
+            Ok(program) => program,
+            Err(e) => panic!("{e}\n{source}"),
+        };
+        let mut system = System::new().pc(program.image().0 .0);
+        for (addr, byte) in program.bytes() {
+            system.set_memory(addr.0, byte.0);
+        }
+        Self {
+            system,
+            steps: None,
+        }
+    }
+
+    /// Like `new`, with the statements on one line separated by `;`
+    pub fn inline(statements: &str) -> Self {
+        let source = statements.split_whitespace().collect::<Vec<_>>().join(" ");
+        Self::new(&source.replace(';', "\n"))
+    }
+
+    pub fn a(mut self, a: u8) -> Self {
+        self.system = self.system.a(a);
+        self
+    }
+
+    pub fn x(mut self, x: u8) -> Self {
+        self.system = self.system.x(x);
+        self
+    }
+
+    pub fn y(mut self, y: u8) -> Self {
+        self.system = self.system.y(y);
+        self
+    }
+
+    pub fn ps(mut self, ps: u8) -> Self {
+        self.system = self.system.ps(ps);
+        self
+    }
+
+    pub fn sp(mut self, sp: u8) -> Self {
+        self.system = self.system.sp(sp);
+        self
+    }
+
+    /// Start at `pc` instead of the lowest address of the code
+    pub fn pc(mut self, pc: u16) -> Self {
+        self.system = self.system.pc(pc);
+        self
+    }
+
+    pub fn memory(mut self, addr: u16, val: u8) -> Self {
+        self.system = self.system.memory(addr, val);
+        self
+    }
+
+    /// Stop after `steps` instructions, unless a `BRK` or a trap comes first
+    pub fn steps(mut self, steps: u64) -> Self {
+        self.steps = Some(steps);
+        self
+    }
+
+    /// The CPU and its clock, ready to run the code, for tests that drive it
+    /// themselves
+    pub fn prepare(self) -> (CPU, Arc<Clock>) {
+        self.system.prepare()
+    }
+
+    /// Run until a `BRK`, which isn't run, a trap or the number of steps
+    pub fn run(self) -> Run {
+        let (mut cpu, _) = self.system.prepare();
+        cpu.debug();
+        let first = cpu.clock().ticks();
+        let limit = self.steps.unwrap_or(MAX_INSTRUCTIONS);
+
+        let mut instructions = 0;
+        while instructions < limit {
+            let pc = cpu.get_reg(Register::PC).unwrap_right();
+            if decode(cpu.peek(pc)).0 == Instruction::BRK {
+                break;
+            }
+            instructions += 1;
+            match cpu.exec() {
+                Ok(StepOutcome::Continue) => (),
+                Ok(_) => break,
+                Err(e) => panic!("{e}\n{cpu}"),
+            }
+        }
+        if self.steps.is_none() && instructions == limit {
+            panic!("Still running after {limit} instructions\n{cpu}");
+        }
+
+        let cycles = cpu.clock().ticks() - first;
+        Run {
+            cpu,
+            cycles,
+            instructions,
+        }
+    }
+}
+
+/// The CPU after a `Test`, with assertions on its state that panic with
+/// both values when they fail
+pub struct Run {
+    cpu: CPU,
+    cycles: u64,
+    instructions: u64,
+}
+
+impl Run {
+    pub fn cpu(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    #[track_caller]
+    fn reg(self, reg: Register, expected: u8) -> Self {
+        let actual = self.cpu.get_reg(reg).unwrap_left().0;
+        assert_eq!(
+            actual, expected,
+            "{reg:?} is {actual:#04X}, not {expected:#04X}"
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn a(self, a: u8) -> Self {
+        self.reg(Register::A, a)
+    }
+
+    #[track_caller]
+    pub fn x(self, x: u8) -> Self {
+        self.reg(Register::X, x)
+    }
+
+    #[track_caller]
+    pub fn y(self, y: u8) -> Self {
+        self.reg(Register::Y, y)
+    }
+
+    #[track_caller]
+    pub fn ps(self, ps: u8) -> Self {
+        self.reg(Register::PS, ps)
+    }
+
+    #[track_caller]
+    pub fn sp(self, sp: u8) -> Self {
+        self.reg(Register::SP, sp)
+    }
+
+    #[track_caller]
+    pub fn pc(self, pc: u16) -> Self {
+        let actual = self.cpu.get_reg(Register::PC).unwrap_right();
+        assert_eq!(actual, Addr(pc), "PC is {actual}, not {}", Addr(pc));
+        self
+    }
+
+    #[track_caller]
+    pub fn flag(self, flag: Flag, set: bool) -> Self {
+        assert_eq!(
+            self.cpu.is_set(flag),
+            set,
+            "{flag:?} is {}",
+            if set { "clear" } else { "set" }
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn memory(self, addr: u16, val: u8) -> Self {
+        let actual = self.cpu.peek(addr).0;
+        assert_eq!(
+            actual,
+            val,
+            "{} is {actual:#04X}, not {val:#04X}",
+            Addr(addr)
+        );
+        self
+    }
+
+    /// The cycles taken by the instructions run
+    #[track_caller]
+    pub fn cycles(self, cycles: u64) -> Self {
+        assert_eq!(self.cycles, cycles, "Took {} cycles", self.cycles);
+        self
+    }
+
+    #[track_caller]
+    pub fn instructions(self, instructions: u64) -> Self {
+        assert_eq!(
+            self.instructions, instructions,
+            "Ran {} instructions",
+            self.instructions
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_asm6502() {
+        asm6502! { lda #$34; adc #$35; brk }
+            .run()
+            .a(0x69)
+            .flag(Flag::Carry, false)
+            .flag(Flag::Zero, false)
+            .pc(0x0404)
+            .cycles(4)
+            .instructions(2);
+
+        asm6502! { ldx #3; loop: dex; sta $10,x; bne loop; jmp * }
+            .a(0xAA)
+            .run()
+            .x(0)
+            .memory(0x10, 0xAA)
+            .memory(0x12, 0xAA)
+            .memory(0x13, 0x00)
+            .pc(0x0407);
+
+        asm6502! { .org $8000; lda ($20),y; inx }
+            .memory(0x20, 0x00)
+            .memory(0x21, 0x30)
+            .memory(0x3005, 0x80)
+            .y(5)
+            .steps(1)
+            .run()
+            .a(0x80)
+            .x(0)
+            .flag(Flag::Negative, true)
+            .pc(0x8002)
+            .cycles(5);
+
+        asm6502!("lda #$1E\nsec\nsbc #$1F")
+            .run()
+            .a(0xFF)
+            .flag(Flag::Carry, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "A is 0x01, not 0x02")]
+    fn test_asm6502_fails() {
+        asm6502! { lda #1 }.run().a(2);
+    }
+}
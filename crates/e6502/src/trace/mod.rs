@@ -0,0 +1,242 @@
+//! Instruction trace log in the format of the well known `nestest.log`
+//!
+//! Every executed instruction is one line, e.g.
+//!
+//! ```text
+//! C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+//! ```
+//!
+//! With effective addresses enabled the operand is followed by the address
+//! that was accessed and the value found there, `LDA ($80),Y = 0200 @ 0204 = 5A`.
+
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+
+use crate::disasm::{self, Disassembly};
+use crate::hardware::cpu::instructions::{AddressingMode, Instruction};
+use crate::hardware::cpu::{Register, CPU};
+use crate::types::{Addr, Byte};
+
+/// Where tracing starts or stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// When the instruction at this address is about to execute
+    Addr(Addr),
+    /// When this many cycles have passed
+    Cycle(u64),
+}
+
+impl Trigger {
+    /// Whether the trigger fires before executing the instruction at `pc`
+    /// after `cycle` cycles
+    pub fn hit(self, pc: Addr, cycle: u64) -> bool {
+        match self {
+            Trigger::Addr(addr) => pc == addr,
+            Trigger::Cycle(n) => cycle >= n,
+        }
+    }
+}
+
+impl TryFrom<&str> for Trigger {
+    type Error = String;
+    /// Either an address, `0xC000`, or a cycle count, `cycle:1234`
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.strip_prefix("cycle:") {
+            Some(cycle) => Ok(Trigger::Cycle(
+                cycle
+                    .parse()
+                    .map_err(|e| format!("Invalid cycle {cycle:?}: {e}"))?,
+            )),
+            None => Ok(Trigger::Addr(Addr::try_from(value)?)),
+        }
+    }
+}
+
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    start: Option<Trigger>,
+    stop: Option<Trigger>,
+    effective: bool,
+    active: bool,
+    stopped: bool,
+}
+
+impl Tracer {
+    /// Trace to `out`, from the first instruction on
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Box::new(out),
+            start: None,
+            stop: None,
+            effective: false,
+            active: true,
+            stopped: false,
+        }
+    }
+
+    /// Trace to the file at `path`, replacing it
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self::new(LineWriter::new(file)))
+    }
+
+    /// Only start tracing once `trigger` is hit
+    pub fn start(mut self, trigger: Trigger) -> Self {
+        self.start = Some(trigger);
+        self.active = false;
+        self
+    }
+
+    /// Stop tracing for good once `trigger` is hit
+    pub fn stop(mut self, trigger: Trigger) -> Self {
+        self.stop = Some(trigger);
+        self
+    }
+
+    /// Show the effective address and value of memory operands
+    pub fn effective(mut self, effective: bool) -> Self {
+        self.effective = effective;
+        self
+    }
+
+    /// The line for the instruction `cpu` is about to execute, if it is to be traced
+    pub fn next_line(&mut self, cpu: &CPU) -> Option<String> {
+        if self.stopped {
+            return None;
+        }
+
+        let pc = cpu.get_reg(Register::PC).unwrap_right();
+        let cycle = cpu.clock().ticks();
+        if self.stop.is_some_and(|t| t.hit(pc, cycle)) {
+            self.active = false;
+            self.stopped = true;
+            let _ = self.out.flush();
+            return None;
+        }
+        if !self.active && self.start.is_some_and(|t| t.hit(pc, cycle)) {
+            self.active = true;
+        }
+
+        self.active.then(|| line(cpu, self.effective))
+    }
+
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        writeln!(self.out, "{line}")
+    }
+
+    /// Trace the instruction `cpu` is about to execute
+    pub fn trace(&mut self, cpu: &CPU) -> std::io::Result<()> {
+        match self.next_line(cpu) {
+            Some(line) => self.write_line(&line),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Format the instruction `cpu` is about to execute, and the registers before it
+pub fn line(cpu: &CPU, effective: bool) -> String {
+    let reg = |reg| cpu.get_reg(reg).unwrap_left().0;
+    let pc = cpu.get_reg(Register::PC).unwrap_right();
+    let ins = disasm::disassemble(pc, |addr| cpu.peek(addr));
+
+    let mut text = ins.to_string();
+    if effective {
+        text += &effective_address(&ins, cpu);
+    }
+
+    format!(
+        "{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        pc.0,
+        ins.hex(),
+        text,
+        reg(Register::A),
+        reg(Register::X),
+        reg(Register::Y),
+        reg(Register::PS),
+        reg(Register::SP),
+        cpu.clock().ticks()
+    )
+}
+
+/// The address a memory operand resolves to and the value there, computed the
+/// way the CPU does it
+fn effective_address(ins: &Disassembly, cpu: &CPU) -> String {
+    let Some(operand) = ins.operand() else {
+        return String::new();
+    };
+    let x = cpu.get_reg(Register::X).unwrap_left();
+    let y = cpu.get_reg(Register::Y).unwrap_left();
+    let peek = |addr: Addr| cpu.peek(addr).0;
+    let word = |addr: Addr| Addr::new(cpu.peek(addr + 1), cpu.peek(addr));
+
+    match ins.mode {
+        AddressingMode::ZeroPage => format!(" = {:02X}", peek(operand)),
+        AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+            let index = if ins.mode == AddressingMode::ZeroPageX {
+                x
+            } else {
+                y
+            };
+            let addr = Addr::from(Byte(operand.0 as u8) + index);
+            format!(" @ {:02X} = {:02X}", addr.0, peek(addr))
+        }
+        AddressingMode::Absolute => match ins.instruction {
+            Some(Instruction::JMP | Instruction::JSR) => String::new(),
+            _ => format!(" = {:02X}", peek(operand)),
+        },
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+            let index = if ins.mode == AddressingMode::AbsoluteX {
+                x
+            } else {
+                y
+            };
+            let addr = operand + index;
+            format!(" @ {:04X} = {:02X}", addr.0, peek(addr))
+        }
+        AddressingMode::Indirect => format!(" = {:04X}", word(operand).0),
+        AddressingMode::IndirectX => {
+            let pointer = Addr::from(Byte(operand.0 as u8) + x);
+            let addr = word(pointer);
+            format!(" @ {:02X} = {:04X} = {:02X}", pointer.0, addr.0, peek(addr))
+        }
+        AddressingMode::IndirectY => {
+            let base = word(operand);
+            let addr = base + y;
+            format!(" = {:04X} @ {:04X} = {:02X}", base.0, addr.0, peek(addr))
+        }
+        AddressingMode::Immediate | AddressingMode::Relative | AddressingMode::Implied => {
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm6502;
+
+    #[test]
+    fn test_line() {
+        let (cpu, _) = asm6502!("lda ($80),y")
+            .y(0x04)
+            .memory(0x80, 0x00)
+            .memory(0x81, 0x02)
+            .memory(0x204, 0x5A)
+            .prepare();
+
+        let line = line(&cpu, true);
+        assert_eq!(
+            &line[..line.rfind(" CYC:").unwrap()],
+            "0400  B1 80     LDA ($80),Y = 0200 @ 0204 = 5A  A:00 X:00 Y:04 P:24 SP:FD"
+        );
+    }
+
+    #[test]
+    fn test_triggers() {
+        assert_eq!(Trigger::try_from("0xC000"), Ok(Trigger::Addr(Addr(0xC000))));
+        assert_eq!(Trigger::try_from("cycle:100"), Ok(Trigger::Cycle(100)));
+        assert!(Trigger::try_from("cycle:x").is_err());
+    }
+}
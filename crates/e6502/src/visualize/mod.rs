@@ -1,5 +1,6 @@
 use e6502::hardware::cpu::{
-    instructions::{get_instruction, AddressingMode, Instruction},
+    decode,
+    instructions::{AddressingMode, Instruction},
     Flag, Register, CPU, STACK_START,
 };
 use e6502::types::Addr;
@@ -115,7 +116,8 @@ pub fn run(mut cpu: CPU, step: bool) -> Result<(), String> {
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } | Event::KeyDown {
+                }
+                | Event::KeyDown {
                     keycode: Some(Keycode::Q),
                     ..
                 } => break 'running,
@@ -126,7 +128,9 @@ pub fn run(mut cpu: CPU, step: bool) -> Result<(), String> {
                     if !pause {
                         continue;
                     }
-                    cpu.exec();
+                    if let Err(e) = cpu.exec() {
+                        eprintln!("{e}");
+                    }
                     refresh = true;
                 }
                 Event::KeyDown {
@@ -277,7 +281,7 @@ pub fn update(
     current_line += 2;
 
     let (instruction, addressing_mode): (Instruction, AddressingMode) =
-        get_instruction(cpu.read(cpu.get_pc()));
+        decode(cpu.peek(cpu.get_pc()));
     let arg = addressing_mode.get(cpu);
     let txt = format!("PC: {:#06X} -> {} {}", cpu.get_pc().0, instruction, arg);
     blit_text(&txt, font, &mut surface, REGISTER_RIGHT, current_line * L)?;
@@ -333,7 +337,7 @@ pub fn update(
 
     for byte in 0..16 {
         let data: Vec<String> = (0..16)
-            .map(|b| format!("{:02x}", cpu.read(STACK_START + b + 16 * byte).0))
+            .map(|b| format!("{:02x}", cpu.peek(STACK_START + b + 16 * byte).0))
             .collect();
         blit_text(
             &format!(
@@ -363,7 +367,7 @@ pub fn update(
 
     for byte in 0..16 {
         let data: Vec<String> = (0..16)
-            .map(|b| format!("{:02x}", cpu.read(Addr::from(b + 16 * byte)).0))
+            .map(|b| format!("{:02x}", cpu.peek(Addr::from(b + 16 * byte)).0))
             .collect();
 
         blit_text(
@@ -382,7 +386,7 @@ pub fn update(
             .map(|b| {
                 format!(
                     "{:02x}",
-                    cpu.read(Addr::from(view_memory_start + b + 16 * byte)).0
+                    cpu.peek(Addr::from(view_memory_start + b + 16 * byte)).0
                 )
             })
             .collect();